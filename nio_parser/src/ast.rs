@@ -1,32 +1,72 @@
+use crate::span::Span;
+use std::fmt;
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Program {
     pub statements: Vec<Stmt>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
-pub enum Stmt {
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum StmtKind {
     Def {
+        /// The `///` comment preceding the definition.
+        doc: Option<String>,
         annotations: Vec<Expr>,
         name: String,
-        params: Vec<(String, String)>,
-        return_type: String,
-        body: Box<Expr>,
+        params: Vec<(String, TypeExpr)>,
+        return_type: TypeExpr,
+        /// Missing for imported functions.
+        body: Option<Box<Expr>>,
     },
+    /// `let name = value`, or `let mut name = value` for a variable that can be assigned to.
     Let {
         name: String,
-        type_: Option<String>,
+        mutable: bool,
+        type_: Option<TypeExpr>,
         value: Box<Expr>,
     },
+    /// `let (a, b) = value`, which binds each element of a tuple to a name.
+    LetTuple {
+        names: Vec<String>,
+        type_: Option<TypeExpr>,
+        value: Box<Expr>,
+    },
+    /// `type Name = ...`, only at the top level.
+    Type {
+        /// The `///` comment preceding the declaration.
+        doc: Option<String>,
+        name: String,
+        def: TypeDef,
+    },
     Expr(Expr),
+    /// A statement that failed to parse.
+    Error,
 }
 
 #[derive(Debug, PartialEq, Clone)]
-pub enum Expr {
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ExprKind {
     BinOp {
         op: BinOp,
         lhs: Box<Expr>,
         rhs: Box<Expr>,
     },
+    UnOp {
+        op: UnOp,
+        operand: Box<Expr>,
+    },
+    /// `lhs = rhs`. The parser turns `lhs += rhs` into `lhs = lhs + rhs`.
     Assign {
         lhs: String,
         rhs: Box<Expr>,
@@ -39,9 +79,143 @@ pub enum Expr {
         callee: Box<Expr>,
         args: Vec<Expr>,
     },
+    /// `{ x: 1, y: 2 }`, or `{ x: 1, ..base }` to copy the other fields from `base`.
+    Record {
+        fields: Vec<(String, Expr)>,
+        base: Option<Box<Expr>>,
+    },
+    /// `expr.name`
+    Field {
+        expr: Box<Expr>,
+        name: String,
+    },
+    /// `(a, b)`, or `(a,)` for a tuple with a single element.
+    Tuple(Vec<Expr>),
+    Array(Vec<Expr>),
+    Index {
+        expr: Box<Expr>,
+        index: Box<Expr>,
+    },
+    If {
+        cond: Box<Expr>,
+        then: Box<Expr>,
+        else_: Box<Expr>,
+    },
+    /// `match scrutinee { pattern => body, ... }`, which evaluates the first arm whose pattern
+    /// matches.
+    Match {
+        scrutinee: Box<Expr>,
+        arms: Vec<MatchArm>,
+    },
+    While {
+        cond: Box<Expr>,
+        body: Box<Expr>,
+    },
+    /// `for var in start..end`, where `end` is excluded.
+    For {
+        var: String,
+        start: Box<Expr>,
+        end: Box<Expr>,
+        body: Box<Expr>,
+    },
+    ForEach {
+        var: String,
+        array: Box<Expr>,
+        body: Box<Expr>,
+    },
+    Loop {
+        body: Box<Expr>,
+    },
+    Break(Option<Box<Expr>>),
+    Continue,
+    /// `{ stmts; value }`, where the value is missing if the block ends with a statement.
+    Block {
+        stmts: Vec<Stmt>,
+        value: Option<Box<Expr>>,
+    },
     Ident(String),
-    IntLit(String),
+    IntLit(IntLit),
+    FloatLit(f64),
+    BoolLit(bool),
     StringLit(String),
+    /// `()`, the only value of type `Unit`.
+    UnitLit,
+    /// An expression that failed to parse.
+    Error,
+}
+
+/// The right-hand side of a `type` declaration.
+#[derive(Debug, PartialEq, Clone)]
+pub enum TypeDef {
+    /// `{ x: Int, y: Int }`
+    Record(Vec<(String, TypeExpr)>),
+    /// `Circle(Float) | Rect(Float, Float) | Empty`, a sum type whose variants have the given
+    /// field types.
+    Enum(Vec<(String, Vec<TypeExpr>)>),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: Expr,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Pattern {
+    pub kind: PatternKind,
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum PatternKind {
+    /// `_`, which matches any value.
+    Wildcard,
+    /// A name, which binds the value to a variable, unless it is a variant without fields like
+    /// `Empty`.
+    Ident(String),
+    /// An integer, possibly negated, `true`, `false` or `()`.
+    Literal(Box<Expr>),
+    /// `Rect(w, h)`, which matches a variant and its fields.
+    Variant { name: String, args: Vec<Pattern> },
+}
+
+/// A type written in the source, like `Int` or `(Int) -> Bool`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TypeExpr {
+    pub kind: TypeExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum TypeExprKind {
+    Name(String),
+    /// `(A, B) -> C`.
+    Func {
+        params: Vec<TypeExpr>,
+        return_type: Box<TypeExpr>,
+    },
+    /// `(A, B)`, or `()` for `Unit`. `(A)` is just `A`, while `(A,)` is a tuple.
+    Tuple(Vec<TypeExpr>),
+    /// A generic type applied to type arguments, like `List[Int]`.
+    App {
+        name: String,
+        args: Vec<TypeExpr>,
+    },
+}
+
+/// The value of an integer literal, before checking that it fits in its type.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct IntLit {
+    pub value: u128,
+    /// 2, 8, 10 or 16.
+    pub radix: u32,
+    pub suffix: Option<IntSuffix>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum IntSuffix {
+    I32,
+    I64,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -49,4 +223,88 @@ pub enum BinOp {
     Add,
     Sub,
     Mul,
+    Div,
+    Rem,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// `&&`, which only evaluates its right-hand side if the left-hand side is true.
+    And,
+    /// `||`, which only evaluates its right-hand side if the left-hand side is false.
+    Or,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum UnOp {
+    Neg,
+    Not,
+}
+
+impl Stmt {
+    pub fn new(kind: StmtKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+}
+
+impl Pattern {
+    pub fn new(kind: PatternKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+}
+
+impl TypeExpr {
+    pub fn new(kind: TypeExprKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+}
+
+impl fmt::Display for TypeExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            TypeExprKind::Name(name) => write!(f, "{}", name),
+            TypeExprKind::Func {
+                params,
+                return_type,
+            } => {
+                write_list(f, "(", params, ")")?;
+                write!(f, " -> {}", return_type)
+            }
+            TypeExprKind::Tuple(types) if types.len() == 1 => write!(f, "({},)", types[0]),
+            TypeExprKind::Tuple(types) => write_list(f, "(", types, ")"),
+            TypeExprKind::App { name, args } => {
+                write!(f, "{}", name)?;
+                write_list(f, "[", args, "]")
+            }
+        }
+    }
+}
+
+fn write_list(
+    f: &mut fmt::Formatter<'_>,
+    open: &str,
+    types: &[TypeExpr],
+    close: &str,
+) -> fmt::Result {
+    write!(f, "{}", open)?;
+    for (i, type_) in types.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", type_)?;
+    }
+    write!(f, "{}", close)
 }
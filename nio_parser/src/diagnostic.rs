@@ -0,0 +1,280 @@
+use crate::span::{Span, line_col};
+use std::fmt::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+    pub primary: bool,
+}
+
+/// A message about the source code, pointing at one or more places in it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Option<&'static str>,
+    pub message: String,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            code: None,
+            message: message.into(),
+            labels: Vec::new(),
+            notes: Vec::new(),
+            help: None,
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, message)
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, message)
+    }
+
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    /// Adds a label marking the main location of the problem with `^^^`.
+    pub fn with_primary(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+            primary: true,
+        });
+        self
+    }
+
+    /// Adds a label marking related code with `---`.
+    pub fn with_secondary(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+            primary: false,
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    /// The span of the first primary label, if any.
+    pub fn primary_span(&self) -> Option<Span> {
+        self.labels
+            .iter()
+            .find(|label| label.primary)
+            .map(|label| label.span)
+    }
+
+    /// Renders the diagnostic with the offending lines of `source`, in the style of rustc:
+    ///
+    /// ```text
+    /// error[E0001]: unexpected `)`
+    ///  --> main.nio:1:9
+    ///   |
+    /// 1 | let x = )
+    ///   |         ^ expected expression
+    /// ```
+    pub fn render(&self, source: &str, path: &str) -> String {
+        let mut out = String::new();
+        // Writing into a `String` cannot fail.
+        self.render_to(&mut out, source, path).unwrap();
+        out
+    }
+
+    fn render_to(&self, out: &mut String, source: &str, path: &str) -> fmt::Result {
+        writeln!(out, "{}", self)?;
+
+        let lines: Vec<&str> = source.split('\n').collect();
+        let mut labels: Vec<(usize, usize, &Label)> = self
+            .labels
+            .iter()
+            .map(|label| {
+                let (line, col) = line_col(source, label.span.start);
+                (line, col, label)
+            })
+            .collect();
+        labels.sort_by_key(|&(line, col, label)| (line, col, !label.primary));
+
+        let gutter = labels
+            .iter()
+            .map(|&(line, _, _)| line.to_string().len())
+            .max()
+            .unwrap_or(0);
+        let pad = " ".repeat(gutter);
+
+        let location = match self.primary_span().or(labels.first().map(|l| l.2.span)) {
+            Some(span) => {
+                let (line, col) = line_col(source, span.start);
+                format!("{}:{}:{}", path, line, col)
+            }
+            None => path.to_string(),
+        };
+        writeln!(out, "{}--> {}", pad, location)?;
+
+        if !labels.is_empty() {
+            writeln!(out, "{} |", pad)?;
+        }
+        let mut previous_line = None;
+        for (i, &(line, col, label)) in labels.iter().enumerate() {
+            let text = lines.get(line - 1).copied().unwrap_or("");
+            if previous_line != Some(line) {
+                if let Some(previous_line) = previous_line
+                    && line > previous_line + 1
+                {
+                    writeln!(out, "...")?;
+                }
+                writeln!(out, "{:>width$} | {}", line, text, width = gutter)?;
+                previous_line = Some(line);
+            }
+
+            // Keep tabs in the indentation so that the markers line up with the source.
+            let indent: String = text
+                .chars()
+                .take(col - 1)
+                .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+                .collect();
+            // Spans running past the end of the line are cut off there.
+            let line_start = label.span.start - byte_offset(text, col);
+            let end = label.span.end.min(line_start + text.len());
+            let width = source
+                .get(label.span.start..end)
+                .map_or(0, |s| s.chars().count())
+                .max(1);
+            let marker = if label.primary { "^" } else { "-" }.repeat(width);
+            let mut marker_line = format!("{} | {}{}", pad, indent, marker);
+            if !label.message.is_empty() {
+                write!(marker_line, " {}", label.message)?;
+            }
+            writeln!(out, "{}", marker_line.trim_end())?;
+
+            let is_last = i + 1 == labels.len();
+            if is_last && (!self.notes.is_empty() || self.help.is_some()) {
+                writeln!(out, "{} |", pad)?;
+            }
+        }
+
+        for note in self.notes.iter() {
+            writeln!(out, "{} = note: {}", pad, note)?;
+        }
+        if let Some(help) = &self.help {
+            writeln!(out, "{} = help: {}", pad, help)?;
+        }
+        Ok(())
+    }
+}
+
+/// Byte offset of the 1-based character column `col` within `text`.
+fn byte_offset(text: &str, col: usize) -> usize {
+    text.char_indices()
+        .nth(col - 1)
+        .map_or(text.len(), |(offset, _)| offset)
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.code {
+            Some(code) => write!(f, "{}[{}]: {}", self.severity, code, self.message),
+            None => write!(f, "{}: {}", self.severity, self.message),
+        }
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let source = "let x = 1\nlet y = x + \"a\"\n";
+        let diagnostic = Diagnostic::error("mismatched types")
+            .with_code("E0100")
+            .with_primary(Span::new(22, 25), "expected `Int`, found `String`")
+            .with_secondary(Span::new(18, 19), "this is an `Int`")
+            .with_note("operands of `+` must have the same type");
+        assert_eq!(
+            diagnostic.render(source, "main.nio"),
+            concat!(
+                "error[E0100]: mismatched types\n",
+                " --> main.nio:2:13\n",
+                "  |\n",
+                "2 | let y = x + \"a\"\n",
+                "  |         - this is an `Int`\n",
+                "  |             ^^^ expected `Int`, found `String`\n",
+                "  |\n",
+                "  = note: operands of `+` must have the same type\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_render_multiple_lines() {
+        let source = "def f(): Int = 1\n\n\nf(2)";
+        let diagnostic = Diagnostic::error("wrong number of arguments")
+            .with_primary(Span::new(19, 23), "")
+            .with_secondary(Span::new(0, 16), "defined here");
+        assert_eq!(
+            diagnostic.render(source, "main.nio"),
+            concat!(
+                "error: wrong number of arguments\n",
+                " --> main.nio:4:1\n",
+                "  |\n",
+                "1 | def f(): Int = 1\n",
+                "  | ---------------- defined here\n",
+                "...\n",
+                "4 | f(2)\n",
+                "  | ^^^^\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_render_end_of_file() {
+        let source = "1 +";
+        let diagnostic =
+            Diagnostic::error("unexpected end of file").with_primary(Span::new(3, 3), "");
+        assert_eq!(
+            diagnostic.render(source, "main.nio"),
+            concat!(
+                "error: unexpected end of file\n",
+                " --> main.nio:1:4\n",
+                "  |\n",
+                "1 | 1 +\n",
+                "  |    ^\n",
+            )
+        );
+    }
+}
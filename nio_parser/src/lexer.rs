@@ -1,19 +1,84 @@
-use crate::{Location, token::Token};
-use std::str::Chars;
+use crate::ast::{IntLit, IntSuffix};
+use crate::{Error, Location, span::Span, token::Token};
+use std::{fmt, str::Chars};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexError {
+    /// The span covers the opening quote.
+    UnterminatedString {
+        span: Span,
+    },
+    /// The span covers the opening `r#"`.
+    UnterminatedRawString {
+        span: Span,
+        hashes: usize,
+    },
+    /// The span covers the opening `/*`.
+    UnterminatedComment {
+        span: Span,
+    },
+    InvalidEscape {
+        span: Span,
+        escape: char,
+    },
+    InvalidUnicodeEscape {
+        span: Span,
+    },
+    InvalidCharacter {
+        span: Span,
+        ch: char,
+    },
+    MalformedNumber {
+        span: Span,
+    },
+}
+
+/// Source text that is skipped by the lexer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriviaKind {
+    LineComment,
+    BlockComment,
+}
 
 pub struct Lexer<'a> {
     input: &'a str,
     chars: Chars<'a>,
+    end: usize,
+    trivia: Option<Vec<Trivia>>,
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(input: &'a str) -> Self {
+        Self::with_range(input, 0, input.len())
+    }
+
+    /// Creates a lexer that only reads `input[start..end]`, keeping offsets relative to `input`.
+    pub fn with_range(input: &'a str, start: usize, end: usize) -> Self {
         Self {
             input,
-            chars: input.chars(),
+            chars: input[start..end].chars(),
+            end,
+            trivia: None,
         }
     }
 
+    /// Makes the lexer record the comments it skips, see `trivia`.
+    pub fn preserve_trivia(mut self) -> Self {
+        self.trivia = Some(Vec::new());
+        self
+    }
+
+    /// The comments skipped so far, if enabled by `preserve_trivia`.
+    pub fn trivia(&self) -> &[Trivia] {
+        self.trivia.as_deref().unwrap_or_default()
+    }
+
     fn next_char(&mut self) {
         self.chars.next();
     }
@@ -24,80 +89,56 @@ impl<'a> Lexer<'a> {
         self.chars.clone().next()
     }
 
+    fn rest(&self) -> &'a str {
+        self.chars.as_str()
+    }
+
     pub fn offset(&self) -> usize {
-        self.input.len() - self.chars.as_str().len()
+        self.end - self.chars.as_str().len()
     }
 
-    pub fn next_token(&mut self) -> Token<'a> {
-        self.skip_whitespace();
-        match self.peek_char() {
+    pub fn next_token(&mut self) -> Result<Token<'a>, LexError> {
+        self.skip_whitespace()?;
+        let token = match self.peek_char() {
+            Some('r') if is_raw_string_start(self.rest()) => self.lex_raw_string()?,
             Some('a'..='z' | 'A'..='Z' | '_') => {
                 let start = self.offset();
                 self.next_char();
-                loop {
-                    match self.peek_char() {
-                        Some('a'..='z' | 'A'..='Z' | '0'..='9' | '_') => {
-                            self.next_char();
-                        }
-                        _ => {
-                            break;
-                        }
-                    }
+                while let Some('a'..='z' | 'A'..='Z' | '0'..='9' | '_') = self.peek_char() {
+                    self.next_char();
                 }
                 let end = self.offset();
                 match &self.input[start..end] {
                     "def" => Token::KwDef,
                     "let" => Token::KwLet,
+                    "mut" => Token::KwMut,
+                    "if" => Token::KwIf,
+                    "else" => Token::KwElse,
+                    "while" => Token::KwWhile,
+                    "for" => Token::KwFor,
+                    "in" => Token::KwIn,
+                    "loop" => Token::KwLoop,
+                    "break" => Token::KwBreak,
+                    "continue" => Token::KwContinue,
+                    "true" => Token::KwTrue,
+                    "false" => Token::KwFalse,
+                    "type" => Token::KwType,
+                    "match" => Token::KwMatch,
                     ident => Token::Ident(ident),
                 }
             }
-            Some('1'..='9') => {
-                let start = self.offset();
-                self.next_char();
-                loop {
-                    match self.peek_char() {
-                        Some('0'..='9') => {
-                            self.next_char();
-                        }
-                        _ => {
-                            break;
-                        }
-                    }
-                }
-                let end = self.offset();
-                return Token::Int(&self.input[start..end]);
-            }
-            Some('"') => {
-                let start = self.offset();
+            Some('0'..='9') => self.lex_number()?,
+            Some('"') => self.lex_string()?,
+            Some('+') => {
                 self.next_char();
-                let mut value = String::new();
-                loop {
-                    match self.peek_char() {
-                        Some('\\') => {
-                            break Token::Unexpected('\\');
-                        }
-                        Some('"') => {
-                            self.next_char();
-                            let end = self.offset();
-                            break Token::String {
-                                raw: &self.input[start..end],
-                                value,
-                            };
-                        }
-                        Some(ch) => {
-                            value.push(ch);
-                            self.next_char();
-                        }
-                        None => {
-                            break Token::Unexpected('"');
-                        }
+                match self.peek_char() {
+                    Some('=') => {
+                        self.next_char();
+                        Token::PlusEq
                     }
+                    _ => Token::Plus,
                 }
             }
-            Some('+') => {
-                self.next_char();
-                Token::Plus
-            }
             Some('-') => {
                 self.next_char();
                 match self.peek_char() {
@@ -105,6 +146,10 @@ impl<'a> Lexer<'a> {
                         self.next_char();
                         Token::RArrow
                     }
+                    Some('=') => {
+                        self.next_char();
+                        Token::MinusEq
+                    }
                     _ => Token::Minus,
                 }
             }
@@ -112,6 +157,16 @@ impl<'a> Lexer<'a> {
                 self.next_char();
                 Token::Star
             }
+            // `skip_whitespace` has skipped the `///` comments that don't document anything.
+            Some('/') if is_doc_comment(self.rest()) => {
+                let start = self.offset() + "///".len();
+                while let Some(ch) = self.peek_char()
+                    && ch != '\n'
+                {
+                    self.next_char();
+                }
+                Token::DocComment(&self.input[start..self.offset()])
+            }
             Some('/') => {
                 self.next_char();
                 Token::Slash
@@ -126,15 +181,33 @@ impl<'a> Lexer<'a> {
             }
             Some('!') => {
                 self.next_char();
-                Token::Not
+                match self.peek_char() {
+                    Some('=') => {
+                        self.next_char();
+                        Token::NotEq
+                    }
+                    _ => Token::Not,
+                }
             }
             Some('&') => {
                 self.next_char();
-                Token::And
+                match self.peek_char() {
+                    Some('&') => {
+                        self.next_char();
+                        Token::AndAnd
+                    }
+                    _ => Token::And,
+                }
             }
             Some('|') => {
                 self.next_char();
-                Token::Or
+                match self.peek_char() {
+                    Some('|') => {
+                        self.next_char();
+                        Token::OrOr
+                    }
+                    _ => Token::Or,
+                }
             }
             Some('=') => {
                 self.next_char();
@@ -157,6 +230,10 @@ impl<'a> Lexer<'a> {
                         self.next_char();
                         Token::Ge
                     }
+                    Some('>') => {
+                        self.next_char();
+                        Token::Shr
+                    }
                     _ => Token::Gt,
                 }
             }
@@ -167,6 +244,10 @@ impl<'a> Lexer<'a> {
                         self.next_char();
                         Token::Le
                     }
+                    Some('<') => {
+                        self.next_char();
+                        Token::Shl
+                    }
                     _ => Token::Lt,
                 }
             }
@@ -200,7 +281,13 @@ impl<'a> Lexer<'a> {
             }
             Some('.') => {
                 self.next_char();
-                Token::Dot
+                match self.peek_char() {
+                    Some('.') => {
+                        self.next_char();
+                        Token::DotDot
+                    }
+                    _ => Token::Dot,
+                }
             }
             Some(',') => {
                 self.next_char();
@@ -219,35 +306,378 @@ impl<'a> Lexer<'a> {
                 Token::Nl
             }
             Some(ch) => {
+                let start = self.offset();
                 self.next_char();
-                Token::Unexpected(ch)
+                let span = Span::new(start, self.offset());
+                return Err(LexError::InvalidCharacter { span, ch });
             }
             None => Token::Eof,
+        };
+        Ok(token)
+    }
+
+    /// Lexes an integer literal such as `42`, `1_000`, `0xff` or `7i64`, or a float literal
+    /// such as `1.5` or `2e-3`.
+    fn lex_number(&mut self) -> Result<Token<'a>, LexError> {
+        let start = self.offset();
+        let radix = match self.rest().get(..2) {
+            Some("0x") => 16,
+            Some("0o") => 8,
+            Some("0b") => 2,
+            _ => 10,
+        };
+        if radix != 10 {
+            self.next_char();
+            self.next_char();
+        }
+        let digits_start = self.offset();
+        while let Some(ch) = self.peek_char()
+            && (ch == '_' || ch.is_ascii_digit() || (radix == 16 && ch.is_ascii_hexdigit()))
+        {
+            self.next_char();
+        }
+        // `1..2` is a range and `1.x` may be a field access, so a fraction must start with a digit.
+        let mut is_float = false;
+        if radix == 10 && self.rest().starts_with('.') && starts_with_digit(&self.rest()[1..]) {
+            self.next_char();
+            self.skip_digits();
+            is_float = true;
+        }
+        if radix == 10
+            && let Some(exponent) = self.rest().strip_prefix(['e', 'E'])
+        {
+            let exponent = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+            if starts_with_digit(exponent) {
+                self.next_char();
+                if let Some('+' | '-') = self.peek_char() {
+                    self.next_char();
+                }
+                self.skip_digits();
+                is_float = true;
+            }
+        }
+        let digits_end = self.offset();
+        while let Some('a'..='z' | 'A'..='Z' | '0'..='9' | '_') = self.peek_char() {
+            self.next_char();
         }
+        let end = self.offset();
+        let span = Span::new(start, end);
+
+        if is_float {
+            if digits_end != end {
+                return Err(LexError::MalformedNumber { span });
+            }
+            let digits = self.input[start..end].replace('_', "");
+            return Ok(Token::Float {
+                raw: &self.input[start..end],
+                value: digits.parse().unwrap(),
+            });
+        }
+        let suffix = match &self.input[digits_end..end] {
+            "" => None,
+            "i32" => Some(IntSuffix::I32),
+            "i64" => Some(IntSuffix::I64),
+            _ => return Err(LexError::MalformedNumber { span }),
+        };
+        let mut value: u128 = 0;
+        let mut has_digits = false;
+        for ch in self.input[digits_start..digits_end].chars() {
+            if ch == '_' {
+                continue;
+            }
+            let digit = ch
+                .to_digit(radix)
+                .ok_or(LexError::MalformedNumber { span })?;
+            // Literals too large for `u128` saturate; they do not fit in any type anyway.
+            value = value
+                .checked_mul(radix as u128)
+                .and_then(|value| value.checked_add(digit as u128))
+                .unwrap_or(u128::MAX);
+            has_digits = true;
+        }
+        if !has_digits {
+            return Err(LexError::MalformedNumber { span });
+        }
+        Ok(Token::Int {
+            raw: &self.input[start..end],
+            value: IntLit {
+                value,
+                radix,
+                suffix,
+            },
+        })
     }
 
-    fn skip_whitespace(&mut self) {
+    fn skip_digits(&mut self) {
+        while let Some('0'..='9' | '_') = self.peek_char() {
+            self.next_char();
+        }
+    }
+
+    /// Lexes a string literal such as `"a\tb"`, which must end on the same line.
+    fn lex_string(&mut self) -> Result<Token<'a>, LexError> {
+        let start = self.offset();
+        self.next_char();
+        let mut value = String::new();
         loop {
             match self.peek_char() {
-                Some('\t' | ' ') => {
+                Some('\\') => {
+                    value.push(self.lex_escape(start)?);
+                }
+                Some('"') => {
                     self.next_char();
+                    let end = self.offset();
+                    return Ok(Token::String {
+                        raw: &self.input[start..end],
+                        value,
+                    });
+                }
+                Some('\n') | None => {
+                    let span = Span::new(start, start + 1);
+                    return Err(LexError::UnterminatedString { span });
                 }
-                _ => {
-                    break;
+                Some(ch) => {
+                    value.push(ch);
+                    self.next_char();
                 }
             }
         }
     }
+
+    /// Lexes an escape sequence in the string starting at `string_start`,
+    /// returning the character it stands for.
+    fn lex_escape(&mut self, string_start: usize) -> Result<char, LexError> {
+        let start = self.offset();
+        self.next_char();
+        let escape = match self.peek_char() {
+            // A backslash at the end of the line does not escape the newline.
+            Some('\n') | None => {
+                let span = Span::new(string_start, string_start + 1);
+                return Err(LexError::UnterminatedString { span });
+            }
+            Some(escape) => escape,
+        };
+        self.next_char();
+        match escape {
+            'n' => Ok('\n'),
+            't' => Ok('\t'),
+            'r' => Ok('\r'),
+            '0' => Ok('\0'),
+            '\\' => Ok('\\'),
+            '"' => Ok('"'),
+            'u' => self.lex_unicode_escape(start),
+            _ => {
+                let span = Span::new(start, self.offset());
+                Err(LexError::InvalidEscape { span, escape })
+            }
+        }
+    }
+
+    /// Lexes the `{1F600}` part of a `\u{1F600}` escape starting at `start`.
+    fn lex_unicode_escape(&mut self, start: usize) -> Result<char, LexError> {
+        let mut digits = None;
+        if self.peek_char() == Some('{') {
+            self.next_char();
+            let digits_start = self.offset();
+            while let Some(ch) = self.peek_char()
+                && ch.is_ascii_hexdigit()
+            {
+                self.next_char();
+            }
+            let digits_end = self.offset();
+            if self.peek_char() == Some('}') {
+                self.next_char();
+                digits = Some(&self.input[digits_start..digits_end]);
+            }
+        }
+        let span = Span::new(start, self.offset());
+        digits
+            .filter(|digits| (1..=6).contains(&digits.len()))
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .and_then(char::from_u32)
+            .ok_or(LexError::InvalidUnicodeEscape { span })
+    }
+
+    /// Lexes a raw string literal such as `r"C:\dir"` or `r#"say "hi""#`,
+    /// which has no escape sequences and may span several lines.
+    fn lex_raw_string(&mut self) -> Result<Token<'a>, LexError> {
+        let start = self.offset();
+        self.next_char();
+        let mut hashes = 0;
+        while self.peek_char() == Some('#') {
+            hashes += 1;
+            self.next_char();
+        }
+        self.next_char();
+        let value_start = self.offset();
+        let closing = format!("\"{}", "#".repeat(hashes));
+        let Some(len) = self.rest().find(&closing) else {
+            let span = Span::new(start, value_start);
+            return Err(LexError::UnterminatedRawString { span, hashes });
+        };
+        let value_end = value_start + len;
+        let end = value_end + closing.len();
+        self.chars = self.input[end..self.end].chars();
+        Ok(Token::String {
+            raw: &self.input[start..end],
+            value: self.input[value_start..value_end].to_string(),
+        })
+    }
+
+    /// Skips spaces, tabs and comments other than doc comments.
+    fn skip_whitespace(&mut self) -> Result<(), LexError> {
+        loop {
+            let start = self.offset();
+            let rest = self.rest();
+            if let Some('\t' | ' ') = self.peek_char() {
+                self.next_char();
+            } else if rest.starts_with("//") && !self.at_doc_comment() {
+                while let Some(ch) = self.peek_char()
+                    && ch != '\n'
+                {
+                    self.next_char();
+                }
+                self.push_trivia(TriviaKind::LineComment, start);
+            } else if rest.starts_with("/*") {
+                self.skip_block_comment()?;
+                self.push_trivia(TriviaKind::BlockComment, start);
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Whether a `///` comment starts here that documents a `def` or `type`. It must be alone on
+    /// its line, and followed on the next line by more of the doc comment, by an annotation or by
+    /// the definition itself. Any other `///` is an ordinary comment.
+    fn at_doc_comment(&self) -> bool {
+        let offset = self.offset();
+        let line_start = self.input[..offset].rfind('\n').map_or(0, |i| i + 1);
+        if !is_doc_comment(self.rest()) || !self.input[line_start..offset].trim().is_empty() {
+            return false;
+        }
+        let mut lookahead = Lexer {
+            input: self.input,
+            chars: self.chars.clone(),
+            end: self.end,
+            trivia: None,
+        };
+        while let Some(ch) = lookahead.peek_char()
+            && ch != '\n'
+        {
+            lookahead.next_char();
+        }
+        if lookahead.next_token() != Ok(Token::Nl) {
+            return false;
+        }
+        match lookahead.next_token() {
+            // The next line is only lexed as a doc comment if it documents a definition too.
+            Ok(Token::DocComment(_) | Token::KwDef | Token::KwType) => true,
+            Ok(Token::At) => loop {
+                match lookahead.next_token() {
+                    Ok(Token::KwDef | Token::KwType) => break true,
+                    Ok(Token::KwLet | Token::Eof) | Err(_) => break false,
+                    Ok(_) => {}
+                }
+            },
+            _ => false,
+        }
+    }
+
+    /// Skips a block comment, which may contain other block comments.
+    fn skip_block_comment(&mut self) -> Result<(), LexError> {
+        let mut openings = Vec::new();
+        loop {
+            let rest = self.rest();
+            if rest.starts_with("/*") {
+                openings.push(self.offset());
+                self.next_char();
+                self.next_char();
+            } else if rest.starts_with("*/") {
+                openings.pop();
+                self.next_char();
+                self.next_char();
+                if openings.is_empty() {
+                    return Ok(());
+                }
+            } else if self.peek_char().is_some() {
+                self.next_char();
+            } else {
+                let start = openings.pop().unwrap();
+                let span = Span::new(start, start + "/*".len());
+                return Err(LexError::UnterminatedComment { span });
+            }
+        }
+    }
+
+    fn push_trivia(&mut self, kind: TriviaKind, start: usize) {
+        let span = Span::new(start, self.offset());
+        if let Some(trivia) = &mut self.trivia {
+            trivia.push(Trivia { kind, span });
+        }
+    }
+}
+
+/// `r"` or `r#"`, with any number of `#`s.
+fn is_raw_string_start(rest: &str) -> bool {
+    rest.strip_prefix('r')
+        .is_some_and(|rest| rest.trim_start_matches('#').starts_with('"'))
+}
+
+/// Whether `rest` starts with a decimal digit.
+fn starts_with_digit(rest: &str) -> bool {
+    rest.starts_with(|ch: char| ch.is_ascii_digit())
+}
+
+/// `///` starts a doc comment, but `////` is an ordinary comment.
+fn is_doc_comment(rest: &str) -> bool {
+    rest.starts_with("///") && !rest.starts_with("////")
+}
+
+impl LexError {
+    pub fn span(&self) -> Span {
+        match self {
+            LexError::UnterminatedString { span }
+            | LexError::UnterminatedRawString { span, .. }
+            | LexError::UnterminatedComment { span }
+            | LexError::InvalidEscape { span, .. }
+            | LexError::InvalidUnicodeEscape { span }
+            | LexError::InvalidCharacter { span, .. }
+            | LexError::MalformedNumber { span } => *span,
+        }
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LexError::UnterminatedString { .. } => write!(f, "unterminated string literal"),
+            LexError::UnterminatedRawString { .. } => write!(f, "unterminated raw string literal"),
+            LexError::UnterminatedComment { .. } => write!(f, "unterminated block comment"),
+            LexError::InvalidEscape { escape, .. } => {
+                write!(f, "unknown character escape `\\{}`", escape.escape_debug())
+            }
+            LexError::InvalidUnicodeEscape { .. } => write!(f, "invalid unicode escape"),
+            LexError::InvalidCharacter { ch, .. } => {
+                write!(f, "invalid character `{}`", ch.escape_debug())
+            }
+            LexError::MalformedNumber { .. } => write!(f, "malformed number literal"),
+        }
+    }
 }
 
 impl<'a> Iterator for Lexer<'a> {
-    type Item = Result<(Location, Token<'a>, Location), &'static str>;
+    type Item = Result<(Location, Token<'a>, Location), Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let token = self.next_token();
-        match token {
-            Token::Eof => None,
-            _ => Some(Ok((0, token, 0))),
+        if let Err(err) = self.skip_whitespace() {
+            return Some(Err(err));
+        }
+        let start = self.offset();
+        match self.next_token() {
+            Ok(Token::Eof) => None,
+            Ok(token) => Some(Ok((start, token, self.offset()))),
+            Err(err) => Some(Err(err)),
         }
     }
 }
@@ -256,15 +686,309 @@ impl<'a> Iterator for Lexer<'a> {
 mod tests {
     use super::*;
 
+    fn int(raw: &str, value: u128, radix: u32, suffix: Option<IntSuffix>) -> Token<'_> {
+        Token::Int {
+            raw,
+            value: IntLit {
+                value,
+                radix,
+                suffix,
+            },
+        }
+    }
+
     #[test]
     fn test_next_token() {
         let code = "12 + 34 * 56";
         let mut l = Lexer::new(code);
-        assert_eq!(l.next_token(), Token::Int("12"));
-        assert_eq!(l.next_token(), Token::Plus);
-        assert_eq!(l.next_token(), Token::Int("34"));
-        assert_eq!(l.next_token(), Token::Star);
-        assert_eq!(l.next_token(), Token::Int("56"));
-        assert_eq!(l.next_token(), Token::Eof);
+        assert_eq!(l.next_token(), Ok(int("12", 12, 10, None)));
+        assert_eq!(l.next_token(), Ok(Token::Plus));
+        assert_eq!(l.next_token(), Ok(int("34", 34, 10, None)));
+        assert_eq!(l.next_token(), Ok(Token::Star));
+        assert_eq!(l.next_token(), Ok(int("56", 56, 10, None)));
+        assert_eq!(l.next_token(), Ok(Token::Eof));
+    }
+
+    #[test]
+    fn test_lex_errors() {
+        let error = |code| Lexer::new(code).next_token().unwrap_err();
+        assert_eq!(
+            error("\"abc\ndef"),
+            LexError::UnterminatedString {
+                span: Span::new(0, 1)
+            }
+        );
+        assert_eq!(
+            error("\"a\\qb\""),
+            LexError::InvalidEscape {
+                span: Span::new(2, 4),
+                escape: 'q'
+            }
+        );
+        assert_eq!(
+            error("/* a /* b */"),
+            LexError::UnterminatedComment {
+                span: Span::new(0, 2)
+            }
+        );
+        assert_eq!(
+            error("\"abc\\\n\""),
+            LexError::UnterminatedString {
+                span: Span::new(0, 1)
+            }
+        );
+        assert_eq!(
+            error("r#\"abc\""),
+            LexError::UnterminatedRawString {
+                span: Span::new(0, 3),
+                hashes: 1
+            }
+        );
+        assert_eq!(
+            error(r#""\u41""#),
+            LexError::InvalidUnicodeEscape {
+                span: Span::new(1, 3)
+            }
+        );
+        for invalid in [r#""\u{}""#, r#""\u{D800}""#, r#""\u{1234567}""#] {
+            assert_eq!(
+                error(invalid),
+                LexError::InvalidUnicodeEscape {
+                    span: Span::new(1, invalid.len() - 1)
+                },
+                "{}",
+                invalid
+            );
+        }
+        assert_eq!(
+            error("$"),
+            LexError::InvalidCharacter {
+                span: Span::new(0, 1),
+                ch: '$'
+            }
+        );
+        assert_eq!(
+            error("12ab"),
+            LexError::MalformedNumber {
+                span: Span::new(0, 4)
+            }
+        );
+    }
+
+    #[test]
+    fn test_operators() {
+        let code = "! != & && | || < <= << > >= >> . ..";
+        let tokens: Vec<_> = Lexer::new(code).map(|item| item.unwrap().1).collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Not,
+                Token::NotEq,
+                Token::And,
+                Token::AndAnd,
+                Token::Or,
+                Token::OrOr,
+                Token::Lt,
+                Token::Le,
+                Token::Shl,
+                Token::Gt,
+                Token::Ge,
+                Token::Shr,
+                Token::Dot,
+                Token::DotDot,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_int_literals() {
+        let lex = |code| Lexer::new(code).next_token();
+        assert_eq!(lex("0"), Ok(int("0", 0, 10, None)));
+        assert_eq!(lex("1_000_000"), Ok(int("1_000_000", 1_000_000, 10, None)));
+        assert_eq!(lex("0xFF_ff"), Ok(int("0xFF_ff", 0xffff, 16, None)));
+        assert_eq!(lex("0o17"), Ok(int("0o17", 0o17, 8, None)));
+        assert_eq!(lex("0b1010"), Ok(int("0b1010", 0b1010, 2, None)));
+        assert_eq!(lex("7i64"), Ok(int("7i64", 7, 10, Some(IntSuffix::I64))));
+        assert_eq!(
+            lex("0x1fi32"),
+            Ok(int("0x1fi32", 0x1f, 16, Some(IntSuffix::I32)))
+        );
+        assert_eq!(
+            lex("99999999999999999999999999999999999999999"),
+            Ok(int(
+                "99999999999999999999999999999999999999999",
+                u128::MAX,
+                10,
+                None
+            ))
+        );
+        for malformed in ["0x", "0b102", "0o8", "12i16", "1f", "0x_"] {
+            assert!(
+                matches!(lex(malformed), Err(LexError::MalformedNumber { .. })),
+                "{}",
+                malformed
+            );
+        }
+    }
+
+    #[test]
+    fn test_float_literals() {
+        let lex = |code| Lexer::new(code).next_token();
+        assert_eq!(
+            lex("1.5"),
+            Ok(Token::Float {
+                raw: "1.5",
+                value: 1.5
+            })
+        );
+        assert_eq!(
+            lex("1_000.25"),
+            Ok(Token::Float {
+                raw: "1_000.25",
+                value: 1000.25
+            })
+        );
+        assert_eq!(
+            lex("2e3"),
+            Ok(Token::Float {
+                raw: "2e3",
+                value: 2e3
+            })
+        );
+        assert_eq!(
+            lex("2.5E-3"),
+            Ok(Token::Float {
+                raw: "2.5E-3",
+                value: 2.5e-3
+            })
+        );
+        let tokens: Vec<_> = Lexer::new("0..10 1.x")
+            .map(|item| item.unwrap().1)
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                int("0", 0, 10, None),
+                Token::DotDot,
+                int("10", 10, 10, None),
+                int("1", 1, 10, None),
+                Token::Dot,
+                Token::Ident("x"),
+            ]
+        );
+        for malformed in ["1.5i32", "1e", "2e+x"] {
+            assert!(
+                matches!(lex(malformed), Err(LexError::MalformedNumber { .. })),
+                "{}",
+                malformed
+            );
+        }
+    }
+
+    #[test]
+    fn test_string_literals() {
+        let value = |code| match Lexer::new(code).next_token() {
+            Ok(Token::String { value, .. }) => value,
+            token => panic!("{:?}", token),
+        };
+        assert_eq!(value(r#""a\tb\nc""#), "a\tb\nc");
+        assert_eq!(value(r#""\\ \" \0 \r""#), "\\ \" \0 \r");
+        assert_eq!(value(r#""\u{41}\u{1F600}""#), "A\u{1F600}");
+        assert_eq!(value(r#"r"C:\dir""#), "C:\\dir");
+        assert_eq!(value(r###"r#"say "hi""#"###), "say \"hi\"");
+        assert_eq!(value("r\"two\nlines\""), "two\nlines");
+    }
+
+    #[test]
+    fn test_comments() {
+        let code = "1 // one\n/* a /* nested */ comment */ 2 //// not doc\n/// doc\ndef";
+        let mut l = Lexer::new(code).preserve_trivia();
+        assert_eq!(l.next_token(), Ok(int("1", 1, 10, None)));
+        assert_eq!(l.next_token(), Ok(Token::Nl));
+        assert_eq!(l.next_token(), Ok(int("2", 2, 10, None)));
+        assert_eq!(l.next_token(), Ok(Token::Nl));
+        assert_eq!(l.next_token(), Ok(Token::DocComment(" doc")));
+        assert_eq!(l.next_token(), Ok(Token::Nl));
+        assert_eq!(l.next_token(), Ok(Token::KwDef));
+        assert_eq!(l.next_token(), Ok(Token::Eof));
+        assert_eq!(
+            l.trivia(),
+            &[
+                Trivia {
+                    kind: TriviaKind::LineComment,
+                    span: Span::new(2, 8),
+                },
+                Trivia {
+                    kind: TriviaKind::BlockComment,
+                    span: Span::new(9, 37),
+                },
+                Trivia {
+                    kind: TriviaKind::LineComment,
+                    span: Span::new(40, 52),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stray_doc_comments() {
+        let tokens = |code| {
+            let mut l = Lexer::new(code);
+            let mut tokens = Vec::new();
+            while let Ok(token) = l.next_token()
+                && token != Token::Eof
+            {
+                tokens.push(token);
+            }
+            tokens
+        };
+        assert_eq!(
+            tokens("/// a\n@export(\"f\")\n// b\ndef"),
+            [
+                Token::DocComment(" a"),
+                Token::Nl,
+                Token::At,
+                Token::Ident("export"),
+                Token::LParen,
+                Token::String {
+                    raw: "\"f\"",
+                    value: "f".to_string()
+                },
+                Token::RParen,
+                Token::Nl,
+                Token::Nl,
+                Token::KwDef,
+            ]
+        );
+        assert_eq!(tokens("/// a\nlet"), [Token::Nl, Token::KwLet]);
+        assert_eq!(
+            tokens("{ /// a\ndef"),
+            [Token::LBrace, Token::Nl, Token::KwDef]
+        );
+        assert_eq!(
+            tokens("/// a\n\n/// b\ntype"),
+            [
+                Token::Nl,
+                Token::Nl,
+                Token::DocComment(" b"),
+                Token::Nl,
+                Token::KwType
+            ]
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        let code = "let x = 12 + \"ab\"";
+        let spans: Vec<_> = Lexer::new(code)
+            .map(|item| {
+                let (start, _, end) = item.unwrap();
+                (start, end)
+            })
+            .collect();
+        assert_eq!(
+            spans,
+            vec![(0, 3), (4, 5), (6, 7), (8, 10), (11, 12), (13, 17)]
+        );
     }
 }
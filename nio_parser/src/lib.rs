@@ -1,15 +1,272 @@
 pub mod ast;
+pub mod diagnostic;
 pub mod lexer;
+pub mod span;
 pub mod token;
 
-use lalrpop_util::{ParseError, lalrpop_mod};
+use diagnostic::Diagnostic;
+use lalrpop_util::{ErrorRecovery, lalrpop_mod};
+use span::Span;
 
-lalrpop_mod!(pub grammar);
+pub use lalrpop_util::ParseError;
+
+lalrpop_mod!(
+    #[allow(clippy::ptr_arg)]
+    pub grammar
+);
 
 pub type Location = usize;
-pub type Error = &'static str;
+pub type Error = lexer::LexError;
+
+/// Parses `input`, failing on the first syntax error.
+pub fn parse(input: &str) -> Result<ast::Program, ParseError<Location, token::Token<'_>, Error>> {
+    let (program, errors) = parse_with_errors(input);
+    match errors.into_iter().next() {
+        Some(err) => Err(err),
+        None => Ok(program),
+    }
+}
+
+/// Parses `input`, recovering from syntax errors at the end of the line.
+///
+/// Returns every error found, along with the program in which the broken parts
+/// are replaced by `StmtKind::Error` and `ExprKind::Error` placeholders.
+pub fn parse_with_errors(
+    input: &str,
+) -> (
+    ast::Program,
+    Vec<ParseError<Location, token::Token<'_>, Error>>,
+) {
+    let mut statements = Vec::new();
+    let mut errors = Vec::new();
+    let mut start = 0;
+    loop {
+        let (mut program, mut range_errors) = parse_range(input, start, input.len());
+        let Some(ParseError::User { error }) =
+            range_errors.pop_if(|err| matches!(err, ParseError::User { .. }))
+        else {
+            statements.append(&mut program.statements);
+            errors.append(&mut range_errors);
+            break;
+        };
+        // The parser cannot go on after a lexical error, so keep what precedes
+        // the offending line and start over from the next one. The input goes on
+        // after that line, so reaching its end is not an error.
+        let span = error.span();
+        let line_start = input[..span.start]
+            .rfind('\n')
+            .map_or(0, |i| i + 1)
+            .max(start);
+        let (mut program, range_errors) = parse_range(input, start, line_start);
+        statements.append(&mut program.statements);
+        errors.extend(
+            range_errors
+                .into_iter()
+                .filter(|err| !matches!(err, ParseError::UnrecognizedEof { .. })),
+        );
+        // An unterminated block comment or raw string takes up the rest of the
+        // input, which would be parsed as code otherwise.
+        let rest_is_skipped = is_rest_skipped(&error);
+        errors.push(ParseError::User { error });
+        if rest_is_skipped {
+            break;
+        }
+        let Some(line_end) = input[span.end..].find('\n').map(|i| span.end + i + 1) else {
+            break;
+        };
+        // Inside a block, the lines up to its end can't be parsed on their own,
+        // so skip them too.
+        let depth = open_brackets(input, start, span.start, 0);
+        let depth = open_brackets(input, span.end, line_end, depth);
+        match skip_open_brackets(input, line_end, depth, &mut errors) {
+            Some(next) => start = next,
+            None => break,
+        }
+    }
+    (ast::Program { statements }, errors)
+}
+
+/// Whether a lexical error takes up the rest of the input.
+fn is_rest_skipped(error: &Error) -> bool {
+    matches!(
+        error,
+        Error::UnterminatedComment { .. } | Error::UnterminatedRawString { .. }
+    )
+}
+
+/// The number of brackets left open after the tokens in `input[start..end]`,
+/// given `depth` open before them. Lexical errors are ignored.
+fn open_brackets(input: &str, start: Location, end: Location, depth: usize) -> usize {
+    lexer::Lexer::with_range(input, start, end)
+        .flatten()
+        .fold(depth, |depth, (_, token, _)| bracket_depth(&token, depth))
+}
+
+/// Skips the lines from `start` until the `depth` open brackets are closed at
+/// the end of a line, and returns where the next line starts. Lexical errors
+/// are reported, the first of each line as in `parse_with_errors`.
+fn skip_open_brackets<'input>(
+    input: &'input str,
+    mut start: Location,
+    mut depth: usize,
+    errors: &mut Vec<ParseError<Location, token::Token<'input>, Error>>,
+) -> Option<Location> {
+    'lines: while depth > 0 {
+        for result in lexer::Lexer::with_range(input, start, input.len()) {
+            match result {
+                Ok((_, token::Token::Nl, end)) if depth == 0 => return Some(end),
+                Ok((_, token, _)) => depth = bracket_depth(&token, depth),
+                Err(error) => {
+                    let end = error.span().end;
+                    let rest_is_skipped = is_rest_skipped(&error);
+                    errors.push(ParseError::User { error });
+                    if rest_is_skipped {
+                        return None;
+                    }
+                    start = end + input[end..].find('\n')? + 1;
+                    continue 'lines;
+                }
+            }
+        }
+        return None;
+    }
+    Some(start)
+}
+
+/// The number of open brackets after `token`, given `depth` before it.
+fn bracket_depth(token: &token::Token<'_>, depth: usize) -> usize {
+    match token {
+        token::Token::LParen | token::Token::LBrace | token::Token::LBracket => depth + 1,
+        token::Token::RParen | token::Token::RBrace | token::Token::RBracket => {
+            depth.saturating_sub(1)
+        }
+        _ => depth,
+    }
+}
+
+fn parse_range(
+    input: &str,
+    start: Location,
+    end: Location,
+) -> (
+    ast::Program,
+    Vec<ParseError<Location, token::Token<'_>, Error>>,
+) {
+    let lexer = lexer::Lexer::with_range(input, start, end);
+    let mut recovered = Vec::new();
+    let result = grammar::ProgramParser::new().parse(&mut recovered, lexer);
+    let mut errors: Vec<_> = recovered
+        .into_iter()
+        .map(|recovery| recovery.error)
+        .collect();
+    let program = match result {
+        Ok(program) => program,
+        Err(err) => {
+            errors.push(err);
+            ast::Program { statements: vec![] }
+        }
+    };
+    (program, errors)
+}
+
+/// The span of the tokens skipped while recovering from an error.
+fn recovery_span(recovery: &ErrorRecovery<Location, token::Token<'_>, Error>) -> Span {
+    let start = match &recovery.error {
+        ParseError::InvalidToken { location } => *location,
+        ParseError::UnrecognizedEof { location, .. } => *location,
+        ParseError::UnrecognizedToken { token, .. } => token.0,
+        ParseError::ExtraToken { token } => token.0,
+        ParseError::User { .. } => 0,
+    };
+    let end = recovery
+        .dropped_tokens
+        .last()
+        .map_or(start, |token| token.2);
+    Span::new(start, end)
+}
+
+impl From<ParseError<Location, token::Token<'_>, Error>> for Diagnostic {
+    fn from(err: ParseError<Location, token::Token<'_>, Error>) -> Self {
+        match err {
+            ParseError::InvalidToken { location } => Diagnostic::error("invalid token")
+                .with_code("E0001")
+                .with_primary(Span::new(location, location), ""),
+            ParseError::UnrecognizedEof { location, expected } => {
+                Diagnostic::error("unexpected end of file")
+                    .with_code("E0002")
+                    .with_primary(Span::new(location, location), describe_expected(&expected))
+            }
+            ParseError::UnrecognizedToken {
+                token: (start, token, end),
+                expected,
+            } => Diagnostic::error(format!("unexpected {}", token))
+                .with_code("E0003")
+                .with_primary(Span::new(start, end), describe_expected(&expected)),
+            ParseError::ExtraToken {
+                token: (start, token, end),
+            } => Diagnostic::error(format!("unexpected {}", token))
+                .with_code("E0003")
+                .with_primary(Span::new(start, end), "expected end of file"),
+            ParseError::User { error } => Diagnostic::from(error),
+        }
+    }
+}
+
+impl From<lexer::LexError> for Diagnostic {
+    fn from(err: lexer::LexError) -> Self {
+        use lexer::LexError::*;
+
+        let diagnostic = Diagnostic::error(err.to_string());
+        match err {
+            UnterminatedString { span } => diagnostic
+                .with_code("E0004")
+                .with_primary(span, "starting here")
+                .with_help("add a closing `\"` before the end of the line"),
+            UnterminatedRawString { span, hashes } => diagnostic
+                .with_code("E0004")
+                .with_primary(span, "starting here")
+                .with_help(format!("add a closing `\"{}`", "#".repeat(hashes))),
+            UnterminatedComment { span } => diagnostic
+                .with_code("E0008")
+                .with_primary(span, "starting here")
+                .with_note("block comments can be nested, so each `/*` needs its own `*/`"),
+            InvalidEscape { span, .. } => diagnostic
+                .with_code("E0005")
+                .with_primary(span, "unknown escape")
+                .with_note("the valid escapes are `\\n`, `\\t`, `\\r`, `\\0`, `\\\\`, `\\\"` and `\\u{...}`")
+                .with_help("use a raw string like `r\"...\"` to write backslashes as they are"),
+            InvalidUnicodeEscape { span } => diagnostic
+                .with_code("E0005")
+                .with_primary(span, "")
+                .with_help("unicode escapes look like `\\u{1F600}`, with up to 6 hex digits"),
+            InvalidCharacter { span, .. } => diagnostic
+                .with_code("E0006")
+                .with_primary(span, "not valid in Nio source"),
+            MalformedNumber { span } => diagnostic
+                .with_code("E0007")
+                .with_primary(span, "numbers cannot be followed by letters")
+                .with_help("separate the number from the following name with a space"),
+        }
+    }
+}
 
-pub fn parse(input: &str) -> Result<ast::Program, ParseError<Location, token::Token, Error>> {
-    let lexer = lexer::Lexer::new(input);
-    grammar::ProgramParser::new().parse(lexer)
+/// Turns LALRPOP's list of expected terminals into "expected one of `+`, identifier".
+fn describe_expected(expected: &[String]) -> String {
+    let names: Vec<String> = expected
+        .iter()
+        .map(|terminal| match terminal.as_str() {
+            "IDENT" => "identifier".to_string(),
+            "INT" => "integer literal".to_string(),
+            "FLOAT" => "float literal".to_string(),
+            "STRING" => "string literal".to_string(),
+            "DOC_COMMENT" => "doc comment".to_string(),
+            "NEWLINE" => "newline".to_string(),
+            quoted => format!("`{}`", quoted.trim_matches('"')),
+        })
+        .collect();
+    match names.as_slice() {
+        [] => String::new(),
+        [name] => format!("expected {}", name),
+        _ => format!("expected one of {}", names.join(", ")),
+    }
 }
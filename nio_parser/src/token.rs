@@ -1,47 +1,130 @@
+use crate::ast::IntLit;
 use std::fmt;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token<'a> {
     Ident(&'a str),
-    Int(&'a str),
+    Int { raw: &'a str, value: IntLit },
+    Float { raw: &'a str, value: f64 },
     String { raw: &'a str, value: String },
-    Plus,     // +
-    Minus,    // -
-    Star,     // *
-    Slash,    // /
-    Percent,  // %
-    Caret,    // ^
-    Not,      // !
-    And,      // &
-    Or,       // |
-    Eq,       // =
-    EqEq,     // ==
-    Gt,       // >
-    Lt,       // <
-    Ge,       // >=
-    Le,       // <=
-    RArrow,   // ->
-    FatArrow, // =>
-    At,       // @
-    LParen,   // (
-    RParen,   // )
-    LBrace,   // [
-    RBrace,   // ]
-    LBracket, // {
-    RBracket, // }
-    Dot,      // .
-    Comma,    // ,
-    Semi,     // ;
-    Colon,    // :
-    Nl,       // newline
-    Eof,      // end-of-file
-    KwDef,    // def
-    KwLet,    // let
-    Unexpected(char),
+    DocComment(&'a str),
+    Plus,       // +
+    PlusEq,     // +=
+    Minus,      // -
+    MinusEq,    // -=
+    Star,       // *
+    Slash,      // /
+    Percent,    // %
+    Caret,      // ^
+    Not,        // !
+    NotEq,      // !=
+    And,        // &
+    AndAnd,     // &&
+    Or,         // |
+    OrOr,       // ||
+    Eq,         // =
+    EqEq,       // ==
+    Gt,         // >
+    Lt,         // <
+    Ge,         // >=
+    Le,         // <=
+    Shl,        // <<
+    Shr,        // >>
+    RArrow,     // ->
+    FatArrow,   // =>
+    At,         // @
+    LParen,     // (
+    RParen,     // )
+    LBrace,     // {
+    RBrace,     // }
+    LBracket,   // [
+    RBracket,   // ]
+    Dot,        // .
+    DotDot,     // ..
+    Comma,      // ,
+    Semi,       // ;
+    Colon,      // :
+    Nl,         // newline
+    Eof,        // end-of-file
+    KwDef,      // def
+    KwLet,      // let
+    KwMut,      // mut
+    KwIf,       // if
+    KwElse,     // else
+    KwWhile,    // while
+    KwFor,      // for
+    KwIn,       // in
+    KwLoop,     // loop
+    KwBreak,    // break
+    KwContinue, // continue
+    KwTrue,     // true
+    KwFalse,    // false
+    KwType,     // type
+    KwMatch,    // match
 }
 
 impl<'a> fmt::Display for Token<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self)
+        let symbol = match self {
+            Token::Ident(name) => return write!(f, "identifier `{}`", name),
+            Token::Int { raw, .. } => return write!(f, "integer literal `{}`", raw),
+            Token::Float { raw, .. } => return write!(f, "float literal `{}`", raw),
+            Token::String { raw, .. } => return write!(f, "string literal {}", raw),
+            Token::DocComment(_) => return write!(f, "doc comment"),
+            Token::Nl => return write!(f, "newline"),
+            Token::Eof => return write!(f, "end of file"),
+            Token::Plus => "+",
+            Token::PlusEq => "+=",
+            Token::Minus => "-",
+            Token::MinusEq => "-=",
+            Token::Star => "*",
+            Token::Slash => "/",
+            Token::Percent => "%",
+            Token::Caret => "^",
+            Token::Not => "!",
+            Token::NotEq => "!=",
+            Token::And => "&",
+            Token::AndAnd => "&&",
+            Token::Or => "|",
+            Token::OrOr => "||",
+            Token::Eq => "=",
+            Token::EqEq => "==",
+            Token::Gt => ">",
+            Token::Lt => "<",
+            Token::Ge => ">=",
+            Token::Le => "<=",
+            Token::Shl => "<<",
+            Token::Shr => ">>",
+            Token::RArrow => "->",
+            Token::FatArrow => "=>",
+            Token::At => "@",
+            Token::LParen => "(",
+            Token::RParen => ")",
+            Token::LBrace => "{",
+            Token::RBrace => "}",
+            Token::LBracket => "[",
+            Token::RBracket => "]",
+            Token::Dot => ".",
+            Token::DotDot => "..",
+            Token::Comma => ",",
+            Token::Semi => ";",
+            Token::Colon => ":",
+            Token::KwDef => "def",
+            Token::KwLet => "let",
+            Token::KwMut => "mut",
+            Token::KwIf => "if",
+            Token::KwElse => "else",
+            Token::KwWhile => "while",
+            Token::KwFor => "for",
+            Token::KwIn => "in",
+            Token::KwLoop => "loop",
+            Token::KwBreak => "break",
+            Token::KwContinue => "continue",
+            Token::KwTrue => "true",
+            Token::KwFalse => "false",
+            Token::KwType => "type",
+            Token::KwMatch => "match",
+        };
+        write!(f, "`{}`", symbol)
     }
 }
@@ -12,3 +12,43 @@ fn test_parse() {
         });
     });
 }
+
+#[test]
+fn test_parse_errors() {
+    use std::fs;
+
+    let mut settings = insta::Settings::clone_current();
+    settings.set_prepend_module_to_snapshot(false);
+    settings.bind(|| {
+        insta::glob!("errors/*.nio", |path| {
+            let input = fs::read_to_string(path).unwrap();
+            let result = nio_parser::parse_with_errors(&input);
+            insta::assert_debug_snapshot!(&result);
+        });
+    });
+}
+
+#[test]
+fn test_expected_terminals() {
+    use nio_parser::diagnostic::Diagnostic;
+
+    let err = nio_parser::parse("let x = )").unwrap_err();
+    let diagnostic = Diagnostic::from(err);
+    let message = &diagnostic.labels[0].message;
+    assert!(message.contains("integer literal"), "{}", message);
+    assert!(message.contains("float literal"), "{}", message);
+    assert!(!message.contains("FLOAT"), "{}", message);
+}
+
+#[test]
+fn test_lex_error_in_block() {
+    let input = "def f(): Int = {\n    let x = $\n    x\n}\nlet y = 1\n";
+    let (program, errors) = nio_parser::parse_with_errors(input);
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+    assert_eq!(program.statements.len(), 2);
+
+    // The lines skipped up to the end of the block are still lexed.
+    let input = "def f(): Int = {\n    let x = $\n    x + #\n}\nlet y = 1\n";
+    let (_, errors) = nio_parser::parse_with_errors(input);
+    assert_eq!(errors.len(), 2, "{:?}", errors);
+}
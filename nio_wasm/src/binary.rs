@@ -18,7 +18,7 @@ struct Emitter<'a> {
 }
 
 impl Emitter<'_> {
-    fn new(writer: &mut dyn Write) -> Emitter {
+    fn new(writer: &mut dyn Write) -> Emitter<'_> {
         Emitter { writer }
     }
 
@@ -19,6 +19,12 @@ macro_rules! bin {
             bin![$e; $($t)*];
         )?
     };
+    ($e:ident; i64($x:expr) $(, $($t:tt)*)?) => {
+        $e.write_i64($x)?;
+        $(
+            bin![$e; $($t)*];
+        )?
+    };
     ($e:ident; f32($x:expr) $(, $($t:tt)*)?) => {
         $e.write_f32($x)?;
         $(
@@ -56,15 +62,42 @@ impl Emitter<'_> {
             // Control Instructions
             Unreachable => bin![0x00],
             Nop => bin![0x01],
-            Block(b, i) => todo!(),
-            Loop(b, i) => todo!(),
-            IfElse(b, i1, i2) => todo!(),
-            Br(l) => todo!(),
-            BrIf(l) => todo!(),
-            BrTable(ls, l) => todo!(),
+            Block(b, i) => {
+                self.write(&[0x02])?;
+                self.emit_block_type(b)?;
+                self.emit_instrs(i)?;
+                bin![0x0b]
+            }
+            Loop(b, i) => {
+                self.write(&[0x03])?;
+                self.emit_block_type(b)?;
+                self.emit_instrs(i)?;
+                bin![0x0b]
+            }
+            IfElse(b, i1, i2) => {
+                self.write(&[0x04])?;
+                self.emit_block_type(b)?;
+                self.emit_instrs(i1)?;
+                if !i2.is_empty() {
+                    self.write(&[0x05])?;
+                    self.emit_instrs(i2)?;
+                }
+                bin![0x0b]
+            }
+            Br(l) => bin![0x0c, u32(l.0)],
+            BrIf(l) => bin![0x0d, u32(l.0)],
+            BrTable(ls, l) => {
+                self.write(&[0x0e])?;
+                self.write_u32(ls.len() as u32)?;
+                for l in ls.iter() {
+                    self.write_u32(l.0)?;
+                }
+                bin![u32(l.0)]
+            }
             Return => bin![0x0f],
-            Call(x) => todo!(),
-            CallIndirect(x) => todo!(),
+            Call(x) => bin![0x10, u32(x.0)],
+            // The table index is always 0 before the reference types proposal.
+            CallIndirect(x) => bin![0x11, u32(x.0), 0x00],
 
             // Parametric Instructions
             Drop => bin![0x1a],
@@ -103,10 +136,11 @@ impl Emitter<'_> {
             I64Store32(m) => bin![0x3e, u32(m.align), u32(m.offset)],
             MemorySize => bin![0x3f, 0x00],
             MemoryGrow => bin![0x40, 0x00],
+            MemoryCopy => bin![0xfc, 10, 0x00, 0x00],
 
             // Numeric Instructions
             I32Const(n) => bin![0x41, i32(*n)],
-            I64Const(n) => todo!(),
+            I64Const(n) => bin![0x42, i64(*n)],
             F32Const(z) => bin![0x43, f32(*z)],
             F64Const(z) => bin![0x44, f64(*z)],
 
@@ -263,11 +297,24 @@ impl Emitter<'_> {
         Ok(())
     }
 
+    fn emit_block_type(&mut self, block_type: &BlockType) -> io::Result<()> {
+        match block_type {
+            BlockType::ValType(None) => self.write(&[0x40]),
+            BlockType::ValType(Some(val_type)) => self.emit_val_type(val_type),
+            BlockType::TypeIdx(x) => self.write_s33(x.0),
+        }
+    }
+
+    fn emit_instrs(&mut self, instrs: &[Instr]) -> io::Result<()> {
+        for instr in instrs.iter() {
+            self.emit_instr(instr)?;
+        }
+        Ok(())
+    }
+
     // Expressions
     pub fn emit_expr(&mut self, expr: &Expr) -> io::Result<()> {
-        for instr in expr.0.iter() {
-            self.emit_instr(&instr)?;
-        }
+        self.emit_instrs(&expr.0)?;
 
         self.write(&[0x0b])?;
 
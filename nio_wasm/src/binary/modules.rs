@@ -29,7 +29,7 @@ impl Emitter<'_> {
     }
 
     // Type Section
-    fn emit_type_sec(&mut self, types: &Vec<FuncType>) -> io::Result<()> {
+    fn emit_type_sec(&mut self, types: &[FuncType]) -> io::Result<()> {
         self.emit_section(1, |e| {
             e.write_u32(types.len() as u32)?;
             for func_type in types.iter() {
@@ -40,12 +40,38 @@ impl Emitter<'_> {
     }
 
     // Import Section
-    fn emit_import_sec(&mut self) -> io::Result<()> {
-        Ok(())
+    fn emit_import_sec(&mut self, imports: &[Import]) -> io::Result<()> {
+        self.emit_section(2, |e| {
+            e.write_u32(imports.len() as u32)?;
+            for import in imports.iter() {
+                e.write_name(&import.module)?;
+                e.write_name(&import.name)?;
+                use ImportDesc::*;
+                match &import.desc {
+                    Func(x) => {
+                        e.write(&[0x00])?;
+                        e.write_u32(x.0)?;
+                    }
+                    Table(tt) => {
+                        e.write(&[0x01])?;
+                        e.emit_table_type(tt)?;
+                    }
+                    Mem(mt) => {
+                        e.write(&[0x02])?;
+                        e.emit_mem_type(mt)?;
+                    }
+                    Global(gt) => {
+                        e.write(&[0x03])?;
+                        e.emit_global_type(gt)?;
+                    }
+                }
+            }
+            Ok(())
+        })
     }
 
     // Function Section
-    fn emit_func_sec(&mut self, funcs: &Vec<Func>) -> io::Result<()> {
+    fn emit_func_sec(&mut self, funcs: &[Func]) -> io::Result<()> {
         self.emit_section(3, |e| {
             e.write_u32(funcs.len() as u32)?;
             for func in funcs.iter() {
@@ -56,22 +82,41 @@ impl Emitter<'_> {
     }
 
     // Table Section
-    fn emit_table_sec(&mut self) -> io::Result<()> {
-        Ok(())
+    fn emit_table_sec(&mut self, tables: &[Table]) -> io::Result<()> {
+        self.emit_section(4, |e| {
+            e.write_u32(tables.len() as u32)?;
+            for table in tables.iter() {
+                e.emit_table_type(&table.r#type)?;
+            }
+            Ok(())
+        })
     }
 
     // Memory Section
-    fn emit_mem_sec(&mut self) -> io::Result<()> {
-        Ok(())
+    fn emit_mem_sec(&mut self, mems: &[Mem]) -> io::Result<()> {
+        self.emit_section(5, |e| {
+            e.write_u32(mems.len() as u32)?;
+            for mem in mems.iter() {
+                e.emit_mem_type(&mem.r#type)?;
+            }
+            Ok(())
+        })
     }
 
     // Global Section
-    fn emit_global_sec(&mut self) -> io::Result<()> {
-        Ok(())
+    fn emit_global_sec(&mut self, globals: &[Global]) -> io::Result<()> {
+        self.emit_section(6, |e| {
+            e.write_u32(globals.len() as u32)?;
+            for global in globals.iter() {
+                e.emit_global_type(&global.r#type)?;
+                e.emit_expr(&global.init)?;
+            }
+            Ok(())
+        })
     }
 
     // Export Section
-    fn emit_export_sec(&mut self, exports: &Vec<Export>) -> io::Result<()> {
+    fn emit_export_sec(&mut self, exports: &[Export]) -> io::Result<()> {
         self.emit_section(7, |e| {
             e.write_u32(exports.len() as u32)?;
             for export in exports.iter() {
@@ -101,17 +146,37 @@ impl Emitter<'_> {
     }
 
     // Start Section
-    fn emit_start_sec(&mut self) -> io::Result<()> {
-        Ok(())
+    fn emit_start_sec(&mut self, start: &Start) -> io::Result<()> {
+        self.emit_section(8, |e| e.write_u32(start.func.0))
     }
 
     // Element Section
-    fn emit_elem_sec(&mut self) -> io::Result<()> {
-        Ok(())
+    fn emit_elem_sec(&mut self, elem: &[Elem]) -> io::Result<()> {
+        self.emit_section(9, |e| {
+            e.write_u32(elem.len() as u32)?;
+            for segment in elem.iter() {
+                // Active segments of function indices, with the short form for table 0.
+                if segment.table.0 == 0 {
+                    e.write_u32(0)?;
+                    e.emit_expr(&segment.offset)?;
+                } else {
+                    e.write_u32(2)?;
+                    e.write_u32(segment.table.0)?;
+                    e.emit_expr(&segment.offset)?;
+                    // `funcref`
+                    e.write(&[0x00])?;
+                }
+                e.write_u32(segment.init.len() as u32)?;
+                for func in segment.init.iter() {
+                    e.write_u32(func.0)?;
+                }
+            }
+            Ok(())
+        })
     }
 
     // Code Section
-    fn emit_code_sec(&mut self, funcs: &Vec<Func>) -> io::Result<()> {
+    fn emit_code_sec(&mut self, funcs: &[Func]) -> io::Result<()> {
         self.emit_section(10, |e| {
             e.write_u32(funcs.len() as u32)?;
             for func in funcs.iter() {
@@ -141,8 +206,41 @@ impl Emitter<'_> {
     }
 
     // Data Section
-    fn emit_data_sec(&mut self) -> io::Result<()> {
-        Ok(())
+    fn emit_data_sec(&mut self, data: &[Data]) -> io::Result<()> {
+        self.emit_section(11, |e| {
+            e.write_u32(data.len() as u32)?;
+            for segment in data.iter() {
+                // Active segments, with the short form for memory 0.
+                if segment.data.0 == 0 {
+                    e.write_u32(0)?;
+                } else {
+                    e.write_u32(2)?;
+                    e.write_u32(segment.data.0)?;
+                }
+                e.emit_expr(&segment.offset)?;
+                e.write_u32(segment.init.len() as u32)?;
+                e.write(&segment.init)?;
+            }
+            Ok(())
+        })
+    }
+
+    // Name Section
+    // https://webassembly.github.io/spec/core/appendix/custom.html#name-section
+    fn emit_name_sec(&mut self, func_names: &[(FuncIdx, Name)]) -> io::Result<()> {
+        self.emit_section(0, |e| {
+            e.write_name(&Name("name".to_string()))?;
+            // The function names subsection.
+            e.write(&[1])?;
+            e.write_sized(|e| {
+                e.write_u32(func_names.len() as u32)?;
+                for (idx, name) in func_names.iter() {
+                    e.write_u32(idx.0)?;
+                    e.write_name(name)?;
+                }
+                Ok(())
+            })
+        })
     }
 
     // Modules
@@ -158,21 +256,61 @@ impl Emitter<'_> {
             self.emit_type_sec(&module.types)?;
         }
 
+        // Import Section
+        if !module.imports.is_empty() {
+            self.emit_import_sec(&module.imports)?;
+        }
+
         // Function Section
         if !module.funcs.is_empty() {
             self.emit_func_sec(&module.funcs)?;
         }
 
+        // Table Section
+        if !module.tables.is_empty() {
+            self.emit_table_sec(&module.tables)?;
+        }
+
+        // Memory Section
+        if !module.mems.is_empty() {
+            self.emit_mem_sec(&module.mems)?;
+        }
+
+        // Global Section
+        if !module.globals.is_empty() {
+            self.emit_global_sec(&module.globals)?;
+        }
+
         // Export Section
         if !module.exports.is_empty() {
             self.emit_export_sec(&module.exports)?;
         }
 
+        // Start Section
+        if let Some(start) = &module.start {
+            self.emit_start_sec(start)?;
+        }
+
+        // Element Section
+        if !module.elem.is_empty() {
+            self.emit_elem_sec(&module.elem)?;
+        }
+
         // Code Section
         if !module.funcs.is_empty() {
             self.emit_code_sec(&module.funcs)?;
         }
 
+        // Data Section
+        if !module.data.is_empty() {
+            self.emit_data_sec(&module.data)?;
+        }
+
+        // Name Section
+        if !module.func_names.is_empty() {
+            self.emit_name_sec(&module.func_names)?;
+        }
+
         Ok(())
     }
 }
@@ -190,4 +328,33 @@ mod tests {
         assert!(result.is_ok());
         assert_eq!(buffer, &[0, 97, 115, 109, 1, 0, 0, 0]);
     }
+
+    #[test]
+    fn test_emit_data_sec() {
+        let mut module = Module::new();
+        module.data.push(Data {
+            data: MemIdx(0),
+            offset: Expr(vec![Instr::I32Const(8)]),
+            init: b"hi".to_vec(),
+        });
+        let mut buffer = Vec::new();
+        let mut emitter = Emitter::new(&mut buffer);
+        let result = emitter.emit_module(&module);
+        assert!(result.is_ok());
+        assert_eq!(buffer[8..], [11, 8, 1, 0, 0x41, 8, 0x0b, 2, b'h', b'i']);
+    }
+
+    #[test]
+    fn test_emit_name_sec() {
+        let mut module = Module::new();
+        module.func_names.push((FuncIdx(3), Name("f".to_string())));
+        let mut buffer = Vec::new();
+        let mut emitter = Emitter::new(&mut buffer);
+        let result = emitter.emit_module(&module);
+        assert!(result.is_ok());
+        assert_eq!(
+            buffer[8..],
+            [0, 11, 4, b'n', b'a', b'm', b'e', 1, 4, 1, 3, 1, b'f']
+        );
+    }
 }
@@ -31,4 +31,43 @@ impl Emitter<'_> {
         self.emit_result_type(&func_type.1)?;
         Ok(())
     }
+
+    // Limits
+    pub fn emit_limits(&mut self, limits: &Limits) -> io::Result<()> {
+        match limits.max {
+            None => {
+                self.write(&[0x00])?;
+                self.write_u32(limits.min)?;
+            }
+            Some(max) => {
+                self.write(&[0x01])?;
+                self.write_u32(limits.min)?;
+                self.write_u32(max)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Memory Types
+    pub fn emit_mem_type(&mut self, mem_type: &MemType) -> io::Result<()> {
+        self.emit_limits(&mem_type.0)
+    }
+
+    // Table Types
+    pub fn emit_table_type(&mut self, table_type: &TableType) -> io::Result<()> {
+        // `funcref`, the only element type.
+        self.write(&[0x70])?;
+        self.emit_limits(&table_type.0)?;
+        Ok(())
+    }
+
+    // Global Types
+    pub fn emit_global_type(&mut self, global_type: &GlobalType) -> io::Result<()> {
+        self.emit_val_type(&global_type.1)?;
+        self.write(&[match global_type.0 {
+            Mut::Const => 0x00,
+            Mut::Var => 0x01,
+        }])?;
+        Ok(())
+    }
 }
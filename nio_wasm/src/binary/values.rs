@@ -23,10 +23,10 @@ impl Emitter<'_> {
 
     pub fn write_s32(&mut self, mut value: i32) -> io::Result<()> {
         loop {
-            if 0 <= value && value < (1 << 6) {
+            if (0..(1 << 6)).contains(&value) {
                 self.write(&[value as u8])?;
                 break;
-            } else if (-1 << 6) <= value && value < 0 {
+            } else if ((-1 << 6)..0).contains(&value) {
                 self.write(&[value as u8 & !(1 << 7)])?;
                 break;
             } else {
@@ -37,12 +37,48 @@ impl Emitter<'_> {
         Ok(())
     }
 
+    pub fn write_s64(&mut self, mut value: i64) -> io::Result<()> {
+        loop {
+            if (0..(1 << 6)).contains(&value) {
+                self.write(&[value as u8])?;
+                break;
+            } else if ((-1 << 6)..0).contains(&value) {
+                self.write(&[value as u8 & !(1 << 7)])?;
+                break;
+            } else {
+                self.write(&[value as u8 | (1 << 7)])?;
+                value >>= 7;
+            }
+        }
+        Ok(())
+    }
+
+    /// Block types encode type indices as non-negative 33-bit signed integers,
+    /// so that they can't be confused with value types.
+    pub fn write_s33(&mut self, value: u32) -> io::Result<()> {
+        let mut value = value as u64;
+        loop {
+            if value < (1 << 6) {
+                self.write(&[value as u8])?;
+                break;
+            } else {
+                self.write(&[value as u8 | (1 << 7)])?;
+                value >>= 7;
+            }
+        }
+        Ok(())
+    }
+
     // Uninterpreted Integers
 
     pub fn write_i32(&mut self, value: u32) -> io::Result<()> {
         self.write_s32(value as i32)
     }
 
+    pub fn write_i64(&mut self, value: u64) -> io::Result<()> {
+        self.write_s64(value as i64)
+    }
+
     // Floating-Point
 
     pub fn write_f32(&mut self, value: f32) -> io::Result<()> {
@@ -84,4 +120,27 @@ mod tests {
         assert!(result.is_ok());
         assert_eq!(buffer, &[0xc0, 0xbb, 0x78]);
     }
+
+    #[test]
+    fn test_write_s64() {
+        let mut buffer = Vec::new();
+        let mut emitter = Emitter::new(&mut buffer);
+        emitter.write_s64(-123456).unwrap();
+        emitter.write_s64(i64::MIN).unwrap();
+        assert_eq!(
+            buffer,
+            &[
+                0xc0, 0xbb, 0x78, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x7f
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_s33() {
+        let mut buffer = Vec::new();
+        let mut emitter = Emitter::new(&mut buffer);
+        emitter.write_s33(63).unwrap();
+        emitter.write_s33(64).unwrap();
+        assert_eq!(buffer, &[0x3f, 0xc0, 0x00]);
+    }
 }
@@ -204,6 +204,7 @@ pub enum Instr {
 
     MemorySize,
     MemoryGrow,
+    MemoryCopy,
 
     // Control Instructions
     Nop,
@@ -15,22 +15,31 @@ pub struct Module {
     pub start: Option<Start>,
     pub imports: Vec<Import>,
     pub exports: Vec<Export>,
+    /// The names of functions for the custom `name` section, by increasing index.
+    pub func_names: Vec<(FuncIdx, Name)>,
 }
 
 // Indices
 
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct TypeIdx(pub u32);
 
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct FuncIdx(pub u32);
 
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct TableIdx(pub u32);
 
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct MemIdx(pub u32);
 
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct GlobalIdx(pub u32);
 
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct LocalIdx(pub u32);
 
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct LabelIdx(pub u32);
 
 // Functions
@@ -126,6 +135,13 @@ impl Module {
             start: None,
             imports: Vec::new(),
             exports: Vec::new(),
+            func_names: Vec::new(),
         }
     }
 }
+
+impl Default for Module {
+    fn default() -> Self {
+        Self::new()
+    }
+}
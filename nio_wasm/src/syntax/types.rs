@@ -2,7 +2,7 @@
 
 // Value Types
 
-#[derive(PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ValType {
     I32,
     I64,
@@ -12,10 +12,12 @@ pub enum ValType {
 
 // Result Types
 
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct ResultType(pub Vec<ValType>);
 
 // Function Types
 
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct FuncType(pub ResultType, pub ResultType);
 
 // Limits
@@ -5,65 +5,221 @@ impl From<ast::Program> for ir::Program {
     fn from(p: ast::Program) -> Self {
         ir::Program {
             statements: p.statements.into_iter().map(ir::Stmt::from).collect(),
+            lambdas: Vec::new(),
         }
     }
 }
 
 impl From<ast::Stmt> for ir::Stmt {
     fn from(s: ast::Stmt) -> Self {
-        match s {
-            ast::Stmt::Def {
+        let kind = match s.kind {
+            ast::StmtKind::Def {
+                doc: _,
                 annotations,
                 name,
                 params,
                 return_type,
                 body,
-            } => ir::Stmt::Def {
+            } => ir::StmtKind::Def {
                 annotations: annotations.into_iter().map(ir::Expr::from).collect(),
                 name,
                 params: params
                     .into_iter()
-                    .map(|(param_name, param_type)| (param_name, ir::Type::Unresolved(param_type)))
+                    .map(|(param_name, param_type)| {
+                        (param_name, ir::Type::Unresolved(Box::new(param_type)))
+                    })
                     .collect(),
-                return_type: ir::Type::Unresolved(return_type),
-                body: Box::new(ir::Expr::from(*body)),
+                return_type: ir::Type::Unresolved(Box::new(return_type)),
+                body: body.map(|body| Box::new(ir::Expr::from(*body))),
             },
-            ast::Stmt::Let { name, type_, value } => ir::Stmt::Let {
+            ast::StmtKind::Let {
+                name,
+                mutable,
+                type_,
+                value,
+            } => ir::StmtKind::Let {
                 name,
+                mutable,
                 type_: match type_ {
-                    Some(type_) => ir::Type::Unresolved(type_),
+                    Some(type_) => ir::Type::Unresolved(Box::new(type_)),
                     None => ir::Type::Untyped,
                 },
                 value: Box::new(ir::Expr::from(*value)),
             },
-            ast::Stmt::Expr(e) => ir::Stmt::Expr(ir::Expr::from(e)),
-        }
+            ast::StmtKind::LetTuple {
+                names,
+                type_,
+                value,
+            } => ir::StmtKind::LetTuple {
+                names,
+                type_: match type_ {
+                    Some(type_) => ir::Type::Unresolved(Box::new(type_)),
+                    None => ir::Type::Untyped,
+                },
+                value: Box::new(ir::Expr::from(*value)),
+            },
+            ast::StmtKind::Type { doc: _, name, def } => ir::StmtKind::Type {
+                name,
+                def: match def {
+                    ast::TypeDef::Record(fields) => ir::TypeDef::Record(
+                        fields
+                            .into_iter()
+                            .map(|(field_name, field_type)| {
+                                (field_name, ir::Type::Unresolved(Box::new(field_type)))
+                            })
+                            .collect(),
+                    ),
+                    ast::TypeDef::Enum(variants) => ir::TypeDef::Enum(
+                        variants
+                            .into_iter()
+                            .map(|(variant_name, field_types)| {
+                                let field_types = field_types
+                                    .into_iter()
+                                    .map(|field_type| ir::Type::Unresolved(Box::new(field_type)))
+                                    .collect();
+                                (variant_name, field_types)
+                            })
+                            .collect(),
+                    ),
+                },
+            },
+            ast::StmtKind::Expr(e) => ir::StmtKind::Expr(ir::Expr::from(e)),
+            ast::StmtKind::Error => unreachable!("programs with syntax errors are not lowered"),
+        };
+        ir::Stmt { kind, span: s.span }
     }
 }
 
 impl From<ast::Expr> for ir::Expr {
     fn from(e: ast::Expr) -> Self {
-        match e {
-            ast::Expr::BinOp { op, lhs, rhs } => ir::Expr::BinOp {
+        let kind = match e.kind {
+            ast::ExprKind::BinOp { op, lhs, rhs } => ir::ExprKind::BinOp {
                 op: ir::BinOp::from(op),
                 lhs: Box::new(ir::Expr::from(*lhs)),
                 rhs: Box::new(ir::Expr::from(*rhs)),
             },
-            ast::Expr::Assign { lhs, rhs } => ir::Expr::Assign {
+            ast::ExprKind::UnOp { op, operand } => ir::ExprKind::UnOp {
+                op: ir::UnOp::from(op),
+                operand: Box::new(ir::Expr::from(*operand)),
+            },
+            ast::ExprKind::Assign { lhs, rhs } => ir::ExprKind::Assign {
                 lhs,
                 rhs: Box::new(ir::Expr::from(*rhs)),
             },
-            ast::Expr::Lambda { params, body } => ir::Expr::Lambda {
-                params,
+            ast::ExprKind::Lambda { params, body } => ir::ExprKind::Lambda {
+                params: params
+                    .into_iter()
+                    .map(|param| (param, ir::Type::Untyped))
+                    .collect(),
                 body: Box::new(ir::Expr::from(*body)),
             },
-            ast::Expr::Call { callee, args } => ir::Expr::Call {
+            ast::ExprKind::Call { callee, args } => ir::ExprKind::Call {
                 callee: Box::new((*callee).into()),
                 args: args.into_iter().map(ir::Expr::from).collect(),
             },
-            ast::Expr::Ident(i) => ir::Expr::Ident(i),
-            ast::Expr::IntLit(i) => ir::Expr::IntLit(i),
-            ast::Expr::StringLit(s) => ir::Expr::StringLit(s),
+            ast::ExprKind::Record { fields, base } => ir::ExprKind::Record {
+                fields: fields
+                    .into_iter()
+                    .map(|(name, value)| (name, ir::Expr::from(value)))
+                    .collect(),
+                base: base.map(|base| Box::new(ir::Expr::from(*base))),
+            },
+            ast::ExprKind::Field { expr, name } => ir::ExprKind::Field {
+                expr: Box::new(ir::Expr::from(*expr)),
+                name,
+            },
+            ast::ExprKind::Tuple(elements) => {
+                ir::ExprKind::Tuple(elements.into_iter().map(ir::Expr::from).collect())
+            }
+            ast::ExprKind::Array(elements) => {
+                ir::ExprKind::Array(elements.into_iter().map(ir::Expr::from).collect())
+            }
+            ast::ExprKind::Index { expr, index } => ir::ExprKind::Index {
+                expr: Box::new(ir::Expr::from(*expr)),
+                index: Box::new(ir::Expr::from(*index)),
+            },
+            ast::ExprKind::If { cond, then, else_ } => ir::ExprKind::If {
+                cond: Box::new(ir::Expr::from(*cond)),
+                then: Box::new(ir::Expr::from(*then)),
+                else_: Box::new(ir::Expr::from(*else_)),
+            },
+            ast::ExprKind::Match { scrutinee, arms } => ir::ExprKind::Match {
+                scrutinee: Box::new(ir::Expr::from(*scrutinee)),
+                arms: arms.into_iter().map(ir::MatchArm::from).collect(),
+            },
+            ast::ExprKind::While { cond, body } => ir::ExprKind::While {
+                cond: Box::new(ir::Expr::from(*cond)),
+                body: Box::new(ir::Expr::from(*body)),
+            },
+            ast::ExprKind::For {
+                var,
+                start,
+                end,
+                body,
+            } => ir::ExprKind::For {
+                var,
+                start: Box::new(ir::Expr::from(*start)),
+                end: Box::new(ir::Expr::from(*end)),
+                body: Box::new(ir::Expr::from(*body)),
+            },
+            ast::ExprKind::ForEach { var, array, body } => ir::ExprKind::ForEach {
+                var,
+                array: Box::new(ir::Expr::from(*array)),
+                body: Box::new(ir::Expr::from(*body)),
+            },
+            ast::ExprKind::Loop { body } => ir::ExprKind::Loop {
+                body: Box::new(ir::Expr::from(*body)),
+            },
+            ast::ExprKind::Break(value) => {
+                ir::ExprKind::Break(value.map(|value| Box::new(ir::Expr::from(*value))))
+            }
+            ast::ExprKind::Continue => ir::ExprKind::Continue,
+            ast::ExprKind::Block { stmts, value } => ir::ExprKind::Block {
+                stmts: stmts.into_iter().map(ir::Stmt::from).collect(),
+                value: value.map(|value| Box::new(ir::Expr::from(*value))),
+            },
+            ast::ExprKind::Ident(i) => ir::ExprKind::Ident(i),
+            ast::ExprKind::IntLit(i) => ir::ExprKind::IntLit(i),
+            ast::ExprKind::FloatLit(f) => ir::ExprKind::FloatLit(f),
+            ast::ExprKind::BoolLit(b) => ir::ExprKind::BoolLit(b),
+            ast::ExprKind::StringLit(s) => ir::ExprKind::StringLit(s),
+            ast::ExprKind::UnitLit => ir::ExprKind::UnitLit,
+            ast::ExprKind::Error => unreachable!("programs with syntax errors are not lowered"),
+        };
+        ir::Expr {
+            kind,
+            span: e.span,
+            type_: ir::Type::Untyped,
+        }
+    }
+}
+
+impl From<ast::MatchArm> for ir::MatchArm {
+    fn from(a: ast::MatchArm) -> Self {
+        ir::MatchArm {
+            pattern: ir::Pattern::from(a.pattern),
+            body: ir::Expr::from(a.body),
+        }
+    }
+}
+
+impl From<ast::Pattern> for ir::Pattern {
+    fn from(p: ast::Pattern) -> Self {
+        let kind = match p.kind {
+            ast::PatternKind::Wildcard => ir::PatternKind::Wildcard,
+            ast::PatternKind::Ident(name) => ir::PatternKind::Binding(name),
+            ast::PatternKind::Literal(lit) => {
+                ir::PatternKind::Literal(Box::new(ir::Expr::from(*lit)))
+            }
+            ast::PatternKind::Variant { name, args } => ir::PatternKind::Variant {
+                name,
+                args: args.into_iter().map(ir::Pattern::from).collect(),
+            },
+        };
+        ir::Pattern {
+            kind,
+            span: p.span,
+            type_: ir::Type::Untyped,
         }
     }
 }
@@ -74,6 +230,30 @@ impl From<ast::BinOp> for ir::BinOp {
             ast::BinOp::Add => ir::BinOp::Add,
             ast::BinOp::Sub => ir::BinOp::Sub,
             ast::BinOp::Mul => ir::BinOp::Mul,
+            ast::BinOp::Div => ir::BinOp::Div,
+            ast::BinOp::Rem => ir::BinOp::Rem,
+            ast::BinOp::BitAnd => ir::BinOp::BitAnd,
+            ast::BinOp::BitOr => ir::BinOp::BitOr,
+            ast::BinOp::BitXor => ir::BinOp::BitXor,
+            ast::BinOp::Shl => ir::BinOp::Shl,
+            ast::BinOp::Shr => ir::BinOp::Shr,
+            ast::BinOp::Eq => ir::BinOp::Eq,
+            ast::BinOp::Ne => ir::BinOp::Ne,
+            ast::BinOp::Lt => ir::BinOp::Lt,
+            ast::BinOp::Le => ir::BinOp::Le,
+            ast::BinOp::Gt => ir::BinOp::Gt,
+            ast::BinOp::Ge => ir::BinOp::Ge,
+            ast::BinOp::And => ir::BinOp::And,
+            ast::BinOp::Or => ir::BinOp::Or,
+        }
+    }
+}
+
+impl From<ast::UnOp> for ir::UnOp {
+    fn from(o: ast::UnOp) -> Self {
+        match o {
+            ast::UnOp::Neg => ir::UnOp::Neg,
+            ast::UnOp::Not => ir::UnOp::Not,
         }
     }
 }
@@ -0,0 +1,716 @@
+//! Turns the lambdas of a checked program into top-level functions, so that codegen can put them
+//! in a function table and call them with `call_indirect`.
+//!
+//! A Wasm function has a single type, so generic `let`s are first copied for each type they are
+//! used with, and the type variables left unconstrained default to `Unit`.
+
+use crate::ir::*;
+use std::collections::HashSet;
+
+pub fn convert(program: &mut Program) {
+    specialize_stmts(&mut program.statements, None);
+    for stmt in program.statements.iter_mut() {
+        for_each_type_in_stmt(stmt, &mut default_vars);
+    }
+
+    let mut converter = Converter {
+        lambdas: Vec::new(),
+        functions: HashSet::new(),
+        globals: Vec::new(),
+    };
+    for stmt in program.statements.iter() {
+        if let StmtKind::Def { name, .. } = &stmt.kind {
+            converter.functions.insert(name.clone());
+        }
+    }
+    for stmt in program.statements.iter_mut() {
+        converter.convert_stmt(stmt);
+    }
+    program.lambdas = converter.lambdas;
+}
+
+/// Replaces each generic `let` in `stmts` by a copy for each type that the statements after it and
+/// `value` use it with. Unused generic `let`s are removed.
+fn specialize_stmts(stmts: &mut Vec<Stmt>, mut value: Option<&mut Expr>) {
+    // Nested `let`s go first, so that their uses of the outer ones are specialized too.
+    for stmt in stmts.iter_mut() {
+        match &mut stmt.kind {
+            StmtKind::Def { body, .. } => {
+                if let Some(body) = body {
+                    specialize_expr(body);
+                }
+            }
+            StmtKind::Let { value, .. } | StmtKind::LetTuple { value, .. } => {
+                specialize_expr(value)
+            }
+            StmtKind::Type { .. } => {}
+            StmtKind::Expr(expr) => specialize_expr(expr),
+        }
+    }
+    if let Some(value) = value.as_deref_mut() {
+        specialize_expr(value);
+    }
+
+    // Likewise, later `let`s go first, since their copies may use the earlier ones.
+    for i in (0..stmts.len()).rev() {
+        let StmtKind::Let {
+            name,
+            mutable,
+            type_,
+            value: let_value,
+        } = &stmts[i].kind
+        else {
+            continue;
+        };
+        if *mutable || !is_generic(let_value, type_) {
+            continue;
+        }
+        let name = name.clone();
+        let generic_type = type_.clone();
+
+        let mut instances: Vec<Type> = Vec::new();
+        for_each_use_in_stmts(
+            &mut stmts[i + 1..],
+            value.as_deref_mut(),
+            &name,
+            &mut |use_| {
+                let k = match instances.iter().position(|type_| *type_ == use_.type_) {
+                    Some(k) => k,
+                    None => {
+                        instances.push(use_.type_.clone());
+                        instances.len() - 1
+                    }
+                };
+                use_.kind = ExprKind::Ident(instance_name(&name, k));
+            },
+        );
+
+        let generic = stmts.remove(i);
+        let copies: Vec<_> = instances
+            .iter()
+            .enumerate()
+            .map(|(k, instance)| {
+                let mut substitution = Vec::new();
+                match_type(&generic_type, instance, &mut substitution);
+                let mut copy = generic.clone();
+                if let StmtKind::Let { name, .. } = &mut copy.kind {
+                    *name = instance_name(name, k);
+                }
+                for_each_type_in_stmt(&mut copy, &mut |type_| {
+                    *type_ = substitute(type_, &substitution);
+                });
+                copy
+            })
+            .collect();
+        stmts.splice(i..i, copies);
+    }
+}
+
+fn specialize_expr(expr: &mut Expr) {
+    match &mut expr.kind {
+        ExprKind::Block { stmts, value } => specialize_stmts(stmts, value.as_deref_mut()),
+        _ => for_each_child(expr, &mut specialize_expr),
+    }
+}
+
+/// Whether the `let` of `value` was made generic by the type checker.
+fn is_generic(value: &Expr, type_: &Type) -> bool {
+    matches!(value.kind, ExprKind::Lambda { .. } | ExprKind::Ident(_)) && has_vars(type_)
+}
+
+/// The name of the copy of a generic `let` for the `k`th type it is used with. Identifiers can't
+/// contain `#`, so it can't clash with a variable of the program.
+fn instance_name(name: &str, k: usize) -> String {
+    format!("{}#{}", name, k)
+}
+
+fn has_vars(type_: &Type) -> bool {
+    match type_ {
+        Type::Var(_) => true,
+        Type::Func(params, return_type) => params.iter().any(has_vars) || has_vars(return_type),
+        Type::Tuple(types) => types.iter().any(has_vars),
+        Type::Array(elem) => has_vars(elem),
+        _ => false,
+    }
+}
+
+/// Finds the types that the variables of `generic` stand for in `instance`.
+fn match_type(generic: &Type, instance: &Type, substitution: &mut Vec<(TypeVar, Type)>) {
+    match (generic, instance) {
+        (Type::Var(var), _) if !substitution.iter().any(|(old, _)| old == var) => {
+            substitution.push((*var, instance.clone()));
+        }
+        (Type::Func(params, return_type), Type::Func(instance_params, instance_return_type)) => {
+            for (param, instance_param) in params.iter().zip(instance_params.iter()) {
+                match_type(param, instance_param, substitution);
+            }
+            match_type(return_type, instance_return_type, substitution);
+        }
+        (Type::Tuple(types), Type::Tuple(instance_types)) => {
+            for (type_, instance_type) in types.iter().zip(instance_types.iter()) {
+                match_type(type_, instance_type, substitution);
+            }
+        }
+        (Type::Array(elem), Type::Array(instance_elem)) => {
+            match_type(elem, instance_elem, substitution);
+        }
+        _ => {}
+    }
+}
+
+fn substitute(type_: &Type, substitution: &[(TypeVar, Type)]) -> Type {
+    match type_ {
+        Type::Var(var) => match substitution.iter().find(|(old, _)| old == var) {
+            Some((_, new)) => new.clone(),
+            None => type_.clone(),
+        },
+        Type::Func(params, return_type) => Type::Func(
+            params
+                .iter()
+                .map(|param| substitute(param, substitution))
+                .collect(),
+            Box::new(substitute(return_type, substitution)),
+        ),
+        Type::Tuple(types) => Type::Tuple(
+            types
+                .iter()
+                .map(|type_| substitute(type_, substitution))
+                .collect(),
+        ),
+        Type::Array(elem) => Type::Array(Box::new(substitute(elem, substitution))),
+        _ => type_.clone(),
+    }
+}
+
+/// Replaces the type variables that nothing constrained, like the type of `x` in
+/// `(|x| 1)(loop {})`, by `Unit`.
+fn default_vars(type_: &mut Type) {
+    match type_ {
+        Type::Var(_) => *type_ = Type::Unit,
+        Type::Func(params, return_type) => {
+            params.iter_mut().for_each(default_vars);
+            default_vars(return_type);
+        }
+        Type::Tuple(types) => types.iter_mut().for_each(default_vars),
+        Type::Array(elem) => default_vars(elem),
+        _ => {}
+    }
+}
+
+/// Calls `f` on the uses of the variable `name` in `expr` that refer to the variable in scope
+/// around `expr`.
+fn for_each_use(expr: &mut Expr, name: &str, f: &mut dyn FnMut(&mut Expr)) {
+    if matches!(&expr.kind, ExprKind::Ident(ident) if ident == name) {
+        f(expr);
+        return;
+    }
+    match &mut expr.kind {
+        ExprKind::Lambda { params, body } => {
+            if !params.iter().any(|(param, _)| param == name) {
+                for_each_use(body, name, f);
+            }
+        }
+        ExprKind::For {
+            var,
+            start,
+            end,
+            body,
+        } => {
+            for_each_use(start, name, f);
+            for_each_use(end, name, f);
+            if var != name {
+                for_each_use(body, name, f);
+            }
+        }
+        ExprKind::ForEach { var, array, body } => {
+            for_each_use(array, name, f);
+            if var != name {
+                for_each_use(body, name, f);
+            }
+        }
+        ExprKind::Match { scrutinee, arms } => {
+            for_each_use(scrutinee, name, f);
+            for arm in arms.iter_mut() {
+                if !binds(&arm.pattern, name) {
+                    for_each_use(&mut arm.body, name, f);
+                }
+            }
+        }
+        ExprKind::Block { stmts, value } => {
+            for_each_use_in_stmts(stmts, value.as_deref_mut(), name, f);
+        }
+        _ => for_each_child(expr, &mut |child| for_each_use(child, name, f)),
+    }
+}
+
+/// Whether `pattern` binds a variable named `name`, which shadows the one outside its arm.
+fn binds(pattern: &Pattern, name: &str) -> bool {
+    pattern
+        .bindings()
+        .into_iter()
+        .any(|(binding, _, _)| binding == name)
+}
+
+/// Like `for_each_use`, for a sequence of statements followed by `value`, stopping where a `let`
+/// shadows `name`.
+fn for_each_use_in_stmts(
+    stmts: &mut [Stmt],
+    value: Option<&mut Expr>,
+    name: &str,
+    f: &mut dyn FnMut(&mut Expr),
+) {
+    for stmt in stmts.iter_mut() {
+        match &mut stmt.kind {
+            StmtKind::Def { params, body, .. } => {
+                if let Some(body) = body
+                    && !params.iter().any(|(param, _)| param == name)
+                {
+                    for_each_use(body, name, f);
+                }
+            }
+            StmtKind::Let {
+                name: let_name,
+                value,
+                ..
+            } => {
+                for_each_use(value, name, f);
+                if let_name == name {
+                    return;
+                }
+            }
+            StmtKind::LetTuple { names, value, .. } => {
+                for_each_use(value, name, f);
+                if names.iter().any(|let_name| let_name == name) {
+                    return;
+                }
+            }
+            StmtKind::Type { .. } => {}
+            StmtKind::Expr(expr) => for_each_use(expr, name, f),
+        }
+    }
+    if let Some(value) = value {
+        for_each_use(value, name, f);
+    }
+}
+
+/// Calls `f` on every type in `stmt`, including those of its expressions.
+fn for_each_type_in_stmt(stmt: &mut Stmt, f: &mut dyn FnMut(&mut Type)) {
+    match &mut stmt.kind {
+        StmtKind::Def {
+            params,
+            return_type,
+            body,
+            ..
+        } => {
+            for (_, type_) in params.iter_mut() {
+                f(type_);
+            }
+            f(return_type);
+            if let Some(body) = body {
+                for_each_type(body, f);
+            }
+        }
+        StmtKind::Let { type_, value, .. } | StmtKind::LetTuple { type_, value, .. } => {
+            f(type_);
+            for_each_type(value, f);
+        }
+        StmtKind::Type {
+            def: TypeDef::Record(fields),
+            ..
+        } => {
+            for (_, type_) in fields.iter_mut() {
+                f(type_);
+            }
+        }
+        StmtKind::Type {
+            def: TypeDef::Enum(variants),
+            ..
+        } => {
+            for (_, field_types) in variants.iter_mut() {
+                field_types.iter_mut().for_each(&mut *f);
+            }
+        }
+        StmtKind::Expr(expr) => for_each_type(expr, f),
+    }
+}
+
+fn for_each_type(expr: &mut Expr, f: &mut dyn FnMut(&mut Type)) {
+    f(&mut expr.type_);
+    match &mut expr.kind {
+        ExprKind::Lambda { params, body } => {
+            for (_, type_) in params.iter_mut() {
+                f(type_);
+            }
+            for_each_type(body, f);
+        }
+        ExprKind::Block { stmts, value } => {
+            for stmt in stmts.iter_mut() {
+                for_each_type_in_stmt(stmt, f);
+            }
+            if let Some(value) = value {
+                for_each_type(value, f);
+            }
+        }
+        ExprKind::Match { scrutinee, arms } => {
+            for_each_type(scrutinee, f);
+            for arm in arms.iter_mut() {
+                for_each_type_in_pattern(&mut arm.pattern, f);
+                for_each_type(&mut arm.body, f);
+            }
+        }
+        _ => for_each_child(expr, &mut |child| for_each_type(child, f)),
+    }
+}
+
+fn for_each_type_in_pattern(pattern: &mut Pattern, f: &mut dyn FnMut(&mut Type)) {
+    f(&mut pattern.type_);
+    match &mut pattern.kind {
+        PatternKind::Literal(lit) => for_each_type(lit, f),
+        PatternKind::Variant { args, .. } => {
+            for arg in args.iter_mut() {
+                for_each_type_in_pattern(arg, f);
+            }
+        }
+        PatternKind::Wildcard | PatternKind::Binding(_) => {}
+    }
+}
+
+/// Calls `f` on the expressions directly inside `expr`, including the values of the statements
+/// of a block.
+fn for_each_child(expr: &mut Expr, f: &mut dyn FnMut(&mut Expr)) {
+    match &mut expr.kind {
+        ExprKind::BinOp { lhs, rhs, .. } => {
+            f(lhs);
+            f(rhs);
+        }
+        ExprKind::UnOp { operand, .. } => f(operand),
+        ExprKind::Assign { rhs, .. } => f(rhs),
+        ExprKind::Lambda { body, .. } => f(body),
+        ExprKind::Call { callee, args } => {
+            f(callee);
+            args.iter_mut().for_each(f);
+        }
+        ExprKind::Record { fields, base } => {
+            for (_, value) in fields.iter_mut() {
+                f(value);
+            }
+            if let Some(base) = base {
+                f(base);
+            }
+        }
+        ExprKind::Field { expr, .. } => f(expr),
+        ExprKind::Tuple(elements) | ExprKind::Array(elements) => elements.iter_mut().for_each(f),
+        ExprKind::Index { expr, index } => {
+            f(expr);
+            f(index);
+        }
+        ExprKind::Construct { args, .. } => args.iter_mut().for_each(f),
+        ExprKind::Match { scrutinee, arms } => {
+            f(scrutinee);
+            for arm in arms.iter_mut() {
+                f(&mut arm.body);
+            }
+        }
+        ExprKind::If { cond, then, else_ } => {
+            f(cond);
+            f(then);
+            f(else_);
+        }
+        ExprKind::While { cond, body } => {
+            f(cond);
+            f(body);
+        }
+        ExprKind::For {
+            start, end, body, ..
+        } => {
+            f(start);
+            f(end);
+            f(body);
+        }
+        ExprKind::ForEach { array, body, .. } => {
+            f(array);
+            f(body);
+        }
+        ExprKind::Loop { body } => f(body),
+        ExprKind::Break(value) => {
+            if let Some(value) = value {
+                f(value);
+            }
+        }
+        ExprKind::Block { stmts, value } => {
+            for stmt in stmts.iter_mut() {
+                match &mut stmt.kind {
+                    StmtKind::Let { value, .. } | StmtKind::LetTuple { value, .. } => f(value),
+                    StmtKind::Expr(expr) => f(expr),
+                    StmtKind::Def { .. } | StmtKind::Type { .. } => {
+                        unreachable!("blocks can't contain definitions")
+                    }
+                }
+            }
+            if let Some(value) = value {
+                f(value);
+            }
+        }
+        ExprKind::Continue
+        | ExprKind::Closure(_)
+        | ExprKind::Ident(_)
+        | ExprKind::IntLit(_)
+        | ExprKind::FloatLit(_)
+        | ExprKind::BoolLit(_)
+        | ExprKind::StringLit(_)
+        | ExprKind::UnitLit => {}
+    }
+}
+
+/// Adds the variables that `expr` uses without declaring them to `free`, in order of first use.
+fn free_vars(expr: &mut Expr, bound: &mut Vec<String>, free: &mut Vec<String>) {
+    let outer_scope = bound.len();
+    match &mut expr.kind {
+        ExprKind::Ident(name) => {
+            if !bound.contains(name) && !free.contains(name) {
+                free.push(name.clone());
+            }
+        }
+        ExprKind::Assign { lhs, rhs } => {
+            if !bound.contains(lhs) && !free.contains(lhs) {
+                free.push(lhs.clone());
+            }
+            free_vars(rhs, bound, free);
+        }
+        ExprKind::Lambda { params, body } => {
+            bound.extend(params.iter().map(|(name, _)| name.clone()));
+            free_vars(body, bound, free);
+        }
+        ExprKind::For {
+            var,
+            start,
+            end,
+            body,
+        } => {
+            free_vars(start, bound, free);
+            free_vars(end, bound, free);
+            bound.push(var.clone());
+            free_vars(body, bound, free);
+        }
+        ExprKind::ForEach { var, array, body } => {
+            free_vars(array, bound, free);
+            bound.push(var.clone());
+            free_vars(body, bound, free);
+        }
+        ExprKind::Match { scrutinee, arms } => {
+            free_vars(scrutinee, bound, free);
+            for arm in arms.iter_mut() {
+                let arm_scope = bound.len();
+                bound.extend(
+                    arm.pattern
+                        .bindings()
+                        .into_iter()
+                        .map(|(name, _, _)| name.clone()),
+                );
+                free_vars(&mut arm.body, bound, free);
+                bound.truncate(arm_scope);
+            }
+        }
+        ExprKind::Block { stmts, value } => {
+            for stmt in stmts.iter_mut() {
+                match &mut stmt.kind {
+                    StmtKind::Let { name, value, .. } => {
+                        free_vars(value, bound, free);
+                        bound.push(name.clone());
+                    }
+                    StmtKind::LetTuple { names, value, .. } => {
+                        free_vars(value, bound, free);
+                        bound.extend(names.iter().cloned());
+                    }
+                    StmtKind::Expr(expr) => free_vars(expr, bound, free),
+                    StmtKind::Def { .. } | StmtKind::Type { .. } => {
+                        unreachable!("blocks can't contain definitions")
+                    }
+                }
+            }
+            if let Some(value) = value {
+                free_vars(value, bound, free);
+            }
+        }
+        _ => for_each_child(expr, &mut |child| free_vars(child, bound, free)),
+    }
+    bound.truncate(outer_scope);
+}
+
+struct Converter {
+    lambdas: Vec<Lambda>,
+    /// The top-level `def`s, which are called directly instead of through a closure.
+    functions: HashSet<String>,
+    /// The top-level `let`s converted so far. They are globals, so lambdas don't capture them.
+    globals: Vec<String>,
+}
+
+impl Converter {
+    fn convert_stmt(&mut self, stmt: &mut Stmt) {
+        match &mut stmt.kind {
+            StmtKind::Def { params, body, .. } => {
+                if let Some(body) = body {
+                    self.convert_expr(body, &mut params.clone());
+                }
+            }
+            StmtKind::Let { name, value, .. } => {
+                self.convert_expr(value, &mut Vec::new());
+                self.globals.push(name.clone());
+            }
+            StmtKind::LetTuple { names, value, .. } => {
+                self.convert_expr(value, &mut Vec::new());
+                self.globals.extend(names.iter().cloned());
+            }
+            StmtKind::Type { .. } => {}
+            StmtKind::Expr(expr) => self.convert_expr(expr, &mut Vec::new()),
+        }
+    }
+
+    /// Converts the lambdas in `expr`, where `locals` are the local variables in scope, innermost
+    /// last.
+    fn convert_expr(&mut self, expr: &mut Expr, locals: &mut Vec<(String, Type)>) {
+        match &mut expr.kind {
+            ExprKind::Ident(name) if self.is_function(name, locals) => {
+                // Wrap a function used as a value in a lambda that calls it, so that it takes
+                // the environment of a closure like lambdas do.
+                let Type::Func(param_types, return_type) = expr.type_.clone() else {
+                    unreachable!("checked by the type checker");
+                };
+                let params: Vec<_> = param_types
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, type_)| (format!("#{}", i), type_))
+                    .collect();
+                let args = params
+                    .iter()
+                    .map(|(name, type_)| Expr {
+                        kind: ExprKind::Ident(name.clone()),
+                        span: expr.span,
+                        type_: type_.clone(),
+                    })
+                    .collect();
+                let call = Expr {
+                    kind: ExprKind::Call {
+                        callee: Box::new(expr.clone()),
+                        args,
+                    },
+                    span: expr.span,
+                    type_: *return_type,
+                };
+                expr.kind = ExprKind::Lambda {
+                    params,
+                    body: Box::new(call),
+                };
+                self.convert_expr(expr, locals);
+            }
+            ExprKind::Lambda { .. } => {
+                let mut free = Vec::new();
+                free_vars(expr, &mut Vec::new(), &mut free);
+                let captures: Vec<_> = free
+                    .into_iter()
+                    .filter_map(|name| locals.iter().rev().find(|(local, _)| *local == name))
+                    .cloned()
+                    .collect();
+                let ExprKind::Lambda { params, mut body } =
+                    std::mem::replace(&mut expr.kind, ExprKind::UnitLit)
+                else {
+                    unreachable!();
+                };
+                let mut lambda_locals = captures.clone();
+                lambda_locals.extend(params.iter().cloned());
+                self.convert_expr(&mut body, &mut lambda_locals);
+                self.lambdas.push(Lambda {
+                    captures,
+                    params,
+                    body: *body,
+                });
+                expr.kind = ExprKind::Closure(self.lambdas.len() - 1);
+            }
+            ExprKind::Call { callee, args } => {
+                // Calls to functions by name stay direct.
+                match &callee.kind {
+                    ExprKind::Ident(name) if self.is_function(name, locals) => {}
+                    _ => self.convert_expr(callee, locals),
+                }
+                for arg in args.iter_mut() {
+                    self.convert_expr(arg, locals);
+                }
+            }
+            ExprKind::For {
+                var,
+                start,
+                end,
+                body,
+            } => {
+                self.convert_expr(start, locals);
+                self.convert_expr(end, locals);
+                locals.push((var.clone(), Type::Int));
+                self.convert_expr(body, locals);
+                locals.pop();
+            }
+            ExprKind::ForEach { var, array, body } => {
+                self.convert_expr(array, locals);
+                let Type::Array(elem) = &array.type_ else {
+                    unreachable!("checked by the type checker");
+                };
+                locals.push((var.clone(), (**elem).clone()));
+                self.convert_expr(body, locals);
+                locals.pop();
+            }
+            ExprKind::Match { scrutinee, arms } => {
+                self.convert_expr(scrutinee, locals);
+                for arm in arms.iter_mut() {
+                    let outer_scope = locals.len();
+                    locals.extend(
+                        arm.pattern
+                            .bindings()
+                            .into_iter()
+                            .map(|(name, type_, _)| (name.clone(), type_.clone())),
+                    );
+                    self.convert_expr(&mut arm.body, locals);
+                    locals.truncate(outer_scope);
+                }
+            }
+            ExprKind::Block { stmts, value } => {
+                let outer_scope = locals.len();
+                for stmt in stmts.iter_mut() {
+                    match &mut stmt.kind {
+                        StmtKind::Let {
+                            name, type_, value, ..
+                        } => {
+                            self.convert_expr(value, locals);
+                            locals.push((name.clone(), type_.clone()));
+                        }
+                        StmtKind::LetTuple {
+                            names,
+                            type_,
+                            value,
+                        } => {
+                            self.convert_expr(value, locals);
+                            let Type::Tuple(types) = type_ else {
+                                unreachable!("checked by the type checker");
+                            };
+                            locals.extend(names.iter().cloned().zip(types.iter().cloned()));
+                        }
+                        StmtKind::Expr(expr) => self.convert_expr(expr, locals),
+                        StmtKind::Def { .. } | StmtKind::Type { .. } => {
+                            unreachable!("blocks can't contain definitions")
+                        }
+                    }
+                }
+                if let Some(value) = value {
+                    self.convert_expr(value, locals);
+                }
+                locals.truncate(outer_scope);
+            }
+            _ => for_each_child(expr, &mut |child| self.convert_expr(child, locals)),
+        }
+    }
+
+    /// Whether `name` refers to a top-level `def` rather than to a variable.
+    fn is_function(&self, name: &str, locals: &[(String, Type)]) -> bool {
+        self.functions.contains(name)
+            && !locals.iter().any(|(local, _)| local == name)
+            && !self.globals.iter().any(|global| global == name)
+    }
+}
@@ -1,44 +1,442 @@
 #![allow(dead_code)]
 
 use crate::ir;
+use crate::parser::diagnostic::Diagnostic;
+use crate::parser::span::Span;
+use crate::patterns::{self, Ctor, Decision, Enums};
+use crate::runtime::{self, Helper};
 use crate::wasm;
 use std::collections::HashMap;
 
-type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+type Result<T> = std::result::Result<T, Diagnostic>;
 
+/// The state of the function being generated.
 struct Context<'a> {
-    locals: Vec<(&'a String, &'a ir::Type)>,
+    /// The variables in scope, innermost last, with the locals holding their values and their
+    /// types.
+    locals: Vec<(&'a String, Vec<wasm::LocalIdx>, ir::Type)>,
+    /// The types of the locals declared by the function, after its parameters.
+    local_types: Vec<wasm::ValType>,
+    /// Locals whose variables went out of scope, and which can be reused.
+    free_locals: Vec<wasm::LocalIdx>,
+    param_count: u32,
+    /// The number of blocks around the current instruction.
+    depth: u32,
+    /// The enclosing loops, innermost last.
+    loops: Vec<LoopLabels>,
+    /// Whether this is `_start`, where the top-level `let`s in scope are always initialized.
+    is_start: bool,
+}
+
+/// A variable of a `match` arm, with its locals, the part of the matched value it is bound to and
+/// its type.
+type ArmBinding<'a> = (&'a String, Vec<wasm::LocalIdx>, Vec<usize>, &'a ir::Type);
+
+/// The state of a `match` whose decision tree is being generated.
+struct MatchState<'a> {
+    /// The locals holding the parts of the matched value loaded so far, by the indices of the
+    /// variant fields that lead to them, with their types.
+    parts: HashMap<Vec<usize>, (Vec<wasm::LocalIdx>, ir::Type)>,
+    /// The locals holding the variables of each arm, with the parts of the value they are bound to.
+    bindings: Vec<Vec<ArmBinding<'a>>>,
+    /// The depth inside the block that the first arm follows. The block of each next arm encloses
+    /// it.
+    first_arm_depth: u32,
+    /// The locals to free once the decision tree is generated.
+    temps: Vec<wasm::LocalIdx>,
+}
+
+/// The blocks that `break` and `continue` branch to, as the depth inside them.
+struct LoopLabels {
+    break_depth: u32,
+    continue_depth: u32,
+    /// The number of variables in scope outside the loop. `break` and `continue` release the
+    /// others.
+    scope: usize,
 }
 
 impl<'a> Context<'a> {
     fn new() -> Self {
-        Self { locals: Vec::new() }
+        Self {
+            locals: Vec::new(),
+            local_types: Vec::new(),
+            free_locals: Vec::new(),
+            param_count: 0,
+            depth: 0,
+            loops: Vec::new(),
+            is_start: false,
+        }
+    }
+
+    fn add_param(&mut self, name: &'a String, type_: &ir::Type) {
+        let mut idxs = vec![];
+        for _ in val_types(type_) {
+            idxs.push(wasm::LocalIdx(self.param_count));
+            self.param_count += 1;
+        }
+        self.locals.push((name, idxs, type_.clone()));
+    }
+
+    /// Returns locals to hold a value of type `type_`, declaring new ones if needed.
+    fn add_local(&mut self, type_: &ir::Type) -> Vec<wasm::LocalIdx> {
+        let mut idxs = vec![];
+        for val_type in val_types(type_) {
+            let free = self
+                .free_locals
+                .iter()
+                .position(|idx| self.local_types[(idx.0 - self.param_count) as usize] == val_type);
+            match free {
+                Some(i) => idxs.push(self.free_locals.swap_remove(i)),
+                None => {
+                    idxs.push(wasm::LocalIdx(
+                        self.param_count + self.local_types.len() as u32,
+                    ));
+                    self.local_types.push(val_type);
+                }
+            }
+        }
+        idxs
+    }
+
+    fn free_local(&mut self, idxs: Vec<wasm::LocalIdx>) {
+        self.free_locals.extend(idxs);
+    }
+
+    /// Ends the scope of the variables declared since `locals.len()` was `outer_scope`.
+    fn exit_scope(&mut self, outer_scope: usize) {
+        let locals: Vec<_> = self.locals.drain(outer_scope..).collect();
+        for (_, idxs, _) in locals {
+            self.free_local(idxs);
+        }
     }
+
+    fn lookup(&self, name: &str) -> Option<&[wasm::LocalIdx]> {
+        self.locals
+            .iter()
+            .rev()
+            .find(|(local_name, _, _)| *local_name == name)
+            .map(|(_, idxs, _)| idxs.as_slice())
+    }
+
+    /// The label index of the block entered at `depth`, seen from the current instruction.
+    fn label(&self, depth: u32) -> wasm::LabelIdx {
+        wasm::LabelIdx(self.depth - depth)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Run the top-level statements from the start section when the module is instantiated,
+    /// instead of exporting them as a `_start` function.
+    pub start_section: bool,
 }
 
+/// Where the bytes of string literals start, followed by the closures, records, values of sum types
+/// and strings allocated at run time. Address 0 stays unused, so that it is never a valid pointer.
+const DATA_START: u32 = 8;
+
+/// The names of the exports that every module has besides the exported functions.
+const RESERVED_EXPORTS: [&str; 3] = ["memory", "alloc", "release_string"];
+
+/// The size of a page of Wasm memory.
+const PAGE_SIZE: u32 = 65536;
+
+/// Where the elements of an array start, after its length and its capacity.
+const ARRAY_START: u32 = 8;
+
 pub struct CodeGenerator {
+    options: Options,
+    types: Vec<wasm::FuncType>,
     func_type_map: HashMap<wasm::FuncType, usize>,
+    /// The indices of the top-level `def`s, imported or not.
+    funcs: HashMap<String, wasm::FuncIdx>,
+    /// The top-level `let`s generated so far, latest last, with the globals holding their values.
+    globals: Vec<(String, Vec<wasm::GlobalIdx>)>,
+    /// The fields of the record types.
+    records: HashMap<String, Vec<(String, ir::Type)>>,
+    /// The variants of the sum types.
+    enums: Enums,
+    /// The global holding the address where the heap ends, where objects are allocated when none
+    /// is free.
+    heap: wasm::GlobalIdx,
+    /// The global holding the list of freed objects.
+    free_list: wasm::GlobalIdx,
+    /// The global holding the number of entries of `globals` that `_start` has initialized.
+    initialized: wasm::GlobalIdx,
+    /// The bytes of the string literals, stored from `DATA_START`.
+    data: Vec<u8>,
+    /// The addresses of the string literals in memory.
+    string_addrs: HashMap<String, u32>,
+    /// The runtime functions used so far, which follow the lambdas and the functions releasing
+    /// their captured values in the function index space.
+    helpers: Vec<HelperFunc>,
+    /// The index of the first runtime function.
+    first_helper: u32,
+    /// The variables captured by each lifted lambda.
+    captures: Vec<Vec<(String, ir::Type)>>,
+}
+
+/// A function added after the lambdas when it is used.
+#[derive(Clone, PartialEq)]
+enum HelperFunc {
+    Runtime(Helper),
+    /// Releases a record, a value of a sum type or an array, and frees it with what it refers to if
+    /// it was the last reference.
+    Drop(ir::Type),
+}
+
+/// The meaning of the annotation on a `def`.
+enum Annotation<'a> {
+    Export(&'a str),
+    Import { module: &'a str, name: &'a str },
 }
 
 impl CodeGenerator {
-    fn new() -> Self {
+    fn new(options: Options) -> Self {
         Self {
+            options,
+            types: Vec::new(),
             func_type_map: HashMap::new(),
+            funcs: HashMap::new(),
+            globals: Vec::new(),
+            records: HashMap::new(),
+            enums: HashMap::new(),
+            heap: wasm::GlobalIdx(0),
+            free_list: wasm::GlobalIdx(0),
+            initialized: wasm::GlobalIdx(0),
+            data: Vec::new(),
+            string_addrs: HashMap::new(),
+            helpers: Vec::new(),
+            first_helper: 0,
+            captures: Vec::new(),
         }
     }
 
     pub fn generate(program: &ir::Program) -> Result<wasm::Module> {
-        let g = Self::new();
+        Self::generate_with_options(program, Options::default())
+    }
+
+    pub fn generate_with_options(program: &ir::Program, options: Options) -> Result<wasm::Module> {
+        let mut g = Self::new(options);
         let mut module = wasm::Module::new();
         g.generate_program(program, &mut module)?;
+        module.types = g.types;
         Ok(module)
     }
 
-    fn generate_program(&self, program: &ir::Program, module: &mut wasm::Module) -> Result<()> {
+    /// Returns the index of `r#type` in the type section, adding it if needed.
+    fn func_type(&mut self, r#type: wasm::FuncType) -> wasm::TypeIdx {
+        let idx = *self
+            .func_type_map
+            .entry(r#type)
+            .or_insert_with_key(|r#type| {
+                self.types.push(r#type.clone());
+                self.types.len() - 1
+            });
+        wasm::TypeIdx(idx as u32)
+    }
+
+    /// The type of a block producing a value of type `type_`, which needs an entry in the type
+    /// section when it is several Wasm values.
+    fn block_type(&mut self, type_: &ir::Type) -> wasm::BlockType {
+        let mut val_types = val_types(type_);
+        match val_types.len() {
+            0 => wasm::BlockType::ValType(None),
+            1 => wasm::BlockType::ValType(val_types.pop()),
+            _ => wasm::BlockType::TypeIdx(self.func_type(wasm::FuncType(
+                wasm::ResultType(vec![]),
+                wasm::ResultType(val_types),
+            ))),
+        }
+    }
+
+    /// Returns the index of the runtime function `helper`, adding it to the module if needed.
+    fn helper(&mut self, helper: Helper) -> wasm::FuncIdx {
+        self.helper_func(HelperFunc::Runtime(helper))
+    }
+
+    fn helper_func(&mut self, helper: HelperFunc) -> wasm::FuncIdx {
+        let i = match self.helpers.iter().position(|used| *used == helper) {
+            Some(i) => i,
+            None => {
+                self.helpers.push(helper);
+                self.helpers.len() - 1
+            }
+        };
+        wasm::FuncIdx(self.first_helper + i as u32)
+    }
+
+    /// Returns the address of the string literal `s`, adding its bytes to the data segment if
+    /// needed.
+    fn string_addr(&mut self, s: &str) -> u32 {
+        *self.string_addrs.entry(s.to_string()).or_insert_with(|| {
+            let addr = DATA_START + self.data.len() as u32;
+            self.data.extend_from_slice(s.as_bytes());
+            addr
+        })
+    }
+
+    fn generate_program(&mut self, program: &ir::Program, module: &mut wasm::Module) -> Result<()> {
+        // Imported functions come first in the function index space, so number them before
+        // generating any call.
+        let mut defined = vec![];
+        let mut export_spans = HashMap::new();
+        for stmt in program.statements.iter() {
+            if let ir::StmtKind::Type { name, def } = &stmt.kind {
+                match def {
+                    ir::TypeDef::Record(fields) => {
+                        self.records.insert(name.clone(), fields.clone());
+                    }
+                    ir::TypeDef::Enum(variants) => {
+                        self.enums.insert(name.clone(), variants.clone());
+                    }
+                }
+            }
+            let ir::StmtKind::Def {
+                annotations,
+                name,
+                params,
+                return_type,
+                body,
+            } = &stmt.kind
+            else {
+                continue;
+            };
+            match (def_annotation(annotations)?, body) {
+                (Some(Annotation::Import { .. }), Some(body)) => {
+                    return Err(Diagnostic::error("imported functions can't have a body")
+                        .with_code("E0204")
+                        .with_primary(body.span, "")
+                        .with_secondary(annotations[0].span, "imported here"));
+                }
+                (
+                    Some(Annotation::Import {
+                        module: import_module,
+                        name: import_name,
+                    }),
+                    None,
+                ) => {
+                    let r#type = self.func_type(func_type(params, return_type));
+                    self.funcs
+                        .insert(name.clone(), wasm::FuncIdx(module.imports.len() as u32));
+                    module.imports.push(wasm::Import {
+                        module: wasm::Name(import_module.to_string()),
+                        name: wasm::Name(import_name.to_string()),
+                        desc: wasm::ImportDesc::Func(r#type),
+                    });
+                }
+                (Some(Annotation::Export(export_name)), Some(_)) => {
+                    let span = annotations[0].span;
+                    if RESERVED_EXPORTS.contains(&export_name)
+                        || (export_name == "_start" && !self.options.start_section)
+                    {
+                        return Err(Diagnostic::error(format!(
+                            "the export name `{}` is reserved",
+                            export_name
+                        ))
+                        .with_code("E0206")
+                        .with_primary(span, "")
+                        .with_note(format!(
+                            "every module exports `{}`, and `_start` unless it is compiled \
+                             with `--start-section`",
+                            RESERVED_EXPORTS.join("`, `")
+                        )));
+                    }
+                    if let Some(first) = export_spans.insert(export_name, span) {
+                        return Err(Diagnostic::error(format!(
+                            "the export name `{}` is used twice",
+                            export_name
+                        ))
+                        .with_code("E0207")
+                        .with_primary(span, "second export")
+                        .with_secondary(first, "first exported here"));
+                    }
+                    defined.push(name);
+                }
+                (_, Some(_)) => defined.push(name),
+                (_, None) => {
+                    return Err(
+                        Diagnostic::error(format!("function `{}` has no body", name))
+                            .with_code("E0203")
+                            .with_primary(stmt.span, "")
+                            .with_help(
+                                "add `= ...`, or import it with `@import(\"module\", \"name\")`",
+                            ),
+                    );
+                }
+            }
+        }
+        let first_defined = module.imports.len();
+        let first_lambda = first_defined + defined.len();
+        for (i, name) in defined.into_iter().enumerate() {
+            self.funcs
+                .insert(name.clone(), wasm::FuncIdx((first_defined + i) as u32));
+        }
+
+        // Closures, records, variants and strings are stored in memory, which is exported so that
+        // the host can read strings. The heap starts after the string literals, once they are all
+        // known. The host allocates the strings it passes with `alloc`, and releases the ones it
+        // gets back with `release_string`.
+        self.heap = wasm::GlobalIdx(module.globals.len() as u32);
+        self.free_list = wasm::GlobalIdx(module.globals.len() as u32 + 1);
+        module.globals.push(wasm::Global {
+            r#type: wasm::GlobalType(wasm::Mut::Var, wasm::ValType::I32),
+            init: wasm::Expr(vec![]),
+        });
+        module.globals.push(wasm::Global {
+            r#type: wasm::GlobalType(wasm::Mut::Var, wasm::ValType::I32),
+            init: wasm::Expr(vec![wasm::Instr::I32Const(0)]),
+        });
+        // A `def` or a lambda can run before `_start` has initialized the top-level `let`s it
+        // uses, and traps if it does.
+        self.initialized = add_globals(&ir::Type::Int, module)[0];
+        module.exports.push(wasm::Export {
+            name: wasm::Name("memory".to_string()),
+            desc: wasm::ExportDesc::Mem(wasm::MemIdx(0)),
+        });
+        // The functions of lambdas follow the `def`s in the function index space, then the
+        // functions releasing their captured values. They are the elements of the table in the
+        // same order, from index 1: calling a closure whose address is 0, and which was never
+        // created, loads index 0, which stays null and traps.
+        let count = 2 * program.lambdas.len() as u32;
+        self.first_helper = first_lambda as u32 + count;
+        module.tables.push(wasm::Table {
+            r#type: wasm::TableType(
+                wasm::Limits {
+                    min: count + 1,
+                    max: Some(count + 1),
+                },
+                wasm::ElemType,
+            ),
+        });
+        if count > 0 {
+            module.elem.push(wasm::Elem {
+                table: wasm::TableIdx(0),
+                offset: wasm::Expr(vec![wasm::Instr::I32Const(1)]),
+                init: (0..count)
+                    .map(|i| wasm::FuncIdx(first_lambda as u32 + i))
+                    .collect(),
+            });
+        }
+        self.captures = program
+            .lambdas
+            .iter()
+            .map(|lambda| lambda.captures.clone())
+            .collect();
+        for (export_name, helper) in [
+            ("alloc", Helper::Alloc),
+            ("release_string", Helper::DropString),
+        ] {
+            module.exports.push(wasm::Export {
+                name: wasm::Name(export_name.to_string()),
+                desc: wasm::ExportDesc::Func(self.helper(helper)),
+            });
+        }
+
         let mut ctx = Context::new();
+        ctx.is_start = true;
         let r#type = wasm::FuncType(wasm::ResultType(vec![]), wasm::ResultType(vec![]));
-        let type_idx = wasm::TypeIdx(module.types.len() as u32);
-        module.types.push(r#type);
+        let type_idx = self.func_type(r#type);
         let mut start_func = wasm::Func {
             r#type: type_idx,
             locals: vec![],
@@ -47,137 +445,1959 @@ impl CodeGenerator {
         for stmt in program.statements.iter() {
             self.generate_stmt(stmt, &mut ctx, module, &mut start_func)?;
         }
-        let func_idx = wasm::FuncIdx(module.funcs.len() as u32);
-        module.funcs.push(start_func);
-        module.exports.push(wasm::Export {
-            name: wasm::Name("_start".to_string()),
-            desc: wasm::ExportDesc::Func(func_idx),
+        for lambda in program.lambdas.iter() {
+            self.generate_lambda(lambda, module)?;
+        }
+        for lambda in program.lambdas.iter() {
+            self.generate_drop_captures(lambda, module);
+        }
+        // Helpers may use helpers that aren't used yet.
+        let heap_start = (DATA_START + self.data.len() as u32).next_multiple_of(8);
+        let mut i = 0;
+        while i < self.helpers.len() {
+            let func = match self.helpers[i].clone() {
+                HelperFunc::Runtime(helper) => {
+                    module.func_names.push((
+                        wasm::FuncIdx(self.first_helper + i as u32),
+                        wasm::Name(helper.name().to_string()),
+                    ));
+                    let type_idx = self.func_type(helper.func_type());
+                    let drop_captures_type = self.drop_type();
+                    let mut env = runtime::Env {
+                        heap: self.heap,
+                        free_list: self.free_list,
+                        heap_start,
+                        drop_captures_type,
+                        helper: &mut |helper| self.helper(helper),
+                    };
+                    helper.func(type_idx, &mut env)
+                }
+                HelperFunc::Drop(type_) => self.generate_drop(&type_),
+            };
+            module.funcs.push(func);
+            i += 1;
+        }
+        module.globals[self.heap.0 as usize].init =
+            wasm::Expr(vec![wasm::Instr::I32Const(heap_start)]);
+        module.mems.push(wasm::Mem {
+            r#type: wasm::MemType(wasm::Limits {
+                min: heap_start.div_ceil(PAGE_SIZE).max(1),
+                max: None,
+            }),
         });
+        if !self.data.is_empty() {
+            module.data.push(wasm::Data {
+                data: wasm::MemIdx(0),
+                offset: wasm::Expr(vec![wasm::Instr::I32Const(DATA_START)]),
+                init: std::mem::take(&mut self.data),
+            });
+        }
+        start_func.locals = ctx.local_types;
+        let func_idx = wasm::FuncIdx((module.imports.len() + module.funcs.len()) as u32);
+        module.funcs.push(start_func);
+        if self.options.start_section {
+            module.start = Some(wasm::Start { func: func_idx });
+        } else {
+            module.exports.push(wasm::Export {
+                name: wasm::Name("_start".to_string()),
+                desc: wasm::ExportDesc::Func(func_idx),
+            });
+        }
         Ok(())
     }
 
     fn generate_stmt<'a>(
-        &self,
+        &mut self,
         stmt: &'a ir::Stmt,
         ctx: &mut Context<'a>,
         module: &mut wasm::Module,
         func: &mut wasm::Func,
     ) -> Result<()> {
-        match stmt {
-            ir::Stmt::Def {
+        match &stmt.kind {
+            ir::StmtKind::Def {
                 annotations,
-                name: _name,
+                name,
                 params,
                 return_type,
                 body,
             } => {
-                match annotations.len() {
-                    0 => {}
-                    1 => {
-                        let annot = &annotations[0];
-                        match annot {
-                            ir::Expr::Call { callee, args } => {
-                                match (callee.as_ref(), args.as_slice()) {
-                                    (ir::Expr::Ident(name), [ir::Expr::StringLit(export_name)])
-                                        if name == "export" =>
-                                    {
-                                        let func_idx = wasm::FuncIdx(module.funcs.len() as u32);
-                                        module.exports.push(wasm::Export {
-                                            name: wasm::Name(export_name.to_string()),
-                                            desc: wasm::ExportDesc::Func(func_idx),
-                                        });
-                                    }
-                                    _ => todo!("Unsupported annotation: {:?}", annot),
-                                }
-                            }
-                            _ => todo!("Unsupported annotation: {:?}", annot),
-                        }
-                    }
-                    _ => todo!("Multiple annotations not supported"),
-                }
-                let mut r#type = wasm::FuncType(wasm::ResultType(vec![]), wasm::ResultType(vec![]));
-                for (_, param_type) in params.iter() {
-                    match param_type {
-                        ir::Type::Int => r#type.0.0.push(wasm::ValType::I32),
-                        _ => todo!(),
-                    }
-                }
-                match return_type {
-                    ir::Type::Int => r#type.1.0.push(wasm::ValType::I32),
-                    _ => todo!(),
+                // Imports were handled along with the function indices.
+                let Some(body) = body else {
+                    return Ok(());
+                };
+                if let Some(Annotation::Export(export_name)) = def_annotation(annotations)? {
+                    module.exports.push(wasm::Export {
+                        name: wasm::Name(export_name.to_string()),
+                        desc: wasm::ExportDesc::Func(self.funcs[name]),
+                    });
                 }
-                let type_idx = wasm::TypeIdx(module.types.len() as u32);
-                module.types.push(r#type);
-                let locals = vec![];
+                let type_idx = self.func_type(func_type(params, return_type));
                 let mut ctx = Context::new();
                 for (param_name, param_type) in params.iter() {
-                    ctx.locals.push((param_name, param_type));
+                    ctx.add_param(param_name, param_type);
                 }
                 let mut instructions = vec![];
                 self.generate_expr(body, &mut ctx, &mut instructions)?;
+                self.release_scope(0, &ctx, &mut instructions);
                 module.funcs.push(wasm::Func {
                     r#type: type_idx,
-                    locals,
+                    locals: ctx.local_types,
                     body: wasm::Expr(instructions),
                 });
             }
-            ir::Stmt::Let { name, type_, value } => {
+            // Top-level `let`s are globals so that `def`s can see them. They are initialized by
+            // `_start`, in order with the other top-level statements.
+            ir::StmtKind::Let {
+                name, type_, value, ..
+            } => {
                 self.generate_expr(value, ctx, &mut func.body.0)?;
-                func.locals.push(wasm::ValType::I32);
-                let local_idx = wasm::LocalIdx(ctx.locals.len() as u32);
-                ctx.locals.push((name, type_));
-                func.body.0.push(wasm::Instr::LocalSet(local_idx));
+                let idxs = add_globals(type_, module);
+                for idx in idxs.iter().rev() {
+                    func.body.0.push(wasm::Instr::GlobalSet(*idx));
+                }
+                self.globals.push((name.clone(), idxs));
+                self.generate_initialized(&mut func.body.0);
             }
-            ir::Stmt::Expr(expr) => {
-                let mut ctx = Context::new();
-                self.generate_expr(expr, &mut ctx, &mut func.body.0)?;
-                todo!();
+            ir::StmtKind::LetTuple {
+                names,
+                type_,
+                value,
+            } => {
+                let ir::Type::Tuple(types) = type_ else {
+                    unreachable!("checked by the type checker");
+                };
+                self.generate_expr(value, ctx, &mut func.body.0)?;
+                let idxs: Vec<_> = types
+                    .iter()
+                    .map(|type_| add_globals(type_, module))
+                    .collect();
+                for idx in idxs.iter().flatten().rev() {
+                    func.body.0.push(wasm::Instr::GlobalSet(*idx));
+                }
+                self.globals.extend(names.iter().cloned().zip(idxs));
+                self.generate_initialized(&mut func.body.0);
+            }
+            // Records and sum types were collected along with the function indices.
+            ir::StmtKind::Type { .. } => {}
+            ir::StmtKind::Expr(expr) => {
+                self.generate_discarded(expr, ctx, &mut func.body.0)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Generates the function of a lifted lambda, which takes the closure before its arguments and
+    /// starts by loading the captured variables from it, with a reference of their own.
+    fn generate_lambda(&mut self, lambda: &ir::Lambda, module: &mut wasm::Module) -> Result<()> {
+        let mut r#type = func_type(&lambda.params, &lambda.body.type_);
+        r#type.0.0.insert(0, wasm::ValType::I32);
+        let type_idx = self.func_type(r#type);
+        let mut ctx = Context::new();
+        let closure = wasm::LocalIdx(0);
+        ctx.param_count = 1;
+        for (param_name, param_type) in lambda.params.iter() {
+            ctx.add_param(param_name, param_type);
+        }
+        let mut instructions = vec![];
+        let (offsets, _) = closure_layout(&lambda.captures);
+        for ((name, type_), offsets) in lambda.captures.iter().zip(offsets) {
+            let idxs = ctx.add_local(type_);
+            for (j, ((idx, val_type), offset)) in
+                idxs.iter().zip(val_types(type_)).zip(offsets).enumerate()
+            {
+                instructions.push(wasm::Instr::LocalGet(closure));
+                instructions.push(load_instr(val_type, offset));
+                instructions.extend(self.retain_instr(type_, j));
+                instructions.push(wasm::Instr::LocalSet(*idx));
             }
+            ctx.locals.push((name, idxs, type_.clone()));
         }
+        self.generate_expr(&lambda.body, &mut ctx, &mut instructions)?;
+        self.release_scope(0, &ctx, &mut instructions);
+        module.funcs.push(wasm::Func {
+            r#type: type_idx,
+            locals: ctx.local_types,
+            body: wasm::Expr(instructions),
+        });
         Ok(())
     }
 
-    fn generate_expr(
-        &self,
-        expr: &ir::Expr,
+    /// Generates the function that releases the values captured by the closures of a lambda, before
+    /// `drop_closure` frees one.
+    fn generate_drop_captures(&mut self, lambda: &ir::Lambda, module: &mut wasm::Module) {
+        let type_idx = self.drop_type();
+        let (offsets, _) = closure_layout(&lambda.captures);
+        let mut instructions = vec![];
+        for ((_, type_), offsets) in lambda.captures.iter().zip(offsets) {
+            self.release_field(wasm::LocalIdx(0), type_, &offsets, &mut instructions);
+        }
+        module.funcs.push(wasm::Func {
+            r#type: type_idx,
+            locals: vec![],
+            body: wasm::Expr(instructions),
+        });
+    }
+
+    /// Generates the function that releases a record, a value of a sum type or an array, and frees
+    /// it with what it refers to if it was the last reference.
+    fn generate_drop(&mut self, type_: &ir::Type) -> wasm::Func {
+        let ptr = wasm::LocalIdx(0);
+        let mut locals = vec![];
+        let mut fields = vec![];
+        match type_ {
+            ir::Type::Record(name) => {
+                let declared = self.records[name].clone();
+                let (offsets, _) = layout(declared.iter().map(|(_, type_)| type_), 0);
+                for ((_, type_), offsets) in declared.iter().zip(offsets) {
+                    self.release_field(ptr, type_, &offsets, &mut fields);
+                }
+            }
+            ir::Type::Enum(name) => {
+                for (index, (_, field_types)) in self.enums[name].clone().iter().enumerate() {
+                    let (offsets, _) = variant_layout(field_types);
+                    let mut variant_fields = vec![];
+                    for (type_, offsets) in field_types.iter().zip(offsets) {
+                        self.release_field(ptr, type_, &offsets, &mut variant_fields);
+                    }
+                    if !variant_fields.is_empty() {
+                        fields.extend([
+                            wasm::Instr::LocalGet(ptr),
+                            load_instr(wasm::ValType::I32, 0),
+                            wasm::Instr::I32Const(index as u32),
+                            wasm::Instr::I32Eq,
+                            wasm::Instr::IfElse(
+                                wasm::BlockType::ValType(None),
+                                variant_fields,
+                                vec![],
+                            ),
+                        ]);
+                    }
+                }
+            }
+            ir::Type::Array(elem) if counted_values(elem).contains(&true) => {
+                // Release the elements from `addr` up to `end`.
+                let (offsets, stride) = element_layout(elem);
+                let (addr, end) = (wasm::LocalIdx(1), wasm::LocalIdx(2));
+                locals = vec![wasm::ValType::I32; 2];
+                let mut loop_instructions = vec![
+                    wasm::Instr::LocalGet(addr),
+                    wasm::Instr::LocalGet(end),
+                    wasm::Instr::I32GeU,
+                    wasm::Instr::BrIf(wasm::LabelIdx(1)),
+                ];
+                self.release_field(addr, elem, &offsets, &mut loop_instructions);
+                loop_instructions.extend([
+                    wasm::Instr::LocalGet(addr),
+                    wasm::Instr::I32Const(stride),
+                    wasm::Instr::I32Add,
+                    wasm::Instr::LocalSet(addr),
+                    wasm::Instr::Br(wasm::LabelIdx(0)),
+                ]);
+                fields.extend([
+                    wasm::Instr::LocalGet(ptr),
+                    wasm::Instr::I32Const(ARRAY_START),
+                    wasm::Instr::I32Add,
+                    wasm::Instr::LocalTee(addr),
+                    wasm::Instr::LocalGet(ptr),
+                    load_instr(wasm::ValType::I32, 0),
+                    wasm::Instr::I32Const(stride),
+                    wasm::Instr::I32Mul,
+                    wasm::Instr::I32Add,
+                    wasm::Instr::LocalSet(end),
+                    wasm::Instr::Block(
+                        wasm::BlockType::ValType(None),
+                        vec![wasm::Instr::Loop(
+                            wasm::BlockType::ValType(None),
+                            loop_instructions,
+                        )],
+                    ),
+                ]);
+            }
+            ir::Type::Array(_) => {}
+            _ => unreachable!("only records, sum types and arrays have a drop function"),
+        }
+        fields.extend([
+            wasm::Instr::LocalGet(ptr),
+            wasm::Instr::Call(self.helper(Helper::Free)),
+        ]);
+        wasm::Func {
+            r#type: self.drop_type(),
+            locals,
+            body: wasm::Expr(vec![
+                wasm::Instr::LocalGet(ptr),
+                wasm::Instr::Call(self.helper(Helper::Release)),
+                wasm::Instr::IfElse(wasm::BlockType::ValType(None), fields, vec![]),
+            ]),
+        }
+    }
+
+    /// The type `(i32) -> ()` of the functions that release a value.
+    fn drop_type(&mut self) -> wasm::TypeIdx {
+        self.func_type(wasm::FuncType(
+            wasm::ResultType(vec![wasm::ValType::I32]),
+            wasm::ResultType(vec![]),
+        ))
+    }
+
+    /// The function that releases a value of type `type_`, if it is reference counted.
+    fn drop_func(&mut self, type_: &ir::Type) -> Option<wasm::FuncIdx> {
+        match type_ {
+            ir::Type::String => Some(self.helper(Helper::DropString)),
+            ir::Type::Func(..) => Some(self.helper(Helper::DropClosure)),
+            ir::Type::Record(_) | ir::Type::Enum(_) | ir::Type::Array(_) => {
+                Some(self.helper_func(HelperFunc::Drop(type_.clone())))
+            }
+            _ => None,
+        }
+    }
+
+    /// The instruction adding a reference to the object that the `j`th Wasm value of a value of
+    /// type `type_` points to, if it is reference counted, right after that value.
+    fn retain_instr(&mut self, type_: &ir::Type, j: usize) -> Option<wasm::Instr> {
+        counted_values(type_)[j].then(|| wasm::Instr::Call(self.helper(Helper::Retain)))
+    }
+
+    /// Releases the value of type `type_` on top of the stack.
+    fn generate_release(&mut self, type_: &ir::Type, instructions: &mut Vec<wasm::Instr>) {
+        // The last element of a tuple is on top.
+        if let ir::Type::Tuple(types) = type_ {
+            for type_ in types.iter().rev() {
+                self.generate_release(type_, instructions);
+            }
+            return;
+        }
+        let count = val_types(type_).len();
+        match self.drop_func(type_) {
+            // The address is under the other values.
+            Some(drop) => {
+                instructions.extend((1..count).map(|_| wasm::Instr::Drop));
+                instructions.push(wasm::Instr::Call(drop));
+            }
+            None => instructions.extend((0..count).map(|_| wasm::Instr::Drop)),
+        }
+    }
+
+    /// Releases the value of type `type_` held by the locals `idxs`.
+    fn release_local(
+        &mut self,
+        idxs: &[wasm::LocalIdx],
+        type_: &ir::Type,
+        instructions: &mut Vec<wasm::Instr>,
+    ) {
+        if let ir::Type::Tuple(types) = type_ {
+            let mut start = 0;
+            for type_ in types.iter() {
+                let end = start + val_types(type_).len();
+                self.release_local(&idxs[start..end], type_, instructions);
+                start = end;
+            }
+        } else if let Some(drop) = self.drop_func(type_) {
+            instructions.push(wasm::Instr::LocalGet(idxs[0]));
+            instructions.push(wasm::Instr::Call(drop));
+        }
+    }
+
+    /// Releases the value of type `type_` stored at `offsets` in the object at the address in
+    /// `ptr`.
+    fn release_field(
+        &mut self,
+        ptr: wasm::LocalIdx,
+        type_: &ir::Type,
+        offsets: &[u32],
+        instructions: &mut Vec<wasm::Instr>,
+    ) {
+        if let ir::Type::Tuple(types) = type_ {
+            let mut start = 0;
+            for type_ in types.iter() {
+                let end = start + val_types(type_).len();
+                self.release_field(ptr, type_, &offsets[start..end], instructions);
+                start = end;
+            }
+        } else if let Some(drop) = self.drop_func(type_) {
+            instructions.extend([
+                wasm::Instr::LocalGet(ptr),
+                load_instr(wasm::ValType::I32, offsets[0]),
+                wasm::Instr::Call(drop),
+            ]);
+        }
+    }
+
+    /// Releases the variables declared after the first `outer_scope` ones, innermost first.
+    fn release_scope(
+        &mut self,
+        outer_scope: usize,
+        ctx: &Context,
+        instructions: &mut Vec<wasm::Instr>,
+    ) {
+        for (_, idxs, type_) in ctx.locals[outer_scope..].iter().rev() {
+            self.release_local(idxs, type_, instructions);
+        }
+    }
+
+    /// Releases the variables declared after the first `outer_scope` ones and ends their scope.
+    fn exit_scope(
+        &mut self,
+        outer_scope: usize,
         ctx: &mut Context,
         instructions: &mut Vec<wasm::Instr>,
+    ) {
+        self.release_scope(outer_scope, ctx, instructions);
+        ctx.exit_scope(outer_scope);
+    }
+
+    /// Generates a statement that may appear in a block.
+    fn generate_local_stmt<'a>(
+        &mut self,
+        stmt: &'a ir::Stmt,
+        ctx: &mut Context<'a>,
+        instructions: &mut Vec<wasm::Instr>,
+    ) -> Result<()> {
+        match &stmt.kind {
+            ir::StmtKind::Let {
+                name, type_, value, ..
+            } => {
+                self.generate_expr(value, ctx, instructions)?;
+                let idxs = ctx.add_local(type_);
+                for idx in idxs.iter().rev() {
+                    instructions.push(wasm::Instr::LocalSet(*idx));
+                }
+                ctx.locals.push((name, idxs, type_.clone()));
+            }
+            ir::StmtKind::LetTuple {
+                names,
+                type_,
+                value,
+            } => {
+                let ir::Type::Tuple(types) = type_ else {
+                    unreachable!("checked by the type checker");
+                };
+                self.generate_expr(value, ctx, instructions)?;
+                let idxs: Vec<_> = types.iter().map(|type_| ctx.add_local(type_)).collect();
+                for idx in idxs.iter().flatten().rev() {
+                    instructions.push(wasm::Instr::LocalSet(*idx));
+                }
+                for ((name, idxs), type_) in names.iter().zip(idxs).zip(types) {
+                    ctx.locals.push((name, idxs, type_.clone()));
+                }
+            }
+            ir::StmtKind::Expr(expr) => self.generate_discarded(expr, ctx, instructions)?,
+            ir::StmtKind::Def { .. } | ir::StmtKind::Type { .. } => {
+                unreachable!("blocks can't contain definitions")
+            }
+        }
+        Ok(())
+    }
+
+    fn generate_expr<'a>(
+        &mut self,
+        expr: &'a ir::Expr,
+        ctx: &mut Context<'a>,
+        instructions: &mut Vec<wasm::Instr>,
     ) -> Result<()> {
-        match expr {
-            ir::Expr::BinOp { op, lhs, rhs } => {
+        match &expr.kind {
+            ir::ExprKind::BinOp {
+                op: op @ (ir::BinOp::And | ir::BinOp::Or),
+                lhs,
+                rhs,
+            } => {
+                // Only evaluate the right-hand side when it decides the result.
+                self.generate_expr(lhs, ctx, instructions)?;
+                let mut rhs_instructions = vec![];
+                ctx.depth += 1;
+                self.generate_expr(rhs, ctx, &mut rhs_instructions)?;
+                ctx.depth -= 1;
+                let (then, else_) = match op {
+                    ir::BinOp::And => (rhs_instructions, vec![wasm::Instr::I32Const(0)]),
+                    _ => (vec![wasm::Instr::I32Const(1)], rhs_instructions),
+                };
+                instructions.push(wasm::Instr::IfElse(
+                    wasm::BlockType::ValType(Some(wasm::ValType::I32)),
+                    then,
+                    else_,
+                ));
+            }
+            ir::ExprKind::BinOp { op, lhs, rhs } if lhs.type_ == ir::Type::String => {
                 self.generate_expr(lhs, ctx, instructions)?;
                 self.generate_expr(rhs, ctx, instructions)?;
                 match op {
                     ir::BinOp::Add => {
-                        instructions.push(wasm::Instr::I32Add);
+                        instructions.push(wasm::Instr::Call(self.helper(Helper::Concat)));
+                    }
+                    // Comparisons compare the result of `compare` with 0.
+                    _ => instructions.extend([
+                        wasm::Instr::Call(self.helper(Helper::Compare)),
+                        wasm::Instr::I32Const(0),
+                        binop_instr(*op, &ir::Type::Int),
+                    ]),
+                }
+            }
+            ir::ExprKind::BinOp { op, lhs, rhs } => {
+                self.generate_expr(lhs, ctx, instructions)?;
+                self.generate_expr(rhs, ctx, instructions)?;
+                instructions.push(binop_instr(*op, &lhs.type_));
+            }
+            ir::ExprKind::UnOp { op, operand } => match (op, &operand.type_) {
+                (ir::UnOp::Neg, ir::Type::Int) => {
+                    instructions.push(wasm::Instr::I32Const(0));
+                    self.generate_expr(operand, ctx, instructions)?;
+                    instructions.push(wasm::Instr::I32Sub);
+                }
+                (ir::UnOp::Neg, ir::Type::Int64) => {
+                    instructions.push(wasm::Instr::I64Const(0));
+                    self.generate_expr(operand, ctx, instructions)?;
+                    instructions.push(wasm::Instr::I64Sub);
+                }
+                (ir::UnOp::Neg, _) => {
+                    self.generate_expr(operand, ctx, instructions)?;
+                    instructions.push(wasm::Instr::F64Neg);
+                }
+                (ir::UnOp::Not, ir::Type::Bool) => {
+                    self.generate_expr(operand, ctx, instructions)?;
+                    instructions.push(wasm::Instr::I32Eqz);
+                }
+                (ir::UnOp::Not, ir::Type::Int64) => {
+                    self.generate_expr(operand, ctx, instructions)?;
+                    instructions.push(wasm::Instr::I64Const(u64::MAX));
+                    instructions.push(wasm::Instr::I64Xor);
+                }
+                (ir::UnOp::Not, _) => {
+                    self.generate_expr(operand, ctx, instructions)?;
+                    instructions.push(wasm::Instr::I32Const(u32::MAX));
+                    instructions.push(wasm::Instr::I32Xor);
+                }
+            },
+            ir::ExprKind::If { cond, then, else_ } => {
+                self.generate_expr(cond, ctx, instructions)?;
+                ctx.depth += 1;
+                let mut then_instructions = vec![];
+                self.generate_expr(then, ctx, &mut then_instructions)?;
+                let mut else_instructions = vec![];
+                self.generate_expr(else_, ctx, &mut else_instructions)?;
+                ctx.depth -= 1;
+                instructions.push(wasm::Instr::IfElse(
+                    self.block_type(&expr.type_),
+                    then_instructions,
+                    else_instructions,
+                ));
+                if expr.type_ == ir::Type::Never {
+                    instructions.push(wasm::Instr::Unreachable);
+                }
+            }
+            ir::ExprKind::While { cond, body } => {
+                // block
+                //   loop
+                //     (br_if 1 (i32.eqz cond))
+                //     body
+                //     (br 0)
+                //   end
+                // end
+                let mut loop_instructions = vec![];
+                ctx.depth += 2;
+                ctx.loops.push(LoopLabels {
+                    break_depth: ctx.depth - 1,
+                    continue_depth: ctx.depth,
+                    scope: ctx.locals.len(),
+                });
+                self.generate_expr(cond, ctx, &mut loop_instructions)?;
+                loop_instructions.push(wasm::Instr::I32Eqz);
+                loop_instructions.push(wasm::Instr::BrIf(wasm::LabelIdx(1)));
+                self.generate_discarded(body, ctx, &mut loop_instructions)?;
+                loop_instructions.push(wasm::Instr::Br(wasm::LabelIdx(0)));
+                ctx.loops.pop();
+                ctx.depth -= 2;
+                instructions.push(wasm::Instr::Block(
+                    wasm::BlockType::ValType(None),
+                    vec![wasm::Instr::Loop(
+                        wasm::BlockType::ValType(None),
+                        loop_instructions,
+                    )],
+                ));
+            }
+            ir::ExprKind::For {
+                var,
+                start,
+                end,
+                body,
+            } => {
+                // (local.set $var start)
+                // (local.set $end end)
+                // block
+                //   loop
+                //     (br_if 1 (i32.ge_s $var $end))
+                //     block
+                //       body
+                //     end
+                //     (local.set $var (i32.add $var 1))
+                //     (br 0)
+                //   end
+                // end
+                let var_idx = ctx.add_local(&ir::Type::Int)[0];
+                let end_idx = ctx.add_local(&ir::Type::Int)[0];
+                self.generate_expr(start, ctx, instructions)?;
+                instructions.push(wasm::Instr::LocalSet(var_idx));
+                self.generate_expr(end, ctx, instructions)?;
+                instructions.push(wasm::Instr::LocalSet(end_idx));
+
+                ctx.locals.push((var, vec![var_idx], ir::Type::Int));
+                ctx.depth += 3;
+                ctx.loops.push(LoopLabels {
+                    break_depth: ctx.depth - 2,
+                    continue_depth: ctx.depth,
+                    scope: ctx.locals.len(),
+                });
+                let mut body_instructions = vec![];
+                self.generate_discarded(body, ctx, &mut body_instructions)?;
+                ctx.loops.pop();
+                ctx.depth -= 3;
+                let (_, var_idxs, _) = ctx.locals.pop().unwrap();
+                ctx.free_local(var_idxs);
+                ctx.free_local(vec![end_idx]);
+
+                let loop_instructions = vec![
+                    wasm::Instr::LocalGet(var_idx),
+                    wasm::Instr::LocalGet(end_idx),
+                    wasm::Instr::I32GeS,
+                    wasm::Instr::BrIf(wasm::LabelIdx(1)),
+                    wasm::Instr::Block(wasm::BlockType::ValType(None), body_instructions),
+                    wasm::Instr::LocalGet(var_idx),
+                    wasm::Instr::I32Const(1),
+                    wasm::Instr::I32Add,
+                    wasm::Instr::LocalSet(var_idx),
+                    wasm::Instr::Br(wasm::LabelIdx(0)),
+                ];
+                instructions.push(wasm::Instr::Block(
+                    wasm::BlockType::ValType(None),
+                    vec![wasm::Instr::Loop(
+                        wasm::BlockType::ValType(None),
+                        loop_instructions,
+                    )],
+                ));
+            }
+            ir::ExprKind::ForEach { var, array, body } => {
+                // (local.set $array array)
+                // (local.set $i 0)
+                // block
+                //   loop
+                //     (br_if 1 (i32.ge_u $i (i32.load $array)))
+                //     (local.set $var (load element $i))
+                //     (local.set $i (i32.add $i 1))
+                //     body
+                //     (release $var)
+                //     (br 0)
+                //   end
+                // end
+                // (release $array)
+                let ir::Type::Array(elem) = &array.type_ else {
+                    unreachable!("checked by the type checker");
+                };
+                let outer_scope = ctx.locals.len();
+                let array_idx = ctx.add_local(&array.type_)[0];
+                let i_idx = ctx.add_local(&ir::Type::Int)[0];
+                self.generate_expr(array, ctx, instructions)?;
+                instructions.extend([
+                    wasm::Instr::LocalSet(array_idx),
+                    wasm::Instr::I32Const(0),
+                    wasm::Instr::LocalSet(i_idx),
+                ]);
+                // The array is in scope under the name of the variable, which shadows it, so that
+                // leaving an outer loop from the body releases it.
+                ctx.locals.push((var, vec![array_idx], array.type_.clone()));
+
+                let addr = ctx.add_local(&ir::Type::Int)[0];
+                let mut loop_instructions = vec![
+                    wasm::Instr::LocalGet(i_idx),
+                    wasm::Instr::LocalGet(array_idx),
+                    load_instr(wasm::ValType::I32, 0),
+                    wasm::Instr::I32GeU,
+                    wasm::Instr::BrIf(wasm::LabelIdx(1)),
+                    wasm::Instr::LocalGet(i_idx),
+                    wasm::Instr::LocalSet(addr),
+                ];
+                self.generate_element_addr(array_idx, addr, elem, &mut loop_instructions);
+                self.generate_load_element(addr, elem, &mut loop_instructions);
+                ctx.free_local(vec![addr]);
+                let var_idxs = ctx.add_local(elem);
+                for idx in var_idxs.iter().rev() {
+                    loop_instructions.push(wasm::Instr::LocalSet(*idx));
+                }
+                loop_instructions.extend([
+                    wasm::Instr::LocalGet(i_idx),
+                    wasm::Instr::I32Const(1),
+                    wasm::Instr::I32Add,
+                    wasm::Instr::LocalSet(i_idx),
+                ]);
+
+                // `break` and `continue` release the variable.
+                ctx.depth += 2;
+                ctx.loops.push(LoopLabels {
+                    break_depth: ctx.depth - 1,
+                    continue_depth: ctx.depth,
+                    scope: ctx.locals.len(),
+                });
+                ctx.locals.push((var, var_idxs, (**elem).clone()));
+                self.generate_discarded(body, ctx, &mut loop_instructions)?;
+                ctx.loops.pop();
+                ctx.depth -= 2;
+                self.exit_scope(ctx.locals.len() - 1, ctx, &mut loop_instructions);
+                loop_instructions.push(wasm::Instr::Br(wasm::LabelIdx(0)));
+                instructions.push(wasm::Instr::Block(
+                    wasm::BlockType::ValType(None),
+                    vec![wasm::Instr::Loop(
+                        wasm::BlockType::ValType(None),
+                        loop_instructions,
+                    )],
+                ));
+                self.exit_scope(outer_scope, ctx, instructions);
+                ctx.free_local(vec![i_idx]);
+            }
+            ir::ExprKind::Loop { body } => {
+                // block (result T)
+                //   loop
+                //     body
+                //     (br 0)
+                //   end
+                //   unreachable
+                // end
+                let mut loop_instructions = vec![];
+                ctx.depth += 2;
+                ctx.loops.push(LoopLabels {
+                    break_depth: ctx.depth - 1,
+                    continue_depth: ctx.depth,
+                    scope: ctx.locals.len(),
+                });
+                self.generate_discarded(body, ctx, &mut loop_instructions)?;
+                loop_instructions.push(wasm::Instr::Br(wasm::LabelIdx(0)));
+                ctx.loops.pop();
+                ctx.depth -= 2;
+                instructions.push(wasm::Instr::Block(
+                    self.block_type(&expr.type_),
+                    vec![
+                        wasm::Instr::Loop(wasm::BlockType::ValType(None), loop_instructions),
+                        // The loop can only be left with `br`.
+                        wasm::Instr::Unreachable,
+                    ],
+                ));
+                if expr.type_ == ir::Type::Never {
+                    instructions.push(wasm::Instr::Unreachable);
+                }
+            }
+            ir::ExprKind::Break(value) => {
+                if let Some(value) = value {
+                    self.generate_expr(value, ctx, instructions)?;
+                }
+                let labels = ctx.loops.last().expect("checked by the type checker");
+                self.release_scope(labels.scope, ctx, instructions);
+                instructions.push(wasm::Instr::Br(ctx.label(labels.break_depth)));
+            }
+            ir::ExprKind::Continue => {
+                let labels = ctx.loops.last().expect("checked by the type checker");
+                self.release_scope(labels.scope, ctx, instructions);
+                instructions.push(wasm::Instr::Br(ctx.label(labels.continue_depth)));
+            }
+            ir::ExprKind::Block { stmts, value } => {
+                let outer_scope = ctx.locals.len();
+                for stmt in stmts.iter() {
+                    self.generate_local_stmt(stmt, ctx, instructions)?;
+                }
+                if let Some(value) = value {
+                    self.generate_expr(value, ctx, instructions)?;
+                }
+                self.exit_scope(outer_scope, ctx, instructions);
+            }
+            ir::ExprKind::Call { callee, args } => match &callee.kind {
+                ir::ExprKind::Ident(name) if !self.is_variable(name, ctx) => {
+                    for arg in args.iter() {
+                        self.generate_expr(arg, ctx, instructions)?;
+                    }
+                    // Otherwise, the type checker has made sure that this is a built-in function.
+                    match (self.funcs.get(name).copied(), name.as_str()) {
+                        (Some(func_idx), _) => instructions.push(wasm::Instr::Call(func_idx)),
+                        (None, "len") if matches!(args[0].type_, ir::Type::Array(_)) => {
+                            // Release the array after reading its length.
+                            let array = ctx.add_local(&args[0].type_)[0];
+                            instructions.extend([
+                                wasm::Instr::LocalTee(array),
+                                load_instr(wasm::ValType::I32, 0),
+                            ]);
+                            self.release_local(&[array], &args[0].type_, instructions);
+                            ctx.free_local(vec![array]);
+                        }
+                        (None, "len") => {
+                            // Release the string under its length.
+                            let len = ctx.add_local(&ir::Type::Int)[0];
+                            instructions.extend([
+                                wasm::Instr::LocalSet(len),
+                                wasm::Instr::Call(self.helper(Helper::DropString)),
+                                wasm::Instr::LocalGet(len),
+                            ]);
+                            ctx.free_local(vec![len]);
+                        }
+                        (None, "slice") => {
+                            instructions.push(wasm::Instr::Call(self.helper(Helper::Slice)));
+                        }
+                        (None, "push") => {
+                            self.generate_push(&args[0].type_, None, expr.span, ctx, instructions)?;
+                        }
+                        (None, "pop") => {
+                            self.generate_pop(&args[0].type_, None, expr.span, ctx, instructions)?;
+                        }
+                        (None, _) => {
+                            instructions.extend(conversion_instr(&args[0].type_, &expr.type_));
+                        }
+                    }
+                }
+                _ => {
+                    // A closure starts with the table index of its function, and is passed to it
+                    // before the arguments.
+                    let ir::Type::Func(param_types, return_type) = &callee.type_ else {
+                        unreachable!("checked by the type checker");
+                    };
+                    let closure = ctx.add_local(&callee.type_)[0];
+                    self.generate_expr(callee, ctx, instructions)?;
+                    instructions.push(wasm::Instr::LocalTee(closure));
+                    for arg in args.iter() {
+                        self.generate_expr(arg, ctx, instructions)?;
+                    }
+                    instructions.push(wasm::Instr::LocalGet(closure));
+                    instructions.push(load_instr(wasm::ValType::I32, 0));
+                    let mut params = vec![wasm::ValType::I32];
+                    for param_type in param_types.iter() {
+                        params.extend(val_types(param_type));
+                    }
+                    let r#type = wasm::FuncType(
+                        wasm::ResultType(params),
+                        wasm::ResultType(val_types(return_type)),
+                    );
+                    instructions.push(wasm::Instr::CallIndirect(self.func_type(r#type)));
+                    self.release_local(&[closure], &callee.type_, instructions);
+                    ctx.free_local(vec![closure]);
+                }
+            },
+            ir::ExprKind::Closure(i) => {
+                // Allocate the closure, then store the table indices of its function and of the
+                // function releasing its captures, and the captured variables, in it.
+                let captures = self.captures[*i].clone();
+                let (offsets, size) = closure_layout(&captures);
+                let closure = self.allocate(size, ctx, instructions);
+                instructions.extend([
+                    wasm::Instr::LocalGet(closure),
+                    wasm::Instr::I32Const(1 + *i as u32),
+                    store_instr(wasm::ValType::I32, 0),
+                    wasm::Instr::LocalGet(closure),
+                    wasm::Instr::I32Const((1 + self.captures.len() + *i) as u32),
+                    store_instr(wasm::ValType::I32, 4),
+                ]);
+                for ((name, type_), offsets) in captures.iter().zip(offsets) {
+                    let idxs = ctx.lookup(name).expect("captured from a local variable");
+                    for (j, ((idx, val_type), offset)) in
+                        idxs.iter().zip(val_types(type_)).zip(offsets).enumerate()
+                    {
+                        instructions.push(wasm::Instr::LocalGet(closure));
+                        instructions.push(wasm::Instr::LocalGet(*idx));
+                        instructions.extend(self.retain_instr(type_, j));
+                        instructions.push(store_instr(val_type, offset));
                     }
-                    ir::BinOp::Sub => {
-                        instructions.push(wasm::Instr::I32Sub);
+                }
+                instructions.push(wasm::Instr::LocalGet(closure));
+                ctx.free_local(vec![closure]);
+            }
+            ir::ExprKind::Record { fields, base } => {
+                // Store the fields in the order they are written, then copy the others from the
+                // base record.
+                let ir::Type::Record(type_name) = &expr.type_ else {
+                    unreachable!("checked by the type checker");
+                };
+                let declared = self.records[type_name].clone();
+                let (offsets, size) = layout(declared.iter().map(|(_, type_)| type_), 0);
+                let record = self.allocate(size, ctx, instructions);
+                for (name, value) in fields.iter() {
+                    let i = field_index(&declared, name);
+                    self.generate_store(record, value, &offsets[i], ctx, instructions)?;
+                }
+                if let Some(base) = base {
+                    let base_idx = ctx.add_local(&base.type_)[0];
+                    self.generate_expr(base, ctx, instructions)?;
+                    instructions.push(wasm::Instr::LocalSet(base_idx));
+                    for ((name, type_), offsets) in declared.iter().zip(offsets) {
+                        if fields.iter().any(|(field, _)| field == name) {
+                            continue;
+                        }
+                        for (j, (val_type, offset)) in
+                            val_types(type_).into_iter().zip(offsets).enumerate()
+                        {
+                            instructions.extend([
+                                wasm::Instr::LocalGet(record),
+                                wasm::Instr::LocalGet(base_idx),
+                                load_instr(val_type, offset),
+                            ]);
+                            instructions.extend(self.retain_instr(type_, j));
+                            instructions.push(store_instr(val_type, offset));
+                        }
+                    }
+                    self.release_local(&[base_idx], &base.type_, instructions);
+                    ctx.free_local(vec![base_idx]);
+                }
+                instructions.push(wasm::Instr::LocalGet(record));
+                ctx.free_local(vec![record]);
+            }
+            ir::ExprKind::Field { expr: record, name } => {
+                let ir::Type::Record(type_name) = &record.type_ else {
+                    unreachable!("checked by the type checker");
+                };
+                // The field gets a reference of its own before the record is released.
+                let declared = &self.records[type_name];
+                let (offsets, _) = layout(declared.iter().map(|(_, type_)| type_), 0);
+                let offsets = offsets[field_index(declared, name)].clone();
+                self.generate_expr(record, ctx, instructions)?;
+                let record_idx = ctx.add_local(&record.type_)[0];
+                instructions.push(wasm::Instr::LocalSet(record_idx));
+                for (j, (val_type, offset)) in
+                    val_types(&expr.type_).into_iter().zip(offsets).enumerate()
+                {
+                    instructions.push(wasm::Instr::LocalGet(record_idx));
+                    instructions.push(load_instr(val_type, offset));
+                    instructions.extend(self.retain_instr(&expr.type_, j));
+                }
+                self.release_local(&[record_idx], &record.type_, instructions);
+                ctx.free_local(vec![record_idx]);
+            }
+            ir::ExprKind::Construct { variant, args } => {
+                // Allocate the value, then store the number of its variant and its fields in it.
+                let ir::Type::Enum(type_name) = &expr.type_ else {
+                    unreachable!("checked by the type checker");
+                };
+                let index = self.enums[type_name]
+                    .iter()
+                    .position(|(name, _)| name == variant)
+                    .expect("checked by the type checker");
+                let (offsets, size) = variant_layout(&self.enums[type_name][index].1);
+                let value = self.allocate(size, ctx, instructions);
+                instructions.extend([
+                    wasm::Instr::LocalGet(value),
+                    wasm::Instr::I32Const(index as u32),
+                    store_instr(wasm::ValType::I32, 0),
+                ]);
+                for (arg, offsets) in args.iter().zip(offsets) {
+                    self.generate_store(value, arg, &offsets, ctx, instructions)?;
+                }
+                instructions.push(wasm::Instr::LocalGet(value));
+                ctx.free_local(vec![value]);
+            }
+            ir::ExprKind::Match { scrutinee, arms } => {
+                // block (result T)
+                //   block
+                //     ...
+                //       block
+                //         decision tree, which sets the variables of the matching arm and leaves
+                //         the block that the arm follows
+                //       end
+                //       arm 0
+                //       (br n-1)
+                //     ...
+                //   end
+                //   arm n-1
+                // end
+                let scrutinee_idxs = ctx.add_local(&scrutinee.type_);
+                self.generate_expr(scrutinee, ctx, instructions)?;
+                for idx in scrutinee_idxs.iter().rev() {
+                    instructions.push(wasm::Instr::LocalSet(*idx));
+                }
+                let end_depth = ctx.depth + 1;
+                let arm_count = arms.len() as u32;
+                let mut state = MatchState {
+                    parts: HashMap::from([(
+                        Vec::new(),
+                        (scrutinee_idxs.clone(), scrutinee.type_.clone()),
+                    )]),
+                    bindings: arms
+                        .iter()
+                        .map(|arm| {
+                            arm.pattern
+                                .bindings()
+                                .into_iter()
+                                .map(|(name, type_, path)| {
+                                    (name, ctx.add_local(type_), path, type_)
+                                })
+                                .collect()
+                        })
+                        .collect(),
+                    first_arm_depth: end_depth + arm_count,
+                    temps: scrutinee_idxs,
+                };
+                let decision = patterns::decision_tree(arms, &self.enums);
+                let mut block = vec![];
+                ctx.depth = state.first_arm_depth;
+                self.generate_decision(&decision, &mut state, ctx, &mut block);
+                ctx.free_local(state.temps);
+
+                for (k, (arm, bindings)) in arms.iter().zip(state.bindings).enumerate() {
+                    let mut arm_instructions =
+                        vec![wasm::Instr::Block(wasm::BlockType::ValType(None), block)];
+                    ctx.depth = state.first_arm_depth - k as u32 - 1;
+                    let outer_scope = ctx.locals.len();
+                    for (name, idxs, _, type_) in bindings {
+                        ctx.locals.push((name, idxs, type_.clone()));
                     }
-                    ir::BinOp::Mul => {
-                        instructions.push(wasm::Instr::I32Mul);
+                    self.generate_expr(&arm.body, ctx, &mut arm_instructions)?;
+                    self.exit_scope(outer_scope, ctx, &mut arm_instructions);
+                    if k as u32 + 1 < arm_count {
+                        arm_instructions.push(wasm::Instr::Br(ctx.label(end_depth)));
                     }
+                    block = arm_instructions;
+                }
+                ctx.depth = end_depth - 1;
+                instructions.push(wasm::Instr::Block(self.block_type(&expr.type_), block));
+                if expr.type_ == ir::Type::Never {
+                    instructions.push(wasm::Instr::Unreachable);
+                }
+            }
+            // The values of the elements of a tuple follow each other, unless one of them diverges.
+            ir::ExprKind::Tuple(elems) if expr.type_ == ir::Type::Never => {
+                for elem in elems.iter() {
+                    self.generate_discarded(elem, ctx, instructions)?;
+                }
+            }
+            ir::ExprKind::Tuple(elems) => {
+                for elem in elems.iter() {
+                    self.generate_expr(elem, ctx, instructions)?;
+                }
+            }
+            ir::ExprKind::Array(elems) => {
+                // Allocate the array with no room to spare, then store its length, its capacity
+                // and its elements in it.
+                let ir::Type::Array(elem) = &expr.type_ else {
+                    unreachable!("checked by the type checker");
+                };
+                let (offsets, stride) = element_layout(elem);
+                let len = elems.len() as u32;
+                let array = self.allocate(ARRAY_START + len * stride, ctx, instructions);
+                for offset in [0, 4] {
+                    instructions.extend([
+                        wasm::Instr::LocalGet(array),
+                        wasm::Instr::I32Const(len),
+                        store_instr(wasm::ValType::I32, offset),
+                    ]);
+                }
+                for (i, value) in elems.iter().enumerate() {
+                    let start = ARRAY_START + i as u32 * stride;
+                    let offsets: Vec<_> = offsets.iter().map(|offset| start + offset).collect();
+                    self.generate_store(array, value, &offsets, ctx, instructions)?;
                 }
+                instructions.push(wasm::Instr::LocalGet(array));
+                ctx.free_local(vec![array]);
             }
-            ir::Expr::Ident(name) => {
-                let mut found = false;
-                for (idx, (local_name, _)) in ctx.locals.iter().enumerate() {
-                    if name == *local_name {
-                        instructions.push(wasm::Instr::LocalGet(wasm::LocalIdx(idx as u32)));
-                        found = true;
-                        break;
+            ir::ExprKind::Index { expr: array, index } => {
+                // The element gets a reference of its own before the array is released.
+                let ir::Type::Array(elem) = &array.type_ else {
+                    unreachable!("checked by the type checker");
+                };
+                let array_idx = ctx.add_local(&array.type_)[0];
+                let addr = ctx.add_local(&ir::Type::Int)[0];
+                self.generate_expr(array, ctx, instructions)?;
+                instructions.push(wasm::Instr::LocalSet(array_idx));
+                self.generate_expr(index, ctx, instructions)?;
+                instructions.push(wasm::Instr::LocalSet(addr));
+                self.generate_element_addr(array_idx, addr, elem, instructions);
+                self.generate_load_element(addr, elem, instructions);
+                self.release_local(&[array_idx], &array.type_, instructions);
+                ctx.free_local(vec![array_idx, addr]);
+            }
+            ir::ExprKind::Lambda { .. } => unreachable!("lifted by closure conversion"),
+            // Reading a variable adds a reference to its value.
+            ir::ExprKind::Ident(name) => {
+                if let Some(idxs) = ctx.lookup(name) {
+                    for (j, idx) in idxs.iter().enumerate() {
+                        instructions.push(wasm::Instr::LocalGet(*idx));
+                        instructions.extend(self.retain_instr(&expr.type_, j));
+                    }
+                } else if let Some((i, idxs)) = self.lookup_global(name) {
+                    let idxs = idxs.to_vec();
+                    self.generate_initialized_check(i, ctx, instructions);
+                    for (j, idx) in idxs.iter().enumerate() {
+                        instructions.push(wasm::Instr::GlobalGet(*idx));
+                        instructions.extend(self.retain_instr(&expr.type_, j));
                     }
+                } else {
+                    return Err(
+                        Diagnostic::error(format!("cannot find variable `{}`", name))
+                            .with_code("E0200")
+                            .with_primary(expr.span, "not found in this scope"),
+                    );
                 }
-                if !found {
-                    return Err("".into());
+            }
+            // The type checker has inferred the type of literals without a suffix, and made sure
+            // that the value fits.
+            ir::ExprKind::IntLit(lit) => match expr.type_ {
+                ir::Type::Int64 => instructions.push(wasm::Instr::I64Const(lit.value as u64)),
+                _ => instructions.push(wasm::Instr::I32Const(lit.value as u32)),
+            },
+            ir::ExprKind::FloatLit(value) => instructions.push(wasm::Instr::F64Const(*value)),
+            ir::ExprKind::BoolLit(value) => {
+                instructions.push(wasm::Instr::I32Const(*value as u32));
+            }
+            ir::ExprKind::UnitLit => {}
+            ir::ExprKind::StringLit(s) => {
+                let addr = self.string_addr(s);
+                instructions.push(wasm::Instr::I32Const(addr));
+                instructions.push(wasm::Instr::I32Const(s.len() as u32));
+            }
+            // The new value is computed before the old one is released, since it may be used by
+            // it, like in `s = s + "!"`.
+            ir::ExprKind::Assign { rhs, .. } if rhs.type_ == ir::Type::Never => {
+                self.generate_expr(rhs, ctx, instructions)?;
+            }
+            ir::ExprKind::Assign { lhs, rhs } => {
+                self.generate_assigned(lhs, rhs, ctx, instructions)?;
+                if let Some(idxs) = ctx.lookup(lhs) {
+                    let idxs = idxs.to_vec();
+                    self.release_local(&idxs, &rhs.type_, instructions);
+                    for idx in idxs.iter().rev() {
+                        instructions.push(wasm::Instr::LocalSet(*idx));
+                    }
+                } else if let Some((i, idxs)) = self.lookup_global(lhs) {
+                    let idxs = idxs.to_vec();
+                    self.generate_initialized_check(i, ctx, instructions);
+                    for idx in idxs.iter() {
+                        instructions.push(wasm::Instr::GlobalGet(*idx));
+                    }
+                    self.generate_release(&rhs.type_, instructions);
+                    for idx in idxs.iter().rev() {
+                        instructions.push(wasm::Instr::GlobalSet(*idx));
+                    }
+                } else {
+                    return Err(Diagnostic::error(format!("cannot find variable `{}`", lhs))
+                        .with_code("E0200")
+                        .with_primary(expr.span, "not found in this scope"));
                 }
             }
-            ir::Expr::IntLit(raw) => {
-                let value = raw.parse::<i32>()?;
-                instructions.push(wasm::Instr::I32Const(value as u32));
+        }
+        Ok(())
+    }
+
+    /// Generates a decision tree of a `match`, which ends by branching to the arm that matches.
+    fn generate_decision<'a>(
+        &mut self,
+        decision: &Decision,
+        state: &mut MatchState<'a>,
+        ctx: &mut Context<'a>,
+        instructions: &mut Vec<wasm::Instr>,
+    ) {
+        let (path, cases, default) = match decision {
+            Decision::Arm(k) => {
+                // The arm owns its variables, and the matched value isn't needed anymore.
+                for (_, idxs, path, type_) in state.bindings[*k].iter() {
+                    for (i, (idx, part_idx)) in idxs.iter().zip(&state.parts[path].0).enumerate() {
+                        instructions.push(wasm::Instr::LocalGet(*part_idx));
+                        instructions.extend(self.retain_instr(type_, i));
+                        instructions.push(wasm::Instr::LocalSet(*idx));
+                    }
+                }
+                let (idxs, type_) = &state.parts[&Vec::new()];
+                self.release_local(idxs, type_, instructions);
+                let arm_depth = state.first_arm_depth - *k as u32;
+                instructions.push(wasm::Instr::Br(ctx.label(arm_depth)));
+                return;
+            }
+            Decision::Switch {
+                path,
+                cases,
+                default,
+            } => (path, cases, default),
+        };
+        let (idxs, type_) = state.parts[path].clone();
+        match (&cases[..], default) {
+            // There is nothing to test, like for `()` or a sum type with a single variant.
+            ([(ctor, decision)], None) => {
+                self.generate_case(path, ctor, decision, state, ctx, instructions);
+            }
+            ([(Ctor::Variant { type_name, .. }, _), ..], _) => {
+                // block
+                //   block
+                //     ...
+                //       block
+                //         (br_table (i32.load value))
+                //       end
+                //       case 0
+                //     ...
+                //   end
+                //   case m-1
+                // end
+                // default
+                let depth = ctx.depth;
+                let case_count = cases.len() as u32;
+                let labels = (0..self.enums[type_name].len())
+                    .map(|index| {
+                        let case = cases.iter().position(|(ctor, _)| {
+                            matches!(ctor, Ctor::Variant { index: i, .. } if *i == index)
+                        });
+                        wasm::LabelIdx(case.map_or(case_count, |case| case as u32))
+                    })
+                    .collect();
+                let mut block = vec![
+                    wasm::Instr::LocalGet(idxs[0]),
+                    load_instr(wasm::ValType::I32, 0),
+                    wasm::Instr::BrTable(labels, wasm::LabelIdx(case_count)),
+                ];
+                for (i, (ctor, decision)) in cases.iter().enumerate() {
+                    let mut case_instructions =
+                        vec![wasm::Instr::Block(wasm::BlockType::ValType(None), block)];
+                    ctx.depth = depth + case_count - i as u32;
+                    self.generate_case(path, ctor, decision, state, ctx, &mut case_instructions);
+                    block = case_instructions;
+                }
+                ctx.depth = depth;
+                instructions.push(wasm::Instr::Block(wasm::BlockType::ValType(None), block));
+                if let Some(default) = default {
+                    self.generate_decision(default, state, ctx, instructions);
+                }
+            }
+            ([(Ctor::Bool(_), _), ..], _) => {
+                let branch = |value: bool| {
+                    cases
+                        .iter()
+                        .find(|(ctor, _)| *ctor == Ctor::Bool(value))
+                        .map(|(_, decision)| decision)
+                        .or(default.as_deref())
+                        .expect("checked by the type checker to be exhaustive")
+                };
+                ctx.depth += 1;
+                let mut then = vec![];
+                self.generate_decision(branch(true), state, ctx, &mut then);
+                let mut else_ = vec![];
+                self.generate_decision(branch(false), state, ctx, &mut else_);
+                ctx.depth -= 1;
+                instructions.push(wasm::Instr::LocalGet(idxs[0]));
+                instructions.push(wasm::Instr::IfElse(
+                    wasm::BlockType::ValType(None),
+                    then,
+                    else_,
+                ));
+            }
+            _ => {
+                // Integers are compared with each literal in turn.
+                let default = default
+                    .as_deref()
+                    .expect("integer literals can't cover every value");
+                self.generate_int_cases(&idxs, &type_, cases, default, state, ctx, instructions);
+            }
+        }
+    }
+
+    /// Generates a decision tree for the values whose part at `path` has the constructor `ctor`,
+    /// after loading the fields of its variant.
+    fn generate_case<'a>(
+        &mut self,
+        path: &[usize],
+        ctor: &Ctor,
+        decision: &Decision,
+        state: &mut MatchState<'a>,
+        ctx: &mut Context<'a>,
+        instructions: &mut Vec<wasm::Instr>,
+    ) {
+        if let Ctor::Variant { type_name, index } = ctor {
+            let value = state.parts[path].0[0];
+            let field_types = &self.enums[type_name][*index].1;
+            let (offsets, _) = variant_layout(field_types);
+            for (i, (field_type, offsets)) in field_types.iter().zip(offsets).enumerate() {
+                let idxs = ctx.add_local(field_type);
+                for ((idx, val_type), offset) in idxs.iter().zip(val_types(field_type)).zip(offsets)
+                {
+                    instructions.push(wasm::Instr::LocalGet(value));
+                    instructions.push(load_instr(val_type, offset));
+                    instructions.push(wasm::Instr::LocalSet(*idx));
+                }
+                state.temps.extend(idxs.iter().copied());
+                let mut field_path = path.to_vec();
+                field_path.push(i);
+                state.parts.insert(field_path, (idxs, field_type.clone()));
+            }
+        }
+        self.generate_decision(decision, state, ctx, instructions);
+    }
+
+    /// Generates `if` instructions comparing an integer with the literals of `cases` in turn.
+    #[allow(clippy::too_many_arguments)]
+    fn generate_int_cases<'a>(
+        &mut self,
+        idxs: &[wasm::LocalIdx],
+        type_: &ir::Type,
+        cases: &[(Ctor, Decision)],
+        default: &Decision,
+        state: &mut MatchState<'a>,
+        ctx: &mut Context<'a>,
+        instructions: &mut Vec<wasm::Instr>,
+    ) {
+        let Some(((Ctor::Int(value), decision), rest)) = cases.split_first() else {
+            self.generate_decision(default, state, ctx, instructions);
+            return;
+        };
+        instructions.push(wasm::Instr::LocalGet(idxs[0]));
+        match type_ {
+            ir::Type::Int64 => instructions.push(wasm::Instr::I64Const(*value as u64)),
+            _ => instructions.push(wasm::Instr::I32Const(*value as u32)),
+        }
+        instructions.push(binop_instr(ir::BinOp::Eq, type_));
+        ctx.depth += 1;
+        let mut then = vec![];
+        self.generate_decision(decision, state, ctx, &mut then);
+        let mut else_ = vec![];
+        self.generate_int_cases(idxs, type_, rest, default, state, ctx, &mut else_);
+        ctx.depth -= 1;
+        instructions.push(wasm::Instr::IfElse(
+            wasm::BlockType::ValType(None),
+            then,
+            else_,
+        ));
+    }
+
+    /// Reserves `size` bytes of memory, and returns a local holding their address, which the caller
+    /// frees.
+    fn allocate(
+        &mut self,
+        size: u32,
+        ctx: &mut Context,
+        instructions: &mut Vec<wasm::Instr>,
+    ) -> wasm::LocalIdx {
+        let idx = ctx.add_local(&ir::Type::Int)[0];
+        instructions.extend([
+            wasm::Instr::I32Const(size),
+            wasm::Instr::Call(self.helper(Helper::Alloc)),
+            wasm::Instr::LocalSet(idx),
+        ]);
+        idx
+    }
+
+    /// Generates `value` and stores its Wasm values at `offsets` from the address in `ptr`.
+    fn generate_store<'a>(
+        &mut self,
+        ptr: wasm::LocalIdx,
+        value: &'a ir::Expr,
+        offsets: &[u32],
+        ctx: &mut Context<'a>,
+        instructions: &mut Vec<wasm::Instr>,
+    ) -> Result<()> {
+        if let [val_type] = val_types(&value.type_)[..] {
+            instructions.push(wasm::Instr::LocalGet(ptr));
+            self.generate_expr(value, ctx, instructions)?;
+            instructions.push(store_instr(val_type, offsets[0]));
+            return Ok(());
+        }
+        self.generate_expr(value, ctx, instructions)?;
+        let idxs = ctx.add_local(&value.type_);
+        for idx in idxs.iter().rev() {
+            instructions.push(wasm::Instr::LocalSet(*idx));
+        }
+        for ((idx, val_type), offset) in idxs.iter().zip(val_types(&value.type_)).zip(offsets) {
+            instructions.push(wasm::Instr::LocalGet(ptr));
+            instructions.push(wasm::Instr::LocalGet(*idx));
+            instructions.push(store_instr(val_type, *offset));
+        }
+        ctx.free_local(idxs);
+        Ok(())
+    }
+
+    /// Turns the index in `addr` into the address of that element of the array in `array`,
+    /// trapping if it is out of bounds.
+    fn generate_element_addr(
+        &mut self,
+        array: wasm::LocalIdx,
+        addr: wasm::LocalIdx,
+        elem: &ir::Type,
+        instructions: &mut Vec<wasm::Instr>,
+    ) {
+        let (_, stride) = element_layout(elem);
+        instructions.extend([
+            // Negative indices are out of bounds as unsigned integers.
+            wasm::Instr::LocalGet(addr),
+            wasm::Instr::LocalGet(array),
+            load_instr(wasm::ValType::I32, 0),
+            wasm::Instr::I32GeU,
+            wasm::Instr::IfElse(
+                wasm::BlockType::ValType(None),
+                vec![wasm::Instr::Call(self.helper(Helper::OutOfBounds))],
+                vec![],
+            ),
+            wasm::Instr::LocalGet(array),
+            wasm::Instr::LocalGet(addr),
+            wasm::Instr::I32Const(stride),
+            wasm::Instr::I32Mul,
+            wasm::Instr::I32Add,
+            wasm::Instr::LocalSet(addr),
+        ]);
+    }
+
+    /// Loads the element of type `elem` at the address in `addr`, adding a reference to it.
+    fn generate_load_element(
+        &mut self,
+        addr: wasm::LocalIdx,
+        elem: &ir::Type,
+        instructions: &mut Vec<wasm::Instr>,
+    ) {
+        let (offsets, _) = element_layout(elem);
+        for (j, (val_type, offset)) in val_types(elem).into_iter().zip(offsets).enumerate() {
+            instructions.push(wasm::Instr::LocalGet(addr));
+            instructions.push(load_instr(val_type, ARRAY_START + offset));
+            instructions.extend(self.retain_instr(elem, j));
+        }
+    }
+
+    /// Generates `push(array, value)` after its arguments: makes room in the array for one more
+    /// element, then stores the value after the others. The array was read from the variable
+    /// `moved_from`, if any, which the result is then assigned to.
+    fn generate_push(
+        &mut self,
+        array_type: &ir::Type,
+        moved_from: Option<&str>,
+        span: Span,
+        ctx: &mut Context,
+        instructions: &mut Vec<wasm::Instr>,
+    ) -> Result<()> {
+        let ir::Type::Array(elem) = array_type else {
+            unreachable!("checked by the type checker");
+        };
+        let (offsets, stride) = element_layout(elem);
+        let counted = counted_words(elem, span)?;
+        let value = ctx.add_local(elem);
+        for idx in value.iter().rev() {
+            instructions.push(wasm::Instr::LocalSet(*idx));
+        }
+        let array = ctx.add_local(array_type)[0];
+        let addr = ctx.add_local(&ir::Type::Int)[0];
+        if let Some(name) = moved_from {
+            self.generate_move(name, array, ctx, instructions);
+        }
+        instructions.extend([
+            wasm::Instr::I32Const(stride),
+            wasm::Instr::I32Const(1),
+            wasm::Instr::I32Const(counted),
+            wasm::Instr::Call(self.helper(Helper::Reserve)),
+            wasm::Instr::LocalTee(array),
+            wasm::Instr::LocalGet(array),
+            load_instr(wasm::ValType::I32, 0),
+            wasm::Instr::I32Const(stride),
+            wasm::Instr::I32Mul,
+            wasm::Instr::I32Add,
+            wasm::Instr::LocalSet(addr),
+        ]);
+        for ((idx, val_type), offset) in value.iter().zip(val_types(elem)).zip(offsets) {
+            instructions.extend([
+                wasm::Instr::LocalGet(addr),
+                wasm::Instr::LocalGet(*idx),
+                store_instr(val_type, ARRAY_START + offset),
+            ]);
+        }
+        instructions.extend([
+            wasm::Instr::LocalGet(array),
+            wasm::Instr::LocalGet(array),
+            load_instr(wasm::ValType::I32, 0),
+            wasm::Instr::I32Const(1),
+            wasm::Instr::I32Add,
+            store_instr(wasm::ValType::I32, 0),
+            wasm::Instr::LocalGet(array),
+        ]);
+        ctx.free_local(value);
+        ctx.free_local(vec![array, addr]);
+        Ok(())
+    }
+
+    /// Generates `pop(array)` after its argument: removes the last element of the array, trapping
+    /// if it is empty, and releases it. The array was read from the variable `moved_from`, if any,
+    /// which the result is then assigned to.
+    fn generate_pop(
+        &mut self,
+        array_type: &ir::Type,
+        moved_from: Option<&str>,
+        span: Span,
+        ctx: &mut Context,
+        instructions: &mut Vec<wasm::Instr>,
+    ) -> Result<()> {
+        let ir::Type::Array(elem) = array_type else {
+            unreachable!("checked by the type checker");
+        };
+        let (offsets, stride) = element_layout(elem);
+        let counted = counted_words(elem, span)?;
+        let array = ctx.add_local(array_type)[0];
+        let addr = ctx.add_local(&ir::Type::Int)[0];
+        if let Some(name) = moved_from {
+            self.generate_move(name, array, ctx, instructions);
+        }
+        instructions.extend([
+            wasm::Instr::I32Const(stride),
+            wasm::Instr::I32Const(0),
+            wasm::Instr::I32Const(counted),
+            wasm::Instr::Call(self.helper(Helper::Reserve)),
+            wasm::Instr::LocalTee(array),
+            load_instr(wasm::ValType::I32, 0),
+            wasm::Instr::I32Eqz,
+            wasm::Instr::IfElse(
+                wasm::BlockType::ValType(None),
+                vec![wasm::Instr::Call(self.helper(Helper::OutOfBounds))],
+                vec![],
+            ),
+            wasm::Instr::LocalGet(array),
+            wasm::Instr::LocalGet(array),
+            load_instr(wasm::ValType::I32, 0),
+            wasm::Instr::I32Const(1),
+            wasm::Instr::I32Sub,
+            wasm::Instr::LocalTee(addr),
+            store_instr(wasm::ValType::I32, 0),
+            wasm::Instr::LocalGet(array),
+            wasm::Instr::LocalGet(addr),
+            wasm::Instr::I32Const(stride),
+            wasm::Instr::I32Mul,
+            wasm::Instr::I32Add,
+            wasm::Instr::LocalSet(addr),
+        ]);
+        let offsets: Vec<_> = offsets.iter().map(|offset| ARRAY_START + offset).collect();
+        self.release_field(addr, elem, &offsets, instructions);
+        instructions.push(wasm::Instr::LocalGet(array));
+        ctx.free_local(vec![array, addr]);
+        Ok(())
+    }
+
+    /// Generates the value assigned to `lhs`. In `a = push(a, x)` and `a = pop(a)`, `a` gives its
+    /// reference to the array to `push` or `pop`, which can then change it in place if nothing
+    /// else refers to it.
+    fn generate_assigned<'a>(
+        &mut self,
+        lhs: &str,
+        rhs: &'a ir::Expr,
+        ctx: &mut Context<'a>,
+        instructions: &mut Vec<wasm::Instr>,
+    ) -> Result<()> {
+        let ir::ExprKind::Call { callee, args } = &rhs.kind else {
+            return self.generate_expr(rhs, ctx, instructions);
+        };
+        let ir::ExprKind::Ident(name) = &callee.kind else {
+            return self.generate_expr(rhs, ctx, instructions);
+        };
+        let is_builtin = !self.is_variable(name, ctx) && !self.funcs.contains_key(name);
+        if !is_builtin
+            || !matches!(name.as_str(), "push" | "pop")
+            || !matches!(&args[0].kind, ir::ExprKind::Ident(arg) if arg == lhs)
+        {
+            return self.generate_expr(rhs, ctx, instructions);
+        }
+        for arg in args.iter() {
+            self.generate_expr(arg, ctx, instructions)?;
+        }
+        if name == "push" {
+            self.generate_push(&args[0].type_, Some(lhs), rhs.span, ctx, instructions)
+        } else {
+            self.generate_pop(&args[0].type_, Some(lhs), rhs.span, ctx, instructions)
+        }
+    }
+
+    /// Takes the array on the stack from the variable `name`, which is left holding 0 in place of
+    /// its reference, unless the other arguments have assigned another array to it.
+    fn generate_move(
+        &mut self,
+        name: &str,
+        array: wasm::LocalIdx,
+        ctx: &Context,
+        instructions: &mut Vec<wasm::Instr>,
+    ) {
+        let (get, set) = match ctx.lookup(name) {
+            Some(idxs) => (
+                wasm::Instr::LocalGet(idxs[0]),
+                wasm::Instr::LocalSet(idxs[0]),
+            ),
+            None => {
+                let (_, idxs) = self
+                    .lookup_global(name)
+                    .expect("checked by the type checker");
+                (
+                    wasm::Instr::GlobalGet(idxs[0]),
+                    wasm::Instr::GlobalSet(idxs[0]),
+                )
             }
-            _ => todo!(),
+        };
+        instructions.extend([
+            wasm::Instr::LocalTee(array),
+            get,
+            wasm::Instr::I32Eq,
+            wasm::Instr::IfElse(
+                wasm::BlockType::ValType(None),
+                vec![
+                    wasm::Instr::LocalGet(array),
+                    wasm::Instr::Call(self.helper(Helper::Release)),
+                    wasm::Instr::Drop,
+                    wasm::Instr::I32Const(0),
+                    set,
+                ],
+                vec![],
+            ),
+            wasm::Instr::LocalGet(array),
+        ]);
+    }
+
+    /// Returns the position of the top-level `let` named `name` in `globals`, with its globals.
+    fn lookup_global(&self, name: &str) -> Option<(usize, &[wasm::GlobalIdx])> {
+        self.globals
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, (global_name, _))| global_name == name)
+            .map(|(i, (_, idxs))| (i, idxs.as_slice()))
+    }
+
+    /// Records in `_start` that the top-level `let`s generated so far are initialized.
+    fn generate_initialized(&mut self, instructions: &mut Vec<wasm::Instr>) {
+        instructions.extend([
+            wasm::Instr::I32Const(self.globals.len() as u32),
+            wasm::Instr::GlobalSet(self.initialized),
+        ]);
+    }
+
+    /// Traps if the top-level `let` at position `i` in `globals` isn't initialized yet, unless
+    /// this is `_start`, which only sees the ones it has initialized.
+    fn generate_initialized_check(
+        &mut self,
+        i: usize,
+        ctx: &Context,
+        instructions: &mut Vec<wasm::Instr>,
+    ) {
+        if ctx.is_start {
+            return;
         }
+        instructions.extend([
+            wasm::Instr::I32Const(i as u32),
+            wasm::Instr::GlobalGet(self.initialized),
+            wasm::Instr::I32GeU,
+            wasm::Instr::IfElse(
+                wasm::BlockType::ValType(None),
+                vec![wasm::Instr::Call(self.helper(Helper::Uninitialized))],
+                vec![],
+            ),
+        ]);
+    }
+
+    /// Whether `name` refers to a variable rather than to a function.
+    fn is_variable(&self, name: &str, ctx: &Context) -> bool {
+        ctx.lookup(name).is_some() || self.lookup_global(name).is_some()
+    }
+
+    /// Generates `expr` for its side effects only.
+    fn generate_discarded<'a>(
+        &mut self,
+        expr: &'a ir::Expr,
+        ctx: &mut Context<'a>,
+        instructions: &mut Vec<wasm::Instr>,
+    ) -> Result<()> {
+        self.generate_expr(expr, ctx, instructions)?;
+        self.generate_release(&expr.type_, instructions);
         Ok(())
     }
 }
+
+/// Declares mutable globals to hold a value of type `type_`, starting at zero.
+fn add_globals(type_: &ir::Type, module: &mut wasm::Module) -> Vec<wasm::GlobalIdx> {
+    let mut idxs = vec![];
+    for val_type in val_types(type_) {
+        idxs.push(wasm::GlobalIdx(module.globals.len() as u32));
+        module.globals.push(wasm::Global {
+            r#type: wasm::GlobalType(wasm::Mut::Var, val_type),
+            init: wasm::Expr(vec![zero(val_type)]),
+        });
+    }
+    idxs
+}
+
+/// The Wasm values representing a value of type `type_`.
+/// `Bool`s are `i32`s that are either 0 or 1, and functions, records, values of sum types and arrays
+/// are addresses in memory. Strings are the address of their first byte and their length in bytes,
+/// and tuples are the values of their elements in order.
+fn val_types(type_: &ir::Type) -> Vec<wasm::ValType> {
+    match type_ {
+        ir::Type::Int
+        | ir::Type::Bool
+        | ir::Type::Func(..)
+        | ir::Type::Record(_)
+        | ir::Type::Enum(_)
+        | ir::Type::Array(_) => vec![wasm::ValType::I32],
+        ir::Type::Int64 => vec![wasm::ValType::I64],
+        ir::Type::Float => vec![wasm::ValType::F64],
+        ir::Type::String => vec![wasm::ValType::I32, wasm::ValType::I32],
+        ir::Type::Tuple(types) => types.iter().flat_map(val_types).collect(),
+        ir::Type::Unit | ir::Type::Never => vec![],
+        ir::Type::Unresolved(_) | ir::Type::Untyped | ir::Type::Var(_) => {
+            unreachable!("resolved by the type checker")
+        }
+    }
+}
+
+/// The instruction for `op` on two operands of type `type_`, except for `&&` and `||`, which
+/// short-circuit.
+fn binop_instr(op: ir::BinOp, type_: &ir::Type) -> wasm::Instr {
+    match type_ {
+        ir::Type::Int | ir::Type::Bool => match op {
+            ir::BinOp::Add => wasm::Instr::I32Add,
+            ir::BinOp::Sub => wasm::Instr::I32Sub,
+            ir::BinOp::Mul => wasm::Instr::I32Mul,
+            ir::BinOp::Div => wasm::Instr::I32DivS,
+            ir::BinOp::Rem => wasm::Instr::I32RemS,
+            ir::BinOp::BitAnd => wasm::Instr::I32And,
+            ir::BinOp::BitOr => wasm::Instr::I32Or,
+            ir::BinOp::BitXor => wasm::Instr::I32Xor,
+            ir::BinOp::Shl => wasm::Instr::I32Shl,
+            ir::BinOp::Shr => wasm::Instr::I32ShrS,
+            ir::BinOp::Eq => wasm::Instr::I32Eq,
+            ir::BinOp::Ne => wasm::Instr::I32Ne,
+            ir::BinOp::Lt => wasm::Instr::I32LtS,
+            ir::BinOp::Le => wasm::Instr::I32LeS,
+            ir::BinOp::Gt => wasm::Instr::I32GtS,
+            ir::BinOp::Ge => wasm::Instr::I32GeS,
+            ir::BinOp::And | ir::BinOp::Or => unreachable!(),
+        },
+        ir::Type::Int64 => match op {
+            ir::BinOp::Add => wasm::Instr::I64Add,
+            ir::BinOp::Sub => wasm::Instr::I64Sub,
+            ir::BinOp::Mul => wasm::Instr::I64Mul,
+            ir::BinOp::Div => wasm::Instr::I64DivS,
+            ir::BinOp::Rem => wasm::Instr::I64RemS,
+            ir::BinOp::BitAnd => wasm::Instr::I64And,
+            ir::BinOp::BitOr => wasm::Instr::I64Or,
+            ir::BinOp::BitXor => wasm::Instr::I64Xor,
+            ir::BinOp::Shl => wasm::Instr::I64Shl,
+            ir::BinOp::Shr => wasm::Instr::I64ShrS,
+            ir::BinOp::Eq => wasm::Instr::I64Eq,
+            ir::BinOp::Ne => wasm::Instr::I64Ne,
+            ir::BinOp::Lt => wasm::Instr::I64LtS,
+            ir::BinOp::Le => wasm::Instr::I64LeS,
+            ir::BinOp::Gt => wasm::Instr::I64GtS,
+            ir::BinOp::Ge => wasm::Instr::I64GeS,
+            ir::BinOp::And | ir::BinOp::Or => unreachable!(),
+        },
+        ir::Type::Float => match op {
+            ir::BinOp::Add => wasm::Instr::F64Add,
+            ir::BinOp::Sub => wasm::Instr::F64Sub,
+            ir::BinOp::Mul => wasm::Instr::F64Mul,
+            ir::BinOp::Div => wasm::Instr::F64Div,
+            ir::BinOp::Eq => wasm::Instr::F64Eq,
+            ir::BinOp::Ne => wasm::Instr::F64Ne,
+            ir::BinOp::Lt => wasm::Instr::F64Lt,
+            ir::BinOp::Le => wasm::Instr::F64Le,
+            ir::BinOp::Gt => wasm::Instr::F64Gt,
+            ir::BinOp::Ge => wasm::Instr::F64Ge,
+            _ => unreachable!("rejected by the type checker"),
+        },
+        // `Unit` has no runtime representation, and its only value is equal to itself.
+        ir::Type::Unit => wasm::Instr::I32Const((op == ir::BinOp::Eq) as u32),
+        _ => unreachable!("rejected by the type checker"),
+    }
+}
+
+/// The instruction that converts a number from type `from` to type `to`, if they differ.
+/// Converting a `Float` to an integer rounds towards zero and saturates instead of trapping.
+fn conversion_instr(from: &ir::Type, to: &ir::Type) -> Option<wasm::Instr> {
+    match (from, to) {
+        (ir::Type::Int, ir::Type::Int64) => Some(wasm::Instr::I64ExtendI32S),
+        (ir::Type::Int64, ir::Type::Int) => Some(wasm::Instr::I32WrapI64),
+        (ir::Type::Int, ir::Type::Float) => Some(wasm::Instr::F64ConvertI32S),
+        (ir::Type::Int64, ir::Type::Float) => Some(wasm::Instr::F64ConvertI64S),
+        (ir::Type::Float, ir::Type::Int) => Some(wasm::Instr::I32TruncSatF64S),
+        (ir::Type::Float, ir::Type::Int64) => Some(wasm::Instr::I64TruncSatF64S),
+        _ => None,
+    }
+}
+
+/// Lays out values of the given types in memory from `start`, each Wasm value at an offset aligned
+/// to its size. Returns the offsets of the Wasm values of each type, and the total size, rounded up
+/// so that the next allocation is aligned too.
+fn layout<'t>(types: impl IntoIterator<Item = &'t ir::Type>, start: u32) -> (Vec<Vec<u32>>, u32) {
+    let mut offsets = vec![];
+    let mut size = start;
+    for type_ in types {
+        let mut type_offsets = vec![];
+        for val_type in val_types(type_) {
+            let val_size = val_size(val_type);
+            size = size.next_multiple_of(val_size);
+            type_offsets.push(size);
+            size += val_size;
+        }
+        offsets.push(type_offsets);
+    }
+    (offsets, size.next_multiple_of(8))
+}
+
+/// Whether values of type `type_` are objects in memory, which are freed when the last reference
+/// to them is released.
+fn is_counted(type_: &ir::Type) -> bool {
+    matches!(
+        type_,
+        ir::Type::String
+            | ir::Type::Func(..)
+            | ir::Type::Record(_)
+            | ir::Type::Enum(_)
+            | ir::Type::Array(_)
+    )
+}
+
+/// The layout of a closure capturing `captures`: the table index of its function, the table index
+/// of the function releasing its captured values, then the captured values.
+fn closure_layout(captures: &[(String, ir::Type)]) -> (Vec<Vec<u32>>, u32) {
+    layout(captures.iter().map(|(_, type_)| type_), 8)
+}
+
+/// Whether each Wasm value of a value of type `type_` is the address of a reference-counted
+/// object.
+fn counted_values(type_: &ir::Type) -> Vec<bool> {
+    match type_ {
+        ir::Type::Tuple(types) => types.iter().flat_map(counted_values).collect(),
+        _ => {
+            let counted = is_counted(type_);
+            (0..val_types(type_).len())
+                .map(|j| j == 0 && counted)
+                .collect()
+        }
+    }
+}
+
+/// The layout of an element of an array of `elem`s: the offsets of its values from the start of
+/// the element, and the distance between elements, which keeps them aligned. The elements follow
+/// the length and the capacity of the array, from offset `ARRAY_START`.
+fn element_layout(elem: &ir::Type) -> (Vec<u32>, u32) {
+    let val_types = val_types(elem);
+    let (mut offsets, _) = layout([elem], 0);
+    let offsets = offsets.pop().unwrap();
+    let end = match (offsets.last(), val_types.last()) {
+        (Some(offset), Some(val_type)) => offset + val_size(*val_type),
+        _ => 0,
+    };
+    let align = val_types.into_iter().map(val_size).max().unwrap_or(1);
+    (offsets, end.next_multiple_of(align))
+}
+
+/// The mask of the 4-byte words of an element of an array of `elem`s that hold the address of a
+/// reference-counted object, which `reserve` retains when it copies the elements. `span` is the
+/// `push` or `pop` that needs it.
+fn counted_words(elem: &ir::Type, span: Span) -> Result<u32> {
+    let (offsets, _) = element_layout(elem);
+    let mut mask = 0;
+    for (offset, counted) in offsets.into_iter().zip(counted_values(elem)) {
+        if !counted {
+            continue;
+        }
+        if offset >= 4 * u32::BITS {
+            return Err(
+                Diagnostic::error(format!("arrays of `{}` can't grow or shrink", elem))
+                    .with_code("E0205")
+                    .with_primary(
+                        span,
+                        "the elements refer to objects after their first 128 bytes",
+                    ),
+            );
+        }
+        mask |= 1 << (offset / 4);
+    }
+    Ok(mask)
+}
+
+/// The layout of a variant with fields of types `field_types`: its number, then its fields.
+fn variant_layout(field_types: &[ir::Type]) -> (Vec<Vec<u32>>, u32) {
+    layout(field_types, 4)
+}
+
+/// The position of the field `name` among the fields of a record.
+fn field_index(fields: &[(String, ir::Type)], name: &str) -> usize {
+    fields
+        .iter()
+        .position(|(field, _)| field == name)
+        .expect("checked by the type checker")
+}
+
+fn val_size(val_type: wasm::ValType) -> u32 {
+    match val_type {
+        wasm::ValType::I32 | wasm::ValType::F32 => 4,
+        wasm::ValType::I64 | wasm::ValType::F64 => 8,
+    }
+}
+
+/// The memory argument to access a value of type `val_type` at `offset` from an aligned address.
+fn mem_arg(val_type: wasm::ValType, offset: u32) -> wasm::MemArg {
+    wasm::MemArg {
+        offset,
+        align: val_size(val_type).trailing_zeros(),
+    }
+}
+
+fn load_instr(val_type: wasm::ValType, offset: u32) -> wasm::Instr {
+    let mem_arg = mem_arg(val_type, offset);
+    match val_type {
+        wasm::ValType::I32 => wasm::Instr::I32Load(mem_arg),
+        wasm::ValType::I64 => wasm::Instr::I64Load(mem_arg),
+        wasm::ValType::F32 => wasm::Instr::F32Load(mem_arg),
+        wasm::ValType::F64 => wasm::Instr::F64Load(mem_arg),
+    }
+}
+
+fn store_instr(val_type: wasm::ValType, offset: u32) -> wasm::Instr {
+    let mem_arg = mem_arg(val_type, offset);
+    match val_type {
+        wasm::ValType::I32 => wasm::Instr::I32Store(mem_arg),
+        wasm::ValType::I64 => wasm::Instr::I64Store(mem_arg),
+        wasm::ValType::F32 => wasm::Instr::F32Store(mem_arg),
+        wasm::ValType::F64 => wasm::Instr::F64Store(mem_arg),
+    }
+}
+
+/// The initial value of a global.
+fn zero(val_type: wasm::ValType) -> wasm::Instr {
+    match val_type {
+        wasm::ValType::I32 => wasm::Instr::I32Const(0),
+        wasm::ValType::I64 => wasm::Instr::I64Const(0),
+        wasm::ValType::F32 => wasm::Instr::F32Const(0.0),
+        wasm::ValType::F64 => wasm::Instr::F64Const(0.0),
+    }
+}
+
+fn func_type(params: &[(String, ir::Type)], return_type: &ir::Type) -> wasm::FuncType {
+    let mut param_types = vec![];
+    for (_, param_type) in params.iter() {
+        param_types.extend(val_types(param_type));
+    }
+    wasm::FuncType(
+        wasm::ResultType(param_types),
+        wasm::ResultType(val_types(return_type)),
+    )
+}
+
+/// Checks that a `def` has at most one annotation, and that it is a known one.
+fn def_annotation(annotations: &[ir::Expr]) -> Result<Option<Annotation<'_>>> {
+    let annot = match annotations {
+        [] => return Ok(None),
+        [annot] => annot,
+        [_, second, ..] => {
+            return Err(Diagnostic::error("multiple annotations are not supported")
+                .with_code("E0201")
+                .with_primary(second.span, "second annotation"));
+        }
+    };
+    let ir::ExprKind::Call { callee, args } = &annot.kind else {
+        return Err(unsupported_annotation(annot));
+    };
+    let ir::ExprKind::Ident(name) = &callee.kind else {
+        return Err(unsupported_annotation(annot));
+    };
+    let args: Option<Vec<&str>> = args
+        .iter()
+        .map(|arg| match &arg.kind {
+            ir::ExprKind::StringLit(s) => Some(s.as_str()),
+            _ => None,
+        })
+        .collect();
+    match (name.as_str(), args.as_deref()) {
+        ("export", Some([export_name])) => Ok(Some(Annotation::Export(export_name))),
+        ("import", Some([module, name])) => Ok(Some(Annotation::Import { module, name })),
+        _ => Err(unsupported_annotation(annot)),
+    }
+}
+
+fn unsupported_annotation(annot: &ir::Expr) -> Diagnostic {
+    Diagnostic::error("unsupported annotation")
+        .with_code("E0201")
+        .with_primary(annot.span, "")
+        .with_help(
+            "the supported annotations are `@export(\"name\")` and `@import(\"module\", \"name\")`",
+        )
+}
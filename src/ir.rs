@@ -1,60 +1,401 @@
+use crate::parser::span::Span;
+use std::fmt;
+
+pub use crate::parser::ast::{IntLit, IntSuffix, TypeExpr, TypeExprKind};
+
 #[derive(Debug)]
 pub struct Program {
     pub statements: Vec<Stmt>,
+    /// The lambdas lifted out of the statements by closure conversion.
+    pub lambdas: Vec<Lambda>,
 }
 
+/// A lambda turned into a top-level function, which receives the variables it captures along
+/// with its arguments.
 #[derive(Debug)]
-pub enum Stmt {
+pub struct Lambda {
+    pub captures: Vec<(String, Type)>,
+    pub params: Vec<(String, Type)>,
+    pub body: Expr,
+}
+
+#[derive(Debug, Clone)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum StmtKind {
     Def {
         annotations: Vec<Expr>,
         name: String,
         params: Vec<(String, Type)>,
         return_type: Type,
-        body: Box<Expr>,
+        /// Missing for imported functions.
+        body: Option<Box<Expr>>,
     },
     Let {
         name: String,
+        /// Whether the variable can be assigned to.
+        mutable: bool,
         type_: Type,
         value: Box<Expr>,
     },
+    /// `let (a, b) = value`, where `type_` is the type of the whole tuple.
+    LetTuple {
+        names: Vec<String>,
+        type_: Type,
+        value: Box<Expr>,
+    },
+    Type {
+        name: String,
+        def: TypeDef,
+    },
     Expr(Expr),
 }
 
-#[derive(Debug)]
-pub enum Expr {
+#[derive(Debug, Clone)]
+pub enum TypeDef {
+    /// The fields of a record, in the order they are laid out in memory.
+    Record(Vec<(String, Type)>),
+    /// The variants of a sum type and the types of their fields. Variants are numbered in order.
+    Enum(Vec<(String, Vec<Type>)>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+    /// Filled in by the type checker.
+    pub type_: Type,
+}
+
+#[derive(Debug, Clone)]
+pub enum ExprKind {
     BinOp {
         op: BinOp,
         lhs: Box<Expr>,
         rhs: Box<Expr>,
     },
+    UnOp {
+        op: UnOp,
+        operand: Box<Expr>,
+    },
     Assign {
         lhs: String,
         rhs: Box<Expr>,
     },
     Lambda {
-        params: Vec<String>,
+        /// The parameter types are inferred by the type checker.
+        params: Vec<(String, Type)>,
         body: Box<Expr>,
     },
+    /// Creates a closure for `Program::lambdas[i]`, capturing the variables it uses.
+    Closure(usize),
     Call {
         callee: Box<Expr>,
         args: Vec<Expr>,
     },
+    /// The fields are in the order they are written, which is the order they are evaluated in.
+    Record {
+        fields: Vec<(String, Expr)>,
+        base: Option<Box<Expr>>,
+    },
+    Field {
+        expr: Box<Expr>,
+        name: String,
+    },
+    /// A tuple literal, like `(1, true)`.
+    Tuple(Vec<Expr>),
+    /// An array literal, like `[1, 2, 3]`.
+    Array(Vec<Expr>),
+    /// `expr[index]`, which traps unless `0 <= index < len(expr)`.
+    Index {
+        expr: Box<Expr>,
+        index: Box<Expr>,
+    },
+    /// A value of a sum type, like `Circle(1.0)`. The type checker turns the calls and
+    /// identifiers that name a variant into this.
+    Construct {
+        variant: String,
+        args: Vec<Expr>,
+    },
+    If {
+        cond: Box<Expr>,
+        then: Box<Expr>,
+        else_: Box<Expr>,
+    },
+    While {
+        cond: Box<Expr>,
+        body: Box<Expr>,
+    },
+    /// The arms are checked to be exhaustive and reachable.
+    Match {
+        scrutinee: Box<Expr>,
+        arms: Vec<MatchArm>,
+    },
+    /// `for var in start..end`, where `end` is excluded.
+    For {
+        var: String,
+        start: Box<Expr>,
+        end: Box<Expr>,
+        body: Box<Expr>,
+    },
+    /// `for var in array`, over the elements of `array` in order.
+    ForEach {
+        var: String,
+        array: Box<Expr>,
+        body: Box<Expr>,
+    },
+    Loop {
+        body: Box<Expr>,
+    },
+    Break(Option<Box<Expr>>),
+    Continue,
+    Block {
+        stmts: Vec<Stmt>,
+        value: Option<Box<Expr>>,
+    },
     Ident(String),
-    IntLit(String),
+    IntLit(IntLit),
+    FloatLit(f64),
+    BoolLit(bool),
     StringLit(String),
+    UnitLit,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: Expr,
+}
+
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    pub kind: PatternKind,
+    pub span: Span,
+    /// The type of the values it matches, filled in by the type checker.
+    pub type_: Type,
+}
+
+#[derive(Debug, Clone)]
+pub enum PatternKind {
+    Wildcard,
+    /// Binds the value to a variable. The type checker turns the names of variants without
+    /// fields into `Variant`s.
+    Binding(String),
+    /// An `Int`, `Int64`, `Bool` or `Unit` literal, possibly negated.
+    Literal(Box<Expr>),
+    Variant {
+        name: String,
+        args: Vec<Pattern>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Type {
-    Unresolved(String),
+    /// A type written in the source that the type checker has not looked up yet.
+    Unresolved(Box<TypeExpr>),
     Untyped,
     Unit,
     Int,
+    Int64,
+    Float,
+    Bool,
+    String,
+    /// The type of expressions that never produce a value, like `break`.
+    Never,
+    /// A function with the given parameter and return types.
+    Func(Vec<Type>, Box<Type>),
+    /// A record declared with `type`, by name.
+    Record(String),
+    /// A sum type declared with `type`, by name.
+    Enum(String),
+    /// `(A, B)`, a fixed number of values of possibly different types. `()` is `Unit` instead.
+    Tuple(Vec<Type>),
+    /// `Array[T]`, a growable sequence of values of type `T`.
+    Array(Box<Type>),
+    /// A type that is being inferred, or a type parameter of a generic `let`.
+    Var(TypeVar),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TypeVar(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BinOp {
     Add,
     Sub,
     Mul,
+    Div,
+    Rem,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnOp {
+    Neg,
+    Not,
+}
+
+impl Pattern {
+    /// The variables that the pattern binds, from left to right, with their types and the indices
+    /// of the variant fields that lead to their values.
+    pub fn bindings(&self) -> Vec<(&String, &Type, Vec<usize>)> {
+        let mut bindings = Vec::new();
+        self.collect_bindings(&mut Vec::new(), &mut bindings);
+        bindings
+    }
+
+    fn collect_bindings<'a>(
+        &'a self,
+        path: &mut Vec<usize>,
+        bindings: &mut Vec<(&'a String, &'a Type, Vec<usize>)>,
+    ) {
+        match &self.kind {
+            PatternKind::Binding(name) => bindings.push((name, &self.type_, path.clone())),
+            PatternKind::Variant { args, .. } => {
+                for (i, arg) in args.iter().enumerate() {
+                    path.push(i);
+                    arg.collect_bindings(path, bindings);
+                    path.pop();
+                }
+            }
+            PatternKind::Wildcard | PatternKind::Literal(_) => {}
+        }
+    }
+}
+
+/// The built-in functions that convert between number types, like `float(1)`, and the type they
+/// convert to.
+pub fn conversion_type(name: &str) -> Option<Type> {
+    match name {
+        "int" => Some(Type::Int),
+        "int64" => Some(Type::Int64),
+        "float" => Some(Type::Float),
+        _ => None,
+    }
+}
+
+/// The built-in functions on strings, like `slice(s, 0, 1)`, and their types. `len` also takes an
+/// array.
+pub fn string_func_type(name: &str) -> Option<Type> {
+    match name {
+        "len" => Some(Type::Func(vec![Type::String], Box::new(Type::Int))),
+        "slice" => Some(Type::Func(
+            vec![Type::String, Type::Int, Type::Int],
+            Box::new(Type::String),
+        )),
+        _ => None,
+    }
+}
+
+/// The built-in functions on arrays of `elem`s other than `len`, like `push(a, x)`, and their
+/// types.
+pub fn array_func_type(name: &str, elem: Type) -> Option<Type> {
+    let array = Type::Array(Box::new(elem.clone()));
+    match name {
+        "push" => Some(Type::Func(vec![array.clone(), elem], Box::new(array))),
+        "pop" => Some(Type::Func(vec![array.clone()], Box::new(array))),
+        _ => None,
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Unresolved(type_expr) => write!(f, "{}", type_expr),
+            Type::Untyped => write!(f, "_"),
+            Type::Unit => write!(f, "Unit"),
+            Type::Int => write!(f, "Int"),
+            Type::Int64 => write!(f, "Int64"),
+            Type::Float => write!(f, "Float"),
+            Type::Bool => write!(f, "Bool"),
+            Type::String => write!(f, "String"),
+            Type::Never => write!(f, "!"),
+            Type::Func(params, return_type) => {
+                write!(f, "(")?;
+                for (i, param) in params.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", param)?;
+                }
+                write!(f, ") -> {}", return_type)
+            }
+            Type::Record(name) | Type::Enum(name) => write!(f, "{}", name),
+            Type::Tuple(types) if types.len() == 1 => write!(f, "({},)", types[0]),
+            Type::Tuple(types) => {
+                write!(f, "(")?;
+                for (i, type_) in types.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", type_)?;
+                }
+                write!(f, ")")
+            }
+            Type::Array(elem) => write!(f, "Array[{}]", elem),
+            Type::Var(var) => write!(f, "{}", var),
+        }
+    }
+}
+
+impl fmt::Display for TypeVar {
+    /// Type variables are written `'a` to `'z`, then `'a1` and so on.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let letter = (b'a' + (self.0 % 26) as u8) as char;
+        match self.0 / 26 {
+            0 => write!(f, "'{}", letter),
+            n => write!(f, "'{}{}", letter, n),
+        }
+    }
+}
+
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Rem => "%",
+            BinOp::BitAnd => "&",
+            BinOp::BitOr => "|",
+            BinOp::BitXor => "^",
+            BinOp::Shl => "<<",
+            BinOp::Shr => ">>",
+            BinOp::Eq => "==",
+            BinOp::Ne => "!=",
+            BinOp::Lt => "<",
+            BinOp::Le => "<=",
+            BinOp::Gt => ">",
+            BinOp::Ge => ">=",
+            BinOp::And => "&&",
+            BinOp::Or => "||",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+impl fmt::Display for UnOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnOp::Neg => write!(f, "-"),
+            UnOp::Not => write!(f, "!"),
+        }
+    }
 }
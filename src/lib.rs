@@ -1,6 +1,9 @@
 pub mod ast_to_ir;
+pub mod closure_conversion;
 pub mod codegen;
 pub mod ir;
+pub mod patterns;
+pub mod runtime;
 pub use nio_parser as parser;
 pub mod typecheck;
 pub use nio_wasm as wasm;
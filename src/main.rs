@@ -1,5 +1,10 @@
 use clap::{Parser, Subcommand};
-use nio::{codegen::CodeGenerator, parser, typecheck};
+use nio::{
+    closure_conversion,
+    codegen::{self, CodeGenerator},
+    parser::{self, diagnostic::Diagnostic},
+    typecheck,
+};
 use std::{
     fs::{self, File},
     io::{self, Read},
@@ -39,6 +44,10 @@ enum Command {
         /// Output file
         #[clap(short, long)]
         output: Option<String>,
+
+        /// Run the top-level statements on instantiation instead of exporting `_start`
+        #[clap(long)]
+        start_section: bool,
     },
 }
 
@@ -55,7 +64,11 @@ fn main() -> Result<()> {
             println!("{:?}", result);
         }
 
-        Command::Compile { input, output } => {
+        Command::Compile {
+            input,
+            output,
+            start_section,
+        } => {
             let source = input.as_str();
             let target = &match output {
                 Some(target) => target,
@@ -65,19 +78,24 @@ fn main() -> Result<()> {
             eprintln!("Compile {}", canonicalize(source)?);
             let input = fs::read_to_string(source)?;
 
-            let program = parser::parse(&input).unwrap_or_else(|err| {
-                eprintln!("ParseError: {}", err);
-                process::exit(1);
-            });
+            let (program, errors) = parser::parse_with_errors(&input);
+            if !errors.is_empty() {
+                abort(errors.into_iter().map(Diagnostic::from), &input, source);
+            }
 
             let mut program = program.into();
 
             typecheck::typecheck(&mut program).unwrap_or_else(|err| {
-                eprintln!("TypeError: {}", err);
-                process::exit(1);
+                abort([Diagnostic::from(err)], &input, source);
             });
+            closure_conversion::convert(&mut program);
 
-            let module = CodeGenerator::generate(&program)?;
+            let options = codegen::Options { start_section };
+            let module = CodeGenerator::generate_with_options(&program, options).unwrap_or_else(
+                |diagnostic| {
+                    abort([diagnostic], &input, source);
+                },
+            );
 
             let mut output = File::create(target)?;
             eprintln!("Emit {}", canonicalize(target)?);
@@ -88,6 +106,14 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+/// Prints the diagnostics and exits with a failure status.
+fn abort(diagnostics: impl IntoIterator<Item = Diagnostic>, input: &str, path: &str) -> ! {
+    for diagnostic in diagnostics {
+        eprintln!("{}", diagnostic.render(input, path));
+    }
+    process::exit(1);
+}
+
 #[cfg(not(all(target_arch = "wasm32", target_os = "wasi")))]
 fn canonicalize(path: &str) -> io::Result<String> {
     Ok(format!("{}", fs::canonicalize(path)?.display()))
@@ -0,0 +1,339 @@
+//! Checks that the arms of a `match` cover every value and can all be reached, and compiles them
+//! to a decision tree that tests each part of the value at most once.
+//!
+//! Both work on a matrix with a row of patterns per arm, as described by Luc Maranget in
+//! "Warnings for pattern matching" and "Compiling pattern matching to good decision trees".
+
+use crate::ir::*;
+use std::collections::HashMap;
+
+/// The variants of each sum type, with the types of their fields.
+pub type Enums = HashMap<String, Vec<(String, Vec<Type>)>>;
+
+/// What a pattern requires of the value it matches, apart from its fields.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Ctor {
+    /// The variant numbered `index` of the sum type `type_name`.
+    Variant {
+        type_name: String,
+        index: usize,
+    },
+    Bool(bool),
+    Unit,
+    /// The value of an `Int` or `Int64` literal.
+    Int(i64),
+}
+
+/// How to find the arm that matches a value.
+#[derive(Debug)]
+pub enum Decision {
+    /// Every value that gets here matches this arm.
+    Arm(usize),
+    /// Continues with the case for the constructor of the part of the value at `path`, or with
+    /// `default` for the other constructors. `default` is `None` when the cases cover them all.
+    Switch {
+        path: Vec<usize>,
+        cases: Vec<(Ctor, Decision)>,
+        default: Option<Box<Decision>>,
+    },
+}
+
+/// A pattern without the variables it binds.
+#[derive(Debug, Clone)]
+enum Pat {
+    Any,
+    Ctor(Ctor, Vec<Pat>),
+}
+
+/// The first arm that can't match any value, because the arms before it match all of its values.
+pub fn unreachable_arm(arms: &[MatchArm], enums: &Enums) -> Option<usize> {
+    let mut rows = Vec::new();
+    for (i, arm) in arms.iter().enumerate() {
+        let row = vec![simplify(&arm.pattern, enums)];
+        if !is_useful(&rows, &row, enums) {
+            return Some(i);
+        }
+        rows.push(row);
+    }
+    None
+}
+
+/// A pattern for values that no arm matches, if there are any.
+pub fn missing_pattern(arms: &[MatchArm], enums: &Enums) -> Option<String> {
+    let rows: Vec<_> = arms
+        .iter()
+        .map(|arm| vec![simplify(&arm.pattern, enums)])
+        .collect();
+    let witness = missing(&rows, 1, enums)?;
+    Some(format_pat(&witness[0], enums))
+}
+
+/// Compiles the arms of an exhaustive `match` to a decision tree.
+pub fn decision_tree(arms: &[MatchArm], enums: &Enums) -> Decision {
+    let rows = arms
+        .iter()
+        .enumerate()
+        .map(|(i, arm)| (vec![simplify(&arm.pattern, enums)], i))
+        .collect();
+    compile(rows, vec![Vec::new()], enums)
+}
+
+fn simplify(pattern: &Pattern, enums: &Enums) -> Pat {
+    match &pattern.kind {
+        PatternKind::Wildcard | PatternKind::Binding(_) => Pat::Any,
+        PatternKind::Literal(lit) => Pat::Ctor(literal_ctor(lit), Vec::new()),
+        PatternKind::Variant { name, args } => {
+            let Type::Enum(type_name) = &pattern.type_ else {
+                unreachable!("checked by the type checker");
+            };
+            let index = enums[type_name]
+                .iter()
+                .position(|(variant, _)| variant == name)
+                .expect("checked by the type checker");
+            let ctor = Ctor::Variant {
+                type_name: type_name.clone(),
+                index,
+            };
+            let args = args.iter().map(|arg| simplify(arg, enums)).collect();
+            Pat::Ctor(ctor, args)
+        }
+    }
+}
+
+/// The constructor of a literal pattern. Integers are wrapped to their type, so that `0xffffffff`
+/// and `-1` are the same `Int`.
+fn literal_ctor(lit: &Expr) -> Ctor {
+    match &lit.kind {
+        ExprKind::IntLit(value) => Ctor::Int(int_value(value, &lit.type_)),
+        ExprKind::UnOp {
+            op: UnOp::Neg,
+            operand,
+        } => match (&operand.kind, &operand.type_) {
+            (ExprKind::IntLit(value), Type::Int64) => {
+                Ctor::Int(int_value(value, &Type::Int64).wrapping_neg())
+            }
+            (ExprKind::IntLit(value), type_) => {
+                Ctor::Int((int_value(value, type_) as i32).wrapping_neg() as i64)
+            }
+            _ => unreachable!("the grammar only negates integers in patterns"),
+        },
+        ExprKind::BoolLit(value) => Ctor::Bool(*value),
+        ExprKind::UnitLit => Ctor::Unit,
+        _ => unreachable!("not a literal pattern"),
+    }
+}
+
+fn int_value(lit: &IntLit, type_: &Type) -> i64 {
+    match type_ {
+        Type::Int64 => lit.value as u64 as i64,
+        _ => lit.value as u32 as i32 as i64,
+    }
+}
+
+fn arity(ctor: &Ctor, enums: &Enums) -> usize {
+    match ctor {
+        Ctor::Variant { type_name, index } => enums[type_name][*index].1.len(),
+        Ctor::Bool(_) | Ctor::Unit | Ctor::Int(_) => 0,
+    }
+}
+
+/// All the constructors of the type of `ctor`, if there are finitely many.
+fn signature(ctor: &Ctor, enums: &Enums) -> Option<Vec<Ctor>> {
+    match ctor {
+        Ctor::Variant { type_name, .. } => Some(
+            (0..enums[type_name].len())
+                .map(|index| Ctor::Variant {
+                    type_name: type_name.clone(),
+                    index,
+                })
+                .collect(),
+        ),
+        Ctor::Bool(_) => Some(vec![Ctor::Bool(true), Ctor::Bool(false)]),
+        Ctor::Unit => Some(vec![Ctor::Unit]),
+        Ctor::Int(_) => None,
+    }
+}
+
+/// The constructors that the rows start with, in order of first appearance.
+fn head_ctors<'a>(rows: impl IntoIterator<Item = &'a Vec<Pat>>) -> Vec<Ctor> {
+    let mut ctors = Vec::new();
+    for row in rows {
+        if let Pat::Ctor(ctor, _) = &row[0]
+            && !ctors.contains(ctor)
+        {
+            ctors.push(ctor.clone());
+        }
+    }
+    ctors
+}
+
+/// The signature of the type of the first column, if `ctors` contains all of it.
+fn complete_signature(ctors: &[Ctor], enums: &Enums) -> Option<Vec<Ctor>> {
+    let signature = signature(ctors.first()?, enums)?;
+    signature
+        .iter()
+        .all(|ctor| ctors.contains(ctor))
+        .then_some(signature)
+}
+
+/// The row for the values starting with `ctor`, whose fields replace the first column, or `None`
+/// if the row doesn't match them.
+fn specialize(row: &[Pat], ctor: &Ctor, arity: usize) -> Option<Vec<Pat>> {
+    let mut specialized = match &row[0] {
+        Pat::Ctor(head, args) if head == ctor => args.clone(),
+        Pat::Ctor(..) => return None,
+        Pat::Any => vec![Pat::Any; arity],
+    };
+    specialized.extend_from_slice(&row[1..]);
+    Some(specialized)
+}
+
+/// The row without its first column for the values whose constructor no row starts with, or
+/// `None` if it doesn't match them.
+fn default(row: &[Pat]) -> Option<Vec<Pat>> {
+    match &row[0] {
+        Pat::Any => Some(row[1..].to_vec()),
+        Pat::Ctor(..) => None,
+    }
+}
+
+/// Whether `row` matches a value that none of `rows` matches.
+fn is_useful(rows: &[Vec<Pat>], row: &[Pat], enums: &Enums) -> bool {
+    let Some((head, rest)) = row.split_first() else {
+        return rows.is_empty();
+    };
+    match head {
+        Pat::Ctor(ctor, _) => {
+            let arity = arity(ctor, enums);
+            let rows: Vec<_> = rows
+                .iter()
+                .filter_map(|other| specialize(other, ctor, arity))
+                .collect();
+            is_useful(&rows, &specialize(row, ctor, arity).unwrap(), enums)
+        }
+        Pat::Any => match complete_signature(&head_ctors(rows), enums) {
+            Some(signature) => signature.iter().any(|ctor| {
+                let arity = arity(ctor, enums);
+                let rows: Vec<_> = rows
+                    .iter()
+                    .filter_map(|other| specialize(other, ctor, arity))
+                    .collect();
+                is_useful(&rows, &specialize(row, ctor, arity).unwrap(), enums)
+            }),
+            None => {
+                let rows: Vec<_> = rows.iter().filter_map(|other| default(other)).collect();
+                is_useful(&rows, rest, enums)
+            }
+        },
+    }
+}
+
+/// A row of `width` patterns whose values none of `rows` matches, if there is one.
+fn missing(rows: &[Vec<Pat>], width: usize, enums: &Enums) -> Option<Vec<Pat>> {
+    if width == 0 {
+        return rows.is_empty().then(Vec::new);
+    }
+    let ctors = head_ctors(rows);
+    if let Some(signature) = complete_signature(&ctors, enums) {
+        return signature.into_iter().find_map(|ctor| {
+            let arity = arity(&ctor, enums);
+            let rows: Vec<_> = rows
+                .iter()
+                .filter_map(|row| specialize(row, &ctor, arity))
+                .collect();
+            let mut args = missing(&rows, arity + width - 1, enums)?;
+            let rest = args.split_off(arity);
+            let mut witness = vec![Pat::Ctor(ctor, args)];
+            witness.extend(rest);
+            Some(witness)
+        });
+    }
+    let rows: Vec<_> = rows.iter().filter_map(|row| default(row)).collect();
+    let rest = missing(&rows, width - 1, enums)?;
+    // Name a constructor that no row starts with, unless there are infinitely many.
+    let head = match ctors.first().and_then(|ctor| signature(ctor, enums)) {
+        Some(signature) => {
+            let ctor = signature
+                .into_iter()
+                .find(|ctor| !ctors.contains(ctor))
+                .unwrap();
+            let arity = arity(&ctor, enums);
+            Pat::Ctor(ctor, vec![Pat::Any; arity])
+        }
+        None => Pat::Any,
+    };
+    let mut witness = vec![head];
+    witness.extend(rest);
+    Some(witness)
+}
+
+fn format_pat(pat: &Pat, enums: &Enums) -> String {
+    match pat {
+        Pat::Any => "_".to_string(),
+        Pat::Ctor(Ctor::Variant { type_name, index }, args) => {
+            let name = &enums[type_name][*index].0;
+            if args.is_empty() {
+                return name.clone();
+            }
+            let args: Vec<_> = args.iter().map(|arg| format_pat(arg, enums)).collect();
+            format!("{}({})", name, args.join(", "))
+        }
+        Pat::Ctor(Ctor::Bool(value), _) => value.to_string(),
+        Pat::Ctor(Ctor::Unit, _) => "()".to_string(),
+        Pat::Ctor(Ctor::Int(value), _) => value.to_string(),
+    }
+}
+
+/// Compiles rows of patterns, each with the arm it belongs to, that match the parts of the value
+/// at `paths`.
+fn compile(rows: Vec<(Vec<Pat>, usize)>, paths: Vec<Vec<usize>>, enums: &Enums) -> Decision {
+    let (first, arm) = rows
+        .first()
+        .expect("checked by the type checker to be exhaustive");
+    // Test the first part that the first row needs to know about, so that it is done as soon as
+    // it matches.
+    let Some(column) = first.iter().position(|pat| matches!(pat, Pat::Ctor(..))) else {
+        return Decision::Arm(*arm);
+    };
+    let mut rows = rows;
+    let mut paths = paths;
+    for (row, _) in rows.iter_mut() {
+        row.swap(0, column);
+    }
+    paths.swap(0, column);
+
+    let ctors = head_ctors(rows.iter().map(|(row, _)| row));
+    let complete = complete_signature(&ctors, enums).is_some();
+    let cases = ctors
+        .into_iter()
+        .map(|ctor| {
+            let arity = arity(&ctor, enums);
+            let rows = rows
+                .iter()
+                .filter_map(|(row, arm)| Some((specialize(row, &ctor, arity)?, *arm)))
+                .collect();
+            let mut field_paths: Vec<_> = (0..arity)
+                .map(|i| {
+                    let mut path = paths[0].clone();
+                    path.push(i);
+                    path
+                })
+                .collect();
+            field_paths.extend_from_slice(&paths[1..]);
+            let decision = compile(rows, field_paths, enums);
+            (ctor, decision)
+        })
+        .collect();
+    let default = (!complete).then(|| {
+        let rows = rows
+            .iter()
+            .filter_map(|(row, arm)| Some((default(row)?, *arm)))
+            .collect();
+        Box::new(compile(rows, paths[1..].to_vec(), enums))
+    });
+    Decision::Switch {
+        path: paths[0].clone(),
+        cases,
+        default,
+    }
+}
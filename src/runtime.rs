@@ -0,0 +1,732 @@
+//! Functions that the generated code calls for operations too long to generate inline: the memory
+//! allocator, reference counting and the operations on strings and arrays. The allocator is in
+//! every module, and the other functions are added when a program uses them.
+//!
+//! Objects allocated at run time start with a header of 8 bytes before the address that points to
+//! them: their size, then the number of references to them. Freed objects are kept in a list sorted
+//! by address, whose link replaces the reference count. Adjacent free blocks are merged, and an
+//! allocation takes the first block large enough, splitting off what it doesn't need. String
+//! literals are stored before the heap and have no header.
+
+use crate::wasm;
+
+/// A function of the runtime. Strings are passed as an address and a length in bytes, and the
+/// functions that take them release them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Helper {
+    /// `alloc(size) -> ptr` allocates an object with a single reference, growing the memory if
+    /// needed. It traps if `size` is negative or the memory can't grow.
+    Alloc,
+    /// `free(ptr)` adds an object to the free list, merging it with the free blocks next to it.
+    Free,
+    /// `retain(ptr) -> ptr` adds a reference to an object.
+    Retain,
+    /// `release(ptr) -> i32` removes a reference to an object, and returns 1 if it was the last
+    /// one. The caller then releases what the object refers to and frees it.
+    Release,
+    /// `drop_string(ptr)` releases a string, and frees it if it was the last reference.
+    DropString,
+    /// `drop_closure(ptr)` releases a closure, and frees it with its captured values if it was the
+    /// last reference.
+    DropClosure,
+    /// `concat(ptr1, len1, ptr2, len2) -> (ptr, len)` copies both strings to a new one.
+    Concat,
+    /// `compare(ptr1, len1, ptr2, len2) -> i32` is -1, 0 or 1 as the first string is before,
+    /// equal to or after the second, byte by byte.
+    Compare,
+    /// `slice(ptr, len, start, end) -> (ptr, len)` copies the bytes from `start` to `end` to a new
+    /// string. It traps unless `0 <= start <= end <= len`.
+    Slice,
+    /// `reserve(ptr, stride, extra, counted) -> ptr` returns an array with the elements of an
+    /// array, with a single reference and room for `extra` more elements, reusing it if it can.
+    /// Elements are `stride` bytes apart, and bit `k` of `counted` is set if the 4-byte word at
+    /// offset `4 * k` in an element is the address of a reference-counted object.
+    Reserve,
+    /// `index_out_of_bounds()` traps. Its name shows in the backtrace.
+    OutOfBounds,
+    /// `uninitialized_variable()` traps. Its name shows in the backtrace.
+    Uninitialized,
+}
+
+/// What the runtime functions need to know about the module they are added to.
+pub struct Env<'a> {
+    /// The global holding the address where the next object is allocated if none is free.
+    pub heap: wasm::GlobalIdx,
+    /// The global holding the address of the header of the first free object, or 0.
+    pub free_list: wasm::GlobalIdx,
+    /// The address where the heap starts. Smaller addresses are string literals.
+    pub heap_start: u32,
+    /// The type `(i32) -> ()` of the functions that release the values captured by a closure.
+    pub drop_captures_type: wasm::TypeIdx,
+    /// Returns the index of another runtime function, adding it to the module if needed.
+    pub helper: &'a mut dyn FnMut(Helper) -> wasm::FuncIdx,
+}
+
+impl Helper {
+    /// The name of the function in the `name` section.
+    pub fn name(self) -> &'static str {
+        match self {
+            Helper::Alloc => "alloc",
+            Helper::Free => "free",
+            Helper::Retain => "retain",
+            Helper::Release => "release",
+            Helper::DropString => "drop_string",
+            Helper::DropClosure => "drop_closure",
+            Helper::Concat => "concat",
+            Helper::Compare => "compare",
+            Helper::Slice => "slice",
+            Helper::Reserve => "reserve",
+            Helper::OutOfBounds => "index_out_of_bounds",
+            Helper::Uninitialized => "uninitialized_variable",
+        }
+    }
+
+    pub fn func_type(self) -> wasm::FuncType {
+        use wasm::ValType::I32;
+        let (params, results) = match self {
+            Helper::Alloc | Helper::Retain | Helper::Release => (vec![I32], vec![I32]),
+            Helper::Free | Helper::DropString | Helper::DropClosure => (vec![I32], vec![]),
+            Helper::Concat | Helper::Slice => (vec![I32; 4], vec![I32; 2]),
+            Helper::Compare | Helper::Reserve => (vec![I32; 4], vec![I32]),
+            Helper::OutOfBounds | Helper::Uninitialized => (vec![], vec![]),
+        };
+        wasm::FuncType(wasm::ResultType(params), wasm::ResultType(results))
+    }
+
+    /// The function, with the type at `type_idx`.
+    pub fn func(self, type_idx: wasm::TypeIdx, env: &mut Env) -> wasm::Func {
+        let (local_count, body) = match self {
+            Helper::Alloc => (4, alloc(env)),
+            Helper::Free => (3, free(env)),
+            Helper::Retain => (1, retain(env)),
+            Helper::Release => (2, release(env)),
+            Helper::DropString => (0, drop_string(env)),
+            Helper::DropClosure => (0, drop_closure(env)),
+            Helper::Concat => (2, concat(env)),
+            Helper::Compare => (5, compare(env)),
+            Helper::Slice => (2, slice(env)),
+            Helper::Reserve => (5, reserve(env)),
+            Helper::OutOfBounds | Helper::Uninitialized => (0, vec![wasm::Instr::Unreachable]),
+        };
+        wasm::Func {
+            r#type: type_idx,
+            locals: vec![wasm::ValType::I32; local_count],
+            body: wasm::Expr(body),
+        }
+    }
+}
+
+fn alloc(env: &mut Env) -> Vec<wasm::Instr> {
+    use wasm::Instr::*;
+    let size = local(0);
+    let (prev, block, heap_end, next) = (local(1), local(2), local(3), local(4));
+    let found = vec![
+        // Split off the end of the block if it can hold another one, which takes its place in the
+        // list.
+        LocalGet(block),
+        load(4),
+        LocalSet(next),
+        LocalGet(block),
+        load(0),
+        LocalGet(size),
+        I32Sub,
+        I32Const(16),
+        I32GeU,
+        IfElse(
+            wasm::BlockType::ValType(None),
+            vec![
+                LocalGet(block),
+                I32Const(8),
+                I32Add,
+                LocalGet(size),
+                I32Add,
+                LocalTee(heap_end),
+                LocalGet(block),
+                load(0),
+                LocalGet(size),
+                I32Sub,
+                I32Const(8),
+                I32Sub,
+                store(0),
+                LocalGet(heap_end),
+                LocalGet(next),
+                store(4),
+                LocalGet(heap_end),
+                LocalSet(next),
+                LocalGet(block),
+                LocalGet(size),
+                store(0),
+            ],
+            vec![],
+        ),
+        // Unlink the block from the list.
+        LocalGet(prev),
+        I32Eqz,
+        IfElse(
+            wasm::BlockType::ValType(None),
+            vec![LocalGet(next), GlobalSet(env.free_list)],
+            vec![LocalGet(prev), LocalGet(next), store(4)],
+        ),
+        LocalGet(block),
+        I32Const(1),
+        store(4),
+        LocalGet(block),
+        I32Const(8),
+        I32Add,
+        Return,
+    ];
+    let search = vec![
+        LocalGet(block),
+        I32Eqz,
+        BrIf(wasm::LabelIdx(1)),
+        // Use the first free block that is large enough.
+        LocalGet(block),
+        load(0),
+        LocalGet(size),
+        I32GeU,
+        IfElse(wasm::BlockType::ValType(None), found, vec![]),
+        LocalGet(block),
+        LocalSet(prev),
+        LocalGet(block),
+        load(4),
+        LocalSet(block),
+        Br(wasm::LabelIdx(0)),
+    ];
+    vec![
+        // The host can ask for any size, and sizes of 2 GiB or more don't fit in the memory.
+        LocalGet(size),
+        I32Const(0),
+        I32LtS,
+        IfElse(wasm::BlockType::ValType(None), vec![Unreachable], vec![]),
+        // Keep every object aligned to 8 bytes.
+        LocalGet(size),
+        I32Const(7),
+        I32Add,
+        I32Const(!7),
+        I32And,
+        LocalSet(size),
+        GlobalGet(env.free_list),
+        LocalSet(block),
+        Block(
+            wasm::BlockType::ValType(None),
+            vec![Loop(wasm::BlockType::ValType(None), search)],
+        ),
+        // Allocate a new block at the end of the heap, growing the memory by enough pages.
+        GlobalGet(env.heap),
+        LocalTee(block),
+        I32Const(8),
+        I32Add,
+        LocalGet(size),
+        I32Add,
+        LocalTee(heap_end),
+        // The end of the heap wraps around past 4 GiB.
+        LocalGet(block),
+        I32LtU,
+        IfElse(wasm::BlockType::ValType(None), vec![Unreachable], vec![]),
+        LocalGet(heap_end),
+        MemorySize,
+        I32Const(16),
+        I32Shl,
+        I32GtU,
+        IfElse(
+            wasm::BlockType::ValType(None),
+            vec![
+                LocalGet(heap_end),
+                MemorySize,
+                I32Const(16),
+                I32Shl,
+                I32Sub,
+                I32Const(0xffff),
+                I32Add,
+                I32Const(16),
+                I32ShrU,
+                MemoryGrow,
+                I32Const(u32::MAX),
+                I32Eq,
+                IfElse(wasm::BlockType::ValType(None), vec![Unreachable], vec![]),
+            ],
+            vec![],
+        ),
+        LocalGet(heap_end),
+        GlobalSet(env.heap),
+        LocalGet(block),
+        LocalGet(size),
+        store(0),
+        LocalGet(block),
+        I32Const(1),
+        store(4),
+        LocalGet(block),
+        I32Const(8),
+        I32Add,
+    ]
+}
+
+fn free(env: &mut Env) -> Vec<wasm::Instr> {
+    use wasm::Instr::*;
+    let ptr = local(0);
+    let (block, prev, next) = (local(1), local(2), local(3));
+    // Find the free blocks before and after this one, since the list is sorted by address.
+    let search = vec![
+        LocalGet(next),
+        I32Eqz,
+        LocalGet(next),
+        LocalGet(block),
+        I32GtU,
+        I32Or,
+        BrIf(wasm::LabelIdx(1)),
+        LocalGet(next),
+        LocalSet(prev),
+        LocalGet(next),
+        load(4),
+        LocalSet(next),
+        Br(wasm::LabelIdx(0)),
+    ];
+    vec![
+        LocalGet(ptr),
+        I32Const(8),
+        I32Sub,
+        LocalSet(block),
+        GlobalGet(env.free_list),
+        LocalSet(next),
+        Block(
+            wasm::BlockType::ValType(None),
+            vec![Loop(wasm::BlockType::ValType(None), search)],
+        ),
+        LocalGet(block),
+        LocalGet(next),
+        store(4),
+        LocalGet(prev),
+        I32Eqz,
+        IfElse(
+            wasm::BlockType::ValType(None),
+            vec![LocalGet(block), GlobalSet(env.free_list)],
+            vec![LocalGet(prev), LocalGet(block), store(4)],
+        ),
+        // Merge the block with the next one, then with the previous one, when they are adjacent.
+        LocalGet(next),
+        LocalGet(block),
+        I32Const(8),
+        I32Add,
+        LocalGet(block),
+        load(0),
+        I32Add,
+        I32Eq,
+        IfElse(
+            wasm::BlockType::ValType(None),
+            vec![
+                LocalGet(block),
+                LocalGet(block),
+                load(0),
+                I32Const(8),
+                I32Add,
+                LocalGet(next),
+                load(0),
+                I32Add,
+                store(0),
+                LocalGet(block),
+                LocalGet(next),
+                load(4),
+                store(4),
+            ],
+            vec![],
+        ),
+        LocalGet(prev),
+        I32Const(8),
+        I32Add,
+        LocalGet(prev),
+        load(0),
+        I32Add,
+        LocalGet(block),
+        I32Eq,
+        LocalGet(prev),
+        I32Const(0),
+        I32Ne,
+        I32And,
+        IfElse(
+            wasm::BlockType::ValType(None),
+            vec![
+                LocalGet(prev),
+                LocalGet(prev),
+                load(0),
+                I32Const(8),
+                I32Add,
+                LocalGet(block),
+                load(0),
+                I32Add,
+                store(0),
+                LocalGet(prev),
+                LocalGet(block),
+                load(4),
+                store(4),
+            ],
+            vec![],
+        ),
+    ]
+}
+
+fn retain(env: &mut Env) -> Vec<wasm::Instr> {
+    use wasm::Instr::*;
+    let (ptr, count_addr) = (local(0), local(1));
+    vec![
+        LocalGet(ptr),
+        I32Const(env.heap_start),
+        I32GtU,
+        IfElse(
+            wasm::BlockType::ValType(None),
+            vec![
+                LocalGet(ptr),
+                I32Const(4),
+                I32Sub,
+                LocalTee(count_addr),
+                LocalGet(count_addr),
+                load(0),
+                I32Const(1),
+                I32Add,
+                store(0),
+            ],
+            vec![],
+        ),
+        LocalGet(ptr),
+    ]
+}
+
+fn release(env: &mut Env) -> Vec<wasm::Instr> {
+    use wasm::Instr::*;
+    let (ptr, count_addr, count) = (local(0), local(1), local(2));
+    vec![
+        // Objects start after their header, so even an empty literal at the end of the literals
+        // isn't in the heap.
+        LocalGet(ptr),
+        I32Const(env.heap_start),
+        I32LeU,
+        IfElse(
+            wasm::BlockType::ValType(None),
+            vec![I32Const(0), Return],
+            vec![],
+        ),
+        LocalGet(ptr),
+        I32Const(4),
+        I32Sub,
+        LocalTee(count_addr),
+        LocalGet(count_addr),
+        load(0),
+        I32Const(1),
+        I32Sub,
+        LocalTee(count),
+        store(0),
+        LocalGet(count),
+        I32Eqz,
+    ]
+}
+
+fn drop_string(env: &mut Env) -> Vec<wasm::Instr> {
+    use wasm::Instr::*;
+    let ptr = local(0);
+    vec![
+        LocalGet(ptr),
+        Call((env.helper)(Helper::Release)),
+        IfElse(
+            wasm::BlockType::ValType(None),
+            vec![LocalGet(ptr), Call((env.helper)(Helper::Free))],
+            vec![],
+        ),
+    ]
+}
+
+fn drop_closure(env: &mut Env) -> Vec<wasm::Instr> {
+    use wasm::Instr::*;
+    let ptr = local(0);
+    vec![
+        LocalGet(ptr),
+        Call((env.helper)(Helper::Release)),
+        IfElse(
+            wasm::BlockType::ValType(None),
+            vec![
+                // The second field of a closure is the table index of the function releasing
+                // its captured values.
+                LocalGet(ptr),
+                LocalGet(ptr),
+                load(4),
+                CallIndirect(env.drop_captures_type),
+                LocalGet(ptr),
+                Call((env.helper)(Helper::Free)),
+            ],
+            vec![],
+        ),
+    ]
+}
+
+fn concat(env: &mut Env) -> Vec<wasm::Instr> {
+    use wasm::Instr::*;
+    let (ptr1, len1, ptr2, len2) = (local(0), local(1), local(2), local(3));
+    let (ptr, len) = (local(4), local(5));
+    vec![
+        LocalGet(len1),
+        LocalGet(len2),
+        I32Add,
+        LocalTee(len),
+        Call((env.helper)(Helper::Alloc)),
+        LocalTee(ptr),
+        LocalGet(ptr1),
+        LocalGet(len1),
+        MemoryCopy,
+        LocalGet(ptr),
+        LocalGet(len1),
+        I32Add,
+        LocalGet(ptr2),
+        LocalGet(len2),
+        MemoryCopy,
+        LocalGet(ptr1),
+        Call((env.helper)(Helper::DropString)),
+        LocalGet(ptr2),
+        Call((env.helper)(Helper::DropString)),
+        LocalGet(ptr),
+        LocalGet(len),
+    ]
+}
+
+fn compare(env: &mut Env) -> Vec<wasm::Instr> {
+    use wasm::Instr::*;
+    let (ptr1, len1, ptr2, len2) = (local(0), local(1), local(2), local(3));
+    let (i, min_len, byte1, byte2, result) = (local(4), local(5), local(6), local(7), local(8));
+    let load_byte = |ptr| {
+        [
+            LocalGet(ptr),
+            LocalGet(i),
+            I32Add,
+            I32Load8U(wasm::MemArg {
+                offset: 0,
+                align: 0,
+            }),
+        ]
+    };
+    let mut loop_body = vec![
+        LocalGet(i),
+        LocalGet(min_len),
+        I32GeU,
+        BrIf(wasm::LabelIdx(1)),
+    ];
+    loop_body.extend(load_byte(ptr1));
+    loop_body.push(LocalSet(byte1));
+    loop_body.extend(load_byte(ptr2));
+    loop_body.push(LocalSet(byte2));
+    let mut differ = Vec::from(sign(byte1, byte2));
+    differ.extend([LocalSet(result), Br(wasm::LabelIdx(3))]);
+    loop_body.extend([
+        LocalGet(byte1),
+        LocalGet(byte2),
+        I32Ne,
+        IfElse(wasm::BlockType::ValType(None), differ, vec![]),
+        LocalGet(i),
+        I32Const(1),
+        I32Add,
+        LocalSet(i),
+        Br(wasm::LabelIdx(0)),
+    ]);
+    // The first differing byte decides, and otherwise the shorter string comes first.
+    let mut block = vec![Block(
+        wasm::BlockType::ValType(None),
+        vec![Loop(wasm::BlockType::ValType(None), loop_body)],
+    )];
+    block.extend(sign(len1, len2));
+    block.push(LocalSet(result));
+    vec![
+        LocalGet(len1),
+        LocalGet(len2),
+        LocalGet(len1),
+        LocalGet(len2),
+        I32LtU,
+        Select,
+        LocalSet(min_len),
+        Block(wasm::BlockType::ValType(None), block),
+        LocalGet(ptr1),
+        Call((env.helper)(Helper::DropString)),
+        LocalGet(ptr2),
+        Call((env.helper)(Helper::DropString)),
+        LocalGet(result),
+    ]
+}
+
+fn slice(env: &mut Env) -> Vec<wasm::Instr> {
+    use wasm::Instr::*;
+    let (ptr, len, start, end) = (local(0), local(1), local(2), local(3));
+    let (new_ptr, new_len) = (local(4), local(5));
+    vec![
+        // Negative indices are larger than any length when compared unsigned.
+        LocalGet(start),
+        LocalGet(end),
+        I32GtU,
+        LocalGet(end),
+        LocalGet(len),
+        I32GtU,
+        I32Or,
+        IfElse(
+            wasm::BlockType::ValType(None),
+            vec![Call((env.helper)(Helper::OutOfBounds))],
+            vec![],
+        ),
+        LocalGet(end),
+        LocalGet(start),
+        I32Sub,
+        LocalTee(new_len),
+        Call((env.helper)(Helper::Alloc)),
+        LocalTee(new_ptr),
+        LocalGet(ptr),
+        LocalGet(start),
+        I32Add,
+        LocalGet(new_len),
+        MemoryCopy,
+        LocalGet(ptr),
+        Call((env.helper)(Helper::DropString)),
+        LocalGet(new_ptr),
+        LocalGet(new_len),
+    ]
+}
+
+fn reserve(env: &mut Env) -> Vec<wasm::Instr> {
+    use wasm::Instr::*;
+    let (ptr, stride, extra, counted) = (local(0), local(1), local(2), local(3));
+    let (len, cap, new_ptr, i, end) = (local(4), local(5), local(6), local(7), local(8));
+    // `i` goes through the words of the elements, and `counted` says which ones to retain.
+    let retain_loop = vec![
+        LocalGet(i),
+        LocalGet(end),
+        I32GeU,
+        BrIf(wasm::LabelIdx(1)),
+        LocalGet(counted),
+        LocalGet(i),
+        LocalGet(stride),
+        I32RemU,
+        I32Const(2),
+        I32ShrU,
+        I32ShrU,
+        I32Const(1),
+        I32And,
+        IfElse(
+            wasm::BlockType::ValType(None),
+            vec![
+                LocalGet(new_ptr),
+                LocalGet(i),
+                I32Add,
+                load(8),
+                Call((env.helper)(Helper::Retain)),
+                Drop,
+            ],
+            vec![],
+        ),
+        LocalGet(i),
+        I32Const(4),
+        I32Add,
+        LocalSet(i),
+        Br(wasm::LabelIdx(0)),
+    ];
+    vec![
+        LocalGet(ptr),
+        load(0),
+        LocalSet(len),
+        // An array that nothing else refers to is changed in place if it has room.
+        LocalGet(ptr),
+        I32Const(4),
+        I32Sub,
+        load(0),
+        I32Const(1),
+        I32Eq,
+        LocalGet(len),
+        LocalGet(extra),
+        I32Add,
+        LocalGet(ptr),
+        load(4),
+        I32LeU,
+        I32And,
+        IfElse(
+            wasm::BlockType::ValType(None),
+            vec![LocalGet(ptr), Return],
+            vec![],
+        ),
+        // Otherwise the elements are copied to a new array, with twice the capacity when it grows.
+        LocalGet(len),
+        LocalGet(extra),
+        I32Add,
+        LocalGet(ptr),
+        load(4),
+        LocalGet(extra),
+        I32Mul,
+        I32Const(1),
+        I32Shl,
+        LocalTee(cap),
+        LocalGet(len),
+        LocalGet(extra),
+        I32Add,
+        LocalGet(cap),
+        I32GtU,
+        Select,
+        LocalTee(cap),
+        LocalGet(stride),
+        I32Mul,
+        I32Const(8),
+        I32Add,
+        Call((env.helper)(Helper::Alloc)),
+        LocalTee(new_ptr),
+        LocalGet(len),
+        store(0),
+        LocalGet(new_ptr),
+        LocalGet(cap),
+        store(4),
+        LocalGet(new_ptr),
+        I32Const(8),
+        I32Add,
+        LocalGet(ptr),
+        I32Const(8),
+        I32Add,
+        LocalGet(len),
+        LocalGet(stride),
+        I32Mul,
+        LocalTee(end),
+        MemoryCopy,
+        // The elements move to the new array if the old one is freed, and are shared otherwise.
+        LocalGet(ptr),
+        Call((env.helper)(Helper::Release)),
+        IfElse(
+            wasm::BlockType::ValType(None),
+            vec![LocalGet(ptr), Call((env.helper)(Helper::Free))],
+            vec![
+                LocalGet(counted),
+                IfElse(
+                    wasm::BlockType::ValType(None),
+                    vec![Block(
+                        wasm::BlockType::ValType(None),
+                        vec![Loop(wasm::BlockType::ValType(None), retain_loop)],
+                    )],
+                    vec![],
+                ),
+            ],
+        ),
+        LocalGet(new_ptr),
+    ]
+}
+
+/// `(a > b) - (a < b)` for unsigned `a` and `b`.
+fn sign(a: wasm::LocalIdx, b: wasm::LocalIdx) -> [wasm::Instr; 7] {
+    use wasm::Instr::*;
+    [
+        LocalGet(a),
+        LocalGet(b),
+        I32GtU,
+        LocalGet(a),
+        LocalGet(b),
+        I32LtU,
+        I32Sub,
+    ]
+}
+
+fn load(offset: u32) -> wasm::Instr {
+    wasm::Instr::I32Load(wasm::MemArg { offset, align: 2 })
+}
+
+fn store(offset: u32) -> wasm::Instr {
+    wasm::Instr::I32Store(wasm::MemArg { offset, align: 2 })
+}
+
+fn local(idx: u32) -> wasm::LocalIdx {
+    wasm::LocalIdx(idx)
+}
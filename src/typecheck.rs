@@ -1,88 +1,1974 @@
 use crate::ir::*;
+use crate::parser::ast::{IntLit, IntSuffix};
+use crate::parser::diagnostic::Diagnostic;
+use crate::parser::span::Span;
+use crate::patterns::{self, Enums};
+use std::collections::{HashMap, HashSet};
 use std::{error, fmt};
 
 #[derive(Debug)]
-pub struct TypeError;
+pub enum TypeError {
+    MismatchedTypes {
+        expected: Type,
+        found: Type,
+        span: Span,
+        /// The annotation that the expected type comes from, if any.
+        expected_span: Option<Span>,
+    },
+    IncompatibleArms {
+        then: Type,
+        else_: Type,
+        then_span: Span,
+        else_span: Span,
+    },
+    /// A literal that doesn't fit in `type_`, which is inferred if the literal has no suffix.
+    IntLitOutOfRange {
+        lit: IntLit,
+        type_: Type,
+        span: Span,
+    },
+    OutsideLoop {
+        keyword: &'static str,
+        span: Span,
+    },
+    WrongArgumentCount {
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+    NotCallable {
+        type_: Type,
+        span: Span,
+    },
+    DuplicateDefinition {
+        name: String,
+        span: Span,
+        first_span: Span,
+    },
+    BreakWithValue {
+        span: Span,
+        loop_span: Span,
+    },
+    UndefinedVariable {
+        name: String,
+        span: Span,
+    },
+    InvalidOperands {
+        op: BinOp,
+        lhs: Type,
+        rhs: Type,
+        span: Span,
+    },
+    InvalidOperand {
+        op: UnOp,
+        operand: Type,
+        span: Span,
+    },
+    UnknownType {
+        name: String,
+        span: Span,
+    },
+    /// Type arguments given to a type that isn't generic, like `Int[Bool]`.
+    WrongTypeArgumentCount {
+        name: String,
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+    /// A field that the record doesn't have, or that no record has if its type isn't known.
+    UnknownField {
+        name: String,
+        type_: Option<Type>,
+        span: Span,
+    },
+    MissingFields {
+        type_: Type,
+        fields: Vec<String>,
+        span: Span,
+    },
+    /// A `match` whose arms don't cover the values like `missing`.
+    NonExhaustiveMatch {
+        missing: String,
+        span: Span,
+    },
+    /// An arm whose values are all matched by the arms before it.
+    UnreachableArm {
+        span: Span,
+    },
+    UnknownVariant {
+        name: String,
+        span: Span,
+    },
+    /// A variant built or matched with the wrong number of fields.
+    WrongFieldCount {
+        variant: String,
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+    InvalidConversion {
+        from: Type,
+        to: Type,
+        span: Span,
+    },
+    /// A type variable would have to contain itself, like the type of `f` in `|f| f(f)`.
+    InfiniteType {
+        var: TypeVar,
+        type_: Type,
+        span: Span,
+    },
+    /// An assignment to a variable declared without `mut`, or to a function.
+    AssignToImmutable {
+        name: String,
+        span: Span,
+    },
+    /// An assignment in a lambda to a local variable from outside it, which it only has a copy of.
+    AssignToCaptured {
+        name: String,
+        span: Span,
+    },
+}
 
 impl fmt::Display for TypeError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "-")
+        match self {
+            TypeError::MismatchedTypes { .. } => write!(f, "mismatched types"),
+            TypeError::IncompatibleArms { .. } => {
+                write!(f, "`if` and `else` have incompatible types")
+            }
+            TypeError::IntLitOutOfRange { type_, .. } => {
+                write!(f, "integer literal is out of range for `{}`", type_)
+            }
+            TypeError::OutsideLoop { keyword, .. } => {
+                write!(f, "`{}` outside of a loop", keyword)
+            }
+            TypeError::WrongArgumentCount {
+                expected, found, ..
+            } => {
+                let plural = if *expected == 1 { "" } else { "s" };
+                write!(
+                    f,
+                    "this function takes {} argument{} but {} were supplied",
+                    expected, plural, found
+                )
+            }
+            TypeError::NotCallable { type_, .. } => {
+                write!(f, "expected function, found `{}`", type_)
+            }
+            TypeError::DuplicateDefinition { name, .. } => {
+                write!(f, "the name `{}` is defined multiple times", name)
+            }
+            TypeError::BreakWithValue { .. } => {
+                write!(f, "`break` with a value in a `while` or `for` loop")
+            }
+            TypeError::UndefinedVariable { name, .. } => {
+                write!(f, "cannot find variable `{}`", name)
+            }
+            TypeError::InvalidOperands { op, lhs, rhs, .. } => {
+                write!(f, "cannot apply `{}` to `{}` and `{}`", op, lhs, rhs)
+            }
+            TypeError::InvalidOperand { op, operand, .. } => {
+                write!(f, "cannot apply unary `{}` to `{}`", op, operand)
+            }
+            TypeError::UnknownType { name, .. } => write!(f, "cannot find type `{}`", name),
+            TypeError::WrongTypeArgumentCount {
+                name,
+                expected,
+                found,
+                ..
+            } => {
+                let plural = if *expected == 1 { "" } else { "s" };
+                write!(
+                    f,
+                    "type `{}` takes {} type argument{} but {} were supplied",
+                    name, expected, plural, found
+                )
+            }
+            TypeError::UnknownField { name, type_, .. } => match type_ {
+                Some(type_) => write!(f, "no field `{}` on type `{}`", name, type_),
+                None => write!(f, "no record has a field `{}`", name),
+            },
+            TypeError::MissingFields { type_, fields, .. } => {
+                let fields: Vec<_> = fields.iter().map(|field| format!("`{}`", field)).collect();
+                let plural = if fields.len() == 1 { "" } else { "s" };
+                write!(
+                    f,
+                    "missing field{} {} in `{}`",
+                    plural,
+                    fields.join(", "),
+                    type_
+                )
+            }
+            TypeError::NonExhaustiveMatch { missing, .. } => {
+                write!(f, "non-exhaustive patterns: `{}` not covered", missing)
+            }
+            TypeError::UnreachableArm { .. } => write!(f, "unreachable pattern"),
+            TypeError::UnknownVariant { name, .. } => write!(f, "cannot find variant `{}`", name),
+            TypeError::WrongFieldCount {
+                variant,
+                expected,
+                found,
+                ..
+            } => {
+                let plural = if *expected == 1 { "" } else { "s" };
+                write!(
+                    f,
+                    "variant `{}` has {} field{} but {} were supplied",
+                    variant, expected, plural, found
+                )
+            }
+            TypeError::InvalidConversion { from, to, .. } => {
+                write!(f, "cannot convert `{}` to `{}`", from, to)
+            }
+            TypeError::InfiniteType { var, type_, .. } => {
+                write!(
+                    f,
+                    "cannot construct the infinite type `{} = {}`",
+                    var, type_
+                )
+            }
+            TypeError::AssignToImmutable { name, .. } => {
+                write!(f, "cannot assign to immutable variable `{}`", name)
+            }
+            TypeError::AssignToCaptured { name, .. } => {
+                write!(f, "cannot assign to captured variable `{}`", name)
+            }
+        }
     }
 }
 
 impl error::Error for TypeError {}
 
+impl From<TypeError> for Diagnostic {
+    fn from(err: TypeError) -> Self {
+        let diagnostic = Diagnostic::error(err.to_string());
+        match err {
+            TypeError::MismatchedTypes {
+                expected,
+                found,
+                span,
+                expected_span,
+            } => {
+                let diagnostic = diagnostic
+                    .with_code("E0100")
+                    .with_primary(span, format!("expected `{}`, found `{}`", expected, found));
+                match expected_span {
+                    Some(expected_span) => {
+                        diagnostic.with_secondary(expected_span, "expected because of this")
+                    }
+                    None => diagnostic,
+                }
+            }
+            TypeError::IncompatibleArms {
+                then,
+                else_,
+                then_span,
+                else_span,
+            } => diagnostic
+                .with_code("E0105")
+                .with_primary(else_span, format!("expected `{}`, found `{}`", then, else_))
+                .with_secondary(then_span, format!("this is `{}`", then)),
+            TypeError::IntLitOutOfRange { lit, type_, span } => {
+                let max = int_lit_max(&lit, &type_);
+                // Only decimal literals can be negated without wrapping around.
+                let range = if lit.radix == 10 {
+                    format!("must be between -{} and {}", max + 1, max)
+                } else {
+                    format!("must be at most {:#x}", max)
+                };
+                diagnostic
+                    .with_code("E0101")
+                    .with_primary(span, format!("`{}` literals {}", type_, range))
+            }
+            TypeError::OutsideLoop { span, .. } => diagnostic
+                .with_code("E0106")
+                .with_primary(span, "cannot be used outside of a loop"),
+            TypeError::WrongArgumentCount { expected, span, .. } => diagnostic
+                .with_code("E0108")
+                .with_primary(span, format!("expected {}", expected)),
+            TypeError::NotCallable { span, .. } => diagnostic
+                .with_code("E0109")
+                .with_primary(span, "not a function"),
+            TypeError::DuplicateDefinition {
+                name,
+                span,
+                first_span,
+            } => diagnostic
+                .with_code("E0110")
+                .with_primary(span, format!("`{}` redefined here", name))
+                .with_secondary(first_span, "first defined here"),
+            TypeError::BreakWithValue { span, loop_span } => diagnostic
+                .with_code("E0107")
+                .with_primary(span, "")
+                .with_secondary(loop_span, "this loop can't produce a value")
+                .with_help("use `loop` to break with a value"),
+            TypeError::UndefinedVariable { span, .. } => diagnostic
+                .with_code("E0102")
+                .with_primary(span, "not found in this scope"),
+            TypeError::InvalidOperands { op, span, .. } => diagnostic
+                .with_code("E0103")
+                .with_primary(span, "")
+                .with_note(operator_note(op)),
+            TypeError::InvalidOperand { op, span, .. } => {
+                let note = match op {
+                    UnOp::Neg => "`-` negates an `Int`, `Int64` or `Float`",
+                    UnOp::Not => "`!` negates a `Bool` or flips the bits of an `Int` or `Int64`",
+                };
+                diagnostic
+                    .with_code("E0104")
+                    .with_primary(span, "")
+                    .with_note(note)
+            }
+            TypeError::UnknownType { span, .. } => diagnostic
+                .with_code("E0111")
+                .with_primary(span, "not found")
+                .with_note(
+                    "the built-in types are `Int`, `Int64`, `Float`, `Bool`, `String`, `Unit` and \
+                     `Array[T]`",
+                ),
+            TypeError::WrongTypeArgumentCount { expected, span, .. } => diagnostic
+                .with_code("E0114")
+                .with_primary(span, format!("expected {}", expected)),
+            TypeError::UnknownField { span, .. } => diagnostic
+                .with_code("E0116")
+                .with_primary(span, "unknown field"),
+            TypeError::MissingFields { span, .. } => diagnostic
+                .with_code("E0117")
+                .with_primary(span, "")
+                .with_help("add the missing fields, or copy them from another record with `..`"),
+            TypeError::NonExhaustiveMatch { missing, span } => diagnostic
+                .with_code("E0118")
+                .with_primary(span, format!("pattern `{}` not covered", missing))
+                .with_help("add an arm for it, or a `_` arm that matches any value"),
+            TypeError::UnreachableArm { span } => diagnostic
+                .with_code("E0119")
+                .with_primary(span, "no value reaches this arm")
+                .with_note("the arms above already match all of its values"),
+            TypeError::UnknownVariant { span, .. } => diagnostic
+                .with_code("E0120")
+                .with_primary(span, "not found"),
+            TypeError::WrongFieldCount { expected, span, .. } => diagnostic
+                .with_code("E0121")
+                .with_primary(span, format!("expected {}", expected)),
+            TypeError::InvalidConversion { span, .. } => diagnostic
+                .with_code("E0113")
+                .with_primary(span, "expected a number")
+                .with_note("`int`, `int64` and `float` convert between `Int`, `Int64` and `Float`"),
+            TypeError::InfiniteType { span, .. } => diagnostic
+                .with_code("E0112")
+                .with_primary(span, "cyclic type of infinite size"),
+            TypeError::AssignToImmutable { span, .. } => diagnostic
+                .with_code("E0122")
+                .with_primary(span, "cannot be assigned to")
+                .with_help("declare it with `let mut` to allow assignments"),
+            TypeError::AssignToCaptured { span, .. } => diagnostic
+                .with_code("E0123")
+                .with_primary(span, "assigned in a lambda")
+                .with_note("lambdas capture a copy of the local variables they use"),
+        }
+    }
+}
+
+fn operator_note(op: BinOp) -> &'static str {
+    match op {
+        BinOp::Add => "`+` takes two `Int`s, two `Int64`s, two `Float`s or two `String`s",
+        BinOp::Sub | BinOp::Mul | BinOp::Div => {
+            "arithmetic operators take two `Int`s, two `Int64`s or two `Float`s"
+        }
+        BinOp::Rem | BinOp::Shl | BinOp::Shr => "`%` and shifts take two `Int`s or two `Int64`s",
+        BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor => {
+            "bitwise operators take two `Int`s, two `Int64`s or two `Bool`s"
+        }
+        BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => {
+            "comparison operators take two `Int`s, two `Int64`s, two `Float`s or two `String`s"
+        }
+        BinOp::Eq | BinOp::Ne => {
+            "both sides of an equality must have the same type, which can't be a function, a record, \
+             a sum type, a tuple or an array"
+        }
+        BinOp::And | BinOp::Or => "logical operators take two `Bool`s",
+    }
+}
+
+/// The type of a literal with a suffix. The type of the others is inferred.
+fn int_lit_type(lit: &IntLit) -> Option<Type> {
+    match lit.suffix {
+        None => None,
+        Some(IntSuffix::I32) => Some(Type::Int),
+        Some(IntSuffix::I64) => Some(Type::Int64),
+    }
+}
+
+/// Decimal literals must be within the positive range of `type_`, while
+/// hexadecimal, octal and binary literals may also set the sign bit.
+fn int_lit_max(lit: &IntLit, type_: &Type) -> u128 {
+    let bits = match type_ {
+        Type::Int64 => 64,
+        _ => 32,
+    };
+    if lit.radix == 10 {
+        (1 << (bits - 1)) - 1
+    } else {
+        (1 << bits) - 1
+    }
+}
+
 pub fn typecheck(program: &mut Program) -> Result<(), TypeError> {
     TypeChecker::new().typecheck_program(program)
 }
 
-struct TypeChecker;
+/// The span of the expression that produces the value of `expr`, so that
+/// errors in long blocks point at the last line rather than the whole block.
+fn tail_span(expr: &Expr) -> Span {
+    match &expr.kind {
+        ExprKind::Block {
+            value: Some(value), ..
+        } => tail_span(value),
+        _ => expr.span,
+    }
+}
+
+/// The variables visible at some point, innermost last.
+type Scope = Vec<(String, Scheme)>;
+
+/// The type of a variable, which is generic over `vars` if it was bound by a `let`.
+/// For example, `let id = |x| x` has the type `('a) -> 'a` for all `'a`.
+#[derive(Clone)]
+struct Scheme {
+    vars: Vec<TypeVar>,
+    type_: Type,
+    /// Whether the variable was declared with `let mut`. Those are never generic.
+    mutable: bool,
+}
+
+impl Scheme {
+    /// A type that isn't generic, like the type of a parameter.
+    fn mono(type_: Type) -> Self {
+        Self {
+            vars: Vec::new(),
+            type_,
+            mutable: false,
+        }
+    }
+}
+
+/// Why two types could not be unified.
+enum Mismatch {
+    Types,
+    Infinite(TypeVar, Type),
+}
+
+/// A loop enclosing the expression being checked.
+struct Loop {
+    span: Span,
+    /// Only `loop` can produce a value with `break`, not `while` or `for`.
+    breaks_with_value: bool,
+    /// The type of the `break`s seen so far.
+    break_type: Option<Type>,
+}
+
+struct TypeChecker {
+    /// The types of the top-level `def`s.
+    functions: HashMap<String, Type>,
+    /// The fields of the record types.
+    records: HashMap<String, Vec<(String, Type)>>,
+    /// The record type of each field name. A field of several records belongs to the last one
+    /// declared, like in OCaml.
+    field_records: HashMap<String, String>,
+    /// The variants of the sum types.
+    enums: Enums,
+    /// The sum type of each variant name. Like fields, a variant name refers to the last one
+    /// declared.
+    variant_enums: HashMap<String, String>,
+    /// Where the return type of each `def` is written.
+    return_type_spans: HashMap<String, Span>,
+    loops: Vec<Loop>,
+    /// The variables in `scope[..globals]` are top-level `let`s, which are globals.
+    globals: usize,
+    /// The variables in `scope[..lambda_scope]` are outside the innermost lambda being checked.
+    lambda_scope: usize,
+    /// The type each type variable stands for, if known, and the expression that determined it.
+    bindings: Vec<Option<(Type, Span)>>,
+    /// The type variables of integer literals without a suffix, which stand for `Int` or `Int64`.
+    /// Those that are still unknown at the end are `Int`.
+    int_vars: HashSet<TypeVar>,
+}
 
 impl TypeChecker {
     fn new() -> Self {
-        Self
+        Self {
+            functions: HashMap::new(),
+            records: HashMap::new(),
+            field_records: HashMap::new(),
+            enums: HashMap::new(),
+            variant_enums: HashMap::new(),
+            return_type_spans: HashMap::new(),
+            loops: Vec::new(),
+            globals: 0,
+            lambda_scope: 0,
+            bindings: Vec::new(),
+            int_vars: HashSet::new(),
+        }
     }
 
-    fn resolve_type(&self, type_: &mut Type) -> Result<(), TypeError> {
+    fn fresh_var(&mut self) -> Type {
+        self.bindings.push(None);
+        Type::Var(TypeVar(self.bindings.len() as u32 - 1))
+    }
+
+    /// A type variable for the type of an integer literal.
+    fn fresh_int_var(&mut self) -> Type {
+        let type_ = self.fresh_var();
+        if let Type::Var(var) = type_ {
+            self.int_vars.insert(var);
+        }
+        type_
+    }
+
+    /// Whether `type_` is the type of an integer literal that isn't known yet.
+    fn is_int_var(&self, type_: &Type) -> bool {
+        matches!(self.shallow_resolve(type_), Type::Var(var) if self.int_vars.contains(&var))
+    }
+
+    /// Like `resolve`, but with `Int` for the integer literals whose type isn't known, which is
+    /// what they default to. They allow the same operations as an `Int`.
+    fn default_ints(&self, type_: &Type) -> Type {
+        match self.shallow_resolve(type_) {
+            Type::Var(var) if self.int_vars.contains(&var) => Type::Int,
+            Type::Func(params, return_type) => Type::Func(
+                params
+                    .iter()
+                    .map(|param| self.default_ints(param))
+                    .collect(),
+                Box::new(self.default_ints(&return_type)),
+            ),
+            Type::Tuple(types) => {
+                Type::Tuple(types.iter().map(|type_| self.default_ints(type_)).collect())
+            }
+            Type::Array(elem) => Type::Array(Box::new(self.default_ints(&elem))),
+            type_ => type_,
+        }
+    }
+
+    /// Follows the bindings of `type_` until it is not a bound type variable.
+    fn shallow_resolve(&self, type_: &Type) -> Type {
         match type_ {
-            Type::Unresolved(name) => match name.as_str() {
-                "Int" => {
-                    *type_ = Type::Int;
-                }
-                _ => {}
+            Type::Var(var) => match &self.bindings[var.0 as usize] {
+                Some((bound, _)) => self.shallow_resolve(bound),
+                None => type_.clone(),
             },
+            _ => type_.clone(),
+        }
+    }
+
+    /// Replaces all bound type variables in `type_`.
+    fn resolve(&self, type_: &Type) -> Type {
+        match self.shallow_resolve(type_) {
+            Type::Func(params, return_type) => Type::Func(
+                params.iter().map(|param| self.resolve(param)).collect(),
+                Box::new(self.resolve(&return_type)),
+            ),
+            Type::Tuple(types) => {
+                Type::Tuple(types.iter().map(|type_| self.resolve(type_)).collect())
+            }
+            Type::Array(elem) => Type::Array(Box::new(self.resolve(&elem))),
+            type_ => type_,
+        }
+    }
+
+    /// The expression that determined `type_`, if it was inferred.
+    fn origin(&self, type_: &Type) -> Option<Span> {
+        match type_ {
+            Type::Var(var) => self.bindings[var.0 as usize]
+                .as_ref()
+                .map(|(_, span)| *span),
+            _ => None,
+        }
+    }
+
+    fn occurs(&self, var: TypeVar, type_: &Type) -> bool {
+        match self.shallow_resolve(type_) {
+            Type::Var(other) => other == var,
+            Type::Func(params, return_type) => {
+                params.iter().any(|param| self.occurs(var, param)) || self.occurs(var, &return_type)
+            }
+            Type::Tuple(types) => types.iter().any(|type_| self.occurs(var, type_)),
+            Type::Array(elem) => self.occurs(var, &elem),
+            _ => false,
+        }
+    }
+
+    /// Adds the unbound type variables in `type_` to `vars`.
+    fn free_vars(&self, type_: &Type, vars: &mut Vec<TypeVar>) {
+        match self.shallow_resolve(type_) {
+            Type::Var(var) if !vars.contains(&var) => vars.push(var),
+            Type::Func(params, return_type) => {
+                for param in params.iter() {
+                    self.free_vars(param, vars);
+                }
+                self.free_vars(&return_type, vars);
+            }
+            Type::Tuple(types) => {
+                for type_ in types.iter() {
+                    self.free_vars(type_, vars);
+                }
+            }
+            Type::Array(elem) => self.free_vars(&elem, vars),
             _ => {}
         }
-        Ok(())
     }
 
-    fn typecheck_program(&self, program: &mut Program) -> Result<(), TypeError> {
-        for stmt in program.statements.iter_mut() {
-            self.typecheck_stmt(stmt)?;
+    /// Makes `type_` generic over the type variables that no variable in `scope` depends on.
+    fn generalize(&self, type_: &Type, scope: &Scope) -> Scheme {
+        let mut scope_vars = Vec::new();
+        for (_, scheme) in scope.iter() {
+            let mut vars = Vec::new();
+            self.free_vars(&scheme.type_, &mut vars);
+            scope_vars.extend(vars.into_iter().filter(|var| !scheme.vars.contains(var)));
+        }
+        let mut vars = Vec::new();
+        self.free_vars(type_, &mut vars);
+        // Integer literals have a single type, like other values.
+        vars.retain(|var| !scope_vars.contains(var) && !self.int_vars.contains(var));
+        Scheme {
+            vars,
+            type_: type_.clone(),
+            mutable: false,
+        }
+    }
+
+    /// Returns the type of a use of a variable, with fresh type variables for the generic ones.
+    fn instantiate(&mut self, scheme: &Scheme) -> Type {
+        let substitution: Vec<_> = scheme
+            .vars
+            .iter()
+            .map(|var| (*var, self.fresh_var()))
+            .collect();
+        self.substitute(&scheme.type_, &substitution)
+    }
+
+    fn substitute(&self, type_: &Type, substitution: &[(TypeVar, Type)]) -> Type {
+        match type_ {
+            Type::Var(var) => {
+                if let Some((_, new)) = substitution.iter().find(|(old, _)| old == var) {
+                    return new.clone();
+                }
+                // Bound variables are kept where possible, to remember where their type came from.
+                match &self.bindings[var.0 as usize] {
+                    Some((bound, _))
+                        if substitution.iter().any(|(old, _)| self.occurs(*old, bound)) =>
+                    {
+                        self.substitute(bound, substitution)
+                    }
+                    _ => type_.clone(),
+                }
+            }
+            Type::Func(params, return_type) => Type::Func(
+                params
+                    .iter()
+                    .map(|param| self.substitute(param, substitution))
+                    .collect(),
+                Box::new(self.substitute(return_type, substitution)),
+            ),
+            Type::Tuple(types) => Type::Tuple(
+                types
+                    .iter()
+                    .map(|type_| self.substitute(type_, substitution))
+                    .collect(),
+            ),
+            Type::Array(elem) => Type::Array(Box::new(self.substitute(elem, substitution))),
+            _ => type_.clone(),
+        }
+    }
+
+    /// Checks that `found`, the type of the expression at `span`, is `expected`, inferring the
+    /// type variables in both. `expected_span` is where the expected type comes from, if known.
+    fn unify(
+        &mut self,
+        expected: &Type,
+        found: &Type,
+        span: Span,
+        expected_span: Option<Span>,
+    ) -> Result<(), TypeError> {
+        // A diverging expression can stand for any value, but `() -> !` is not `() -> Int`.
+        if self.shallow_resolve(expected) == Type::Never
+            || self.shallow_resolve(found) == Type::Never
+        {
+            return Ok(());
+        }
+        let expected_span = expected_span.or_else(|| self.origin(expected));
+        match self.unify_inner(expected, found, span) {
+            Ok(()) => Ok(()),
+            Err(Mismatch::Types) => Err(TypeError::MismatchedTypes {
+                expected: self.default_ints(expected),
+                found: self.default_ints(found),
+                span,
+                expected_span,
+            }),
+            Err(Mismatch::Infinite(var, type_)) => Err(TypeError::InfiniteType {
+                var,
+                type_: self.default_ints(&type_),
+                span,
+            }),
+        }
+    }
+
+    fn unify_inner(&mut self, a: &Type, b: &Type, span: Span) -> Result<(), Mismatch> {
+        match (self.shallow_resolve(a), self.shallow_resolve(b)) {
+            (Type::Var(a), Type::Var(b)) if a == b => Ok(()),
+            // The variable of an integer literal stays unbound, so that it keeps its constraint.
+            (Type::Var(var), Type::Var(int_var)) | (Type::Var(int_var), Type::Var(var))
+                if self.int_vars.contains(&int_var) && !self.int_vars.contains(&var) =>
+            {
+                self.bindings[var.0 as usize] = Some((Type::Var(int_var), span));
+                Ok(())
+            }
+            (Type::Var(var), type_) | (type_, Type::Var(var))
+                if self.int_vars.contains(&var)
+                    && !matches!(type_, Type::Int | Type::Int64 | Type::Var(_)) =>
+            {
+                Err(Mismatch::Types)
+            }
+            (Type::Var(var), type_) | (type_, Type::Var(var)) => {
+                if self.occurs(var, &type_) {
+                    return Err(Mismatch::Infinite(var, type_));
+                }
+                self.bindings[var.0 as usize] = Some((type_, span));
+                Ok(())
+            }
+            (Type::Func(a_params, a_return), Type::Func(b_params, b_return)) => {
+                if a_params.len() != b_params.len() {
+                    return Err(Mismatch::Types);
+                }
+                for (a_param, b_param) in a_params.iter().zip(b_params.iter()) {
+                    self.unify_inner(a_param, b_param, span)?;
+                }
+                self.unify_inner(&a_return, &b_return, span)
+            }
+            (Type::Tuple(a_types), Type::Tuple(b_types)) => {
+                if a_types.len() != b_types.len() {
+                    return Err(Mismatch::Types);
+                }
+                for (a_type, b_type) in a_types.iter().zip(b_types.iter()) {
+                    self.unify_inner(a_type, b_type, span)?;
+                }
+                Ok(())
+            }
+            (Type::Array(a_elem), Type::Array(b_elem)) => self.unify_inner(&a_elem, &b_elem, span),
+            (a, b) if a == b => Ok(()),
+            _ => Err(Mismatch::Types),
+        }
+    }
+
+    /// The type of an expression that may have either type, if they are compatible.
+    fn join(&mut self, a: &Type, b: &Type, span: Span) -> Option<Type> {
+        if self.shallow_resolve(a) == Type::Never {
+            return Some(b.clone());
+        }
+        if self.shallow_resolve(b) == Type::Never {
+            return Some(a.clone());
+        }
+        self.unify_inner(a, b, span).ok().map(|()| a.clone())
+    }
+
+    /// Whether `name` refers to a variant, which variables and functions can shadow.
+    fn is_variant(&self, name: &str, scope: &Scope) -> bool {
+        self.variant_enums.contains_key(name)
+            && !scope.iter().any(|(n, _)| n == name)
+            && !self.functions.contains_key(name)
+    }
+
+    /// Whether `callee` is one of the built-in conversions like `float`, which variables and
+    /// functions can shadow.
+    fn is_conversion(&self, callee: &Expr, scope: &Scope) -> bool {
+        self.builtin_name(callee, scope)
+            .is_some_and(|name| conversion_type(name).is_some())
+    }
+
+    /// The type of `callee` if it is one of the built-in functions on strings and arrays like
+    /// `len`, which variables and functions can shadow.
+    fn builtin_func_type(&mut self, callee: &Expr, scope: &Scope) -> Option<Type> {
+        let name = self.builtin_name(callee, scope)?;
+        // `len` takes a string or an array, which is checked once the argument is known.
+        if name == "len" {
+            return Some(Type::Func(vec![self.fresh_var()], Box::new(Type::Int)));
+        }
+        let elem = self.fresh_var();
+        string_func_type(name).or_else(|| array_func_type(name, elem))
+    }
+
+    /// The name of `callee` if it isn't a variable or a function, and so may be a built-in.
+    fn builtin_name<'e>(&self, callee: &'e Expr, scope: &Scope) -> Option<&'e str> {
+        match &callee.kind {
+            ExprKind::Ident(name)
+                if !scope.iter().any(|(n, _)| n == name) && !self.functions.contains_key(name) =>
+            {
+                Some(name)
+            }
+            _ => None,
+        }
+    }
+
+    /// Infers the types of operands that are not known yet, like `x` in `|x| x + 1`. Operands
+    /// of the wrong type are reported by `binop_type` instead.
+    fn infer_operand_types(&mut self, op: BinOp, lhs: &Type, rhs: &Type, span: Span) {
+        let _ = match op {
+            // Both sides have the same type, which is `Int` if neither side is known. The type of
+            // integer literals is left open, since they may still become `Int64`s.
+            BinOp::Add
+            | BinOp::Sub
+            | BinOp::Mul
+            | BinOp::Div
+            | BinOp::Rem
+            | BinOp::Shl
+            | BinOp::Shr
+            | BinOp::BitAnd
+            | BinOp::BitOr
+            | BinOp::BitXor
+            | BinOp::Lt
+            | BinOp::Le
+            | BinOp::Gt
+            | BinOp::Ge => {
+                let same = self.unify_inner(lhs, rhs, span);
+                if self.is_int_var(lhs) {
+                    same
+                } else {
+                    same.and(self.unify_inner(&Type::Int, lhs, span))
+                }
+            }
+            BinOp::Eq | BinOp::Ne => self.unify_inner(lhs, rhs, span),
+            BinOp::And | BinOp::Or => self
+                .unify_inner(&Type::Bool, lhs, span)
+                .and(self.unify_inner(&Type::Bool, rhs, span)),
+        };
+    }
+
+    fn resolve_type(&self, type_: &mut Type) -> Result<(), TypeError> {
+        if let Type::Unresolved(type_expr) = type_ {
+            *type_ = self.resolve_type_expr(type_expr)?;
         }
         Ok(())
     }
 
-    fn typecheck_stmt(&self, stmt: &mut Stmt) -> Result<(), TypeError> {
-        match stmt {
-            Stmt::Def {
-                annotations: _,
-                name: _,
+    fn resolve_type_expr(&self, type_expr: &TypeExpr) -> Result<Type, TypeError> {
+        match &type_expr.kind {
+            TypeExprKind::Name(name) if self.records.contains_key(name) => {
+                Ok(Type::Record(name.clone()))
+            }
+            TypeExprKind::Name(name) if self.enums.contains_key(name) => {
+                Ok(Type::Enum(name.clone()))
+            }
+            TypeExprKind::Name(name) if name == "Array" => Err(TypeError::WrongTypeArgumentCount {
+                name: name.clone(),
+                expected: 1,
+                found: 0,
+                span: type_expr.span,
+            }),
+            TypeExprKind::Name(name) => builtin_type(name).ok_or_else(|| TypeError::UnknownType {
+                name: name.clone(),
+                span: type_expr.span,
+            }),
+            TypeExprKind::Func {
                 params,
                 return_type,
-                body,
             } => {
+                let params = params
+                    .iter()
+                    .map(|param| self.resolve_type_expr(param))
+                    .collect::<Result<_, _>>()?;
+                let return_type = self.resolve_type_expr(return_type)?;
+                Ok(Type::Func(params, Box::new(return_type)))
+            }
+            TypeExprKind::Tuple(types) if types.is_empty() => Ok(Type::Unit),
+            TypeExprKind::Tuple(types) => Ok(Type::Tuple(
+                types
+                    .iter()
+                    .map(|type_| self.resolve_type_expr(type_))
+                    .collect::<Result<_, _>>()?,
+            )),
+            TypeExprKind::App { name, args } if name == "Array" => match &args[..] {
+                [elem] => Ok(Type::Array(Box::new(self.resolve_type_expr(elem)?))),
+                _ => Err(TypeError::WrongTypeArgumentCount {
+                    name: name.clone(),
+                    expected: 1,
+                    found: args.len(),
+                    span: type_expr.span,
+                }),
+            },
+            // `Array` is the only generic type.
+            TypeExprKind::App { name, args } => {
+                if builtin_type(name).is_some()
+                    || name == "Array"
+                    || self.records.contains_key(name)
+                    || self.enums.contains_key(name)
+                {
+                    Err(TypeError::WrongTypeArgumentCount {
+                        name: name.clone(),
+                        expected: 0,
+                        found: args.len(),
+                        span: type_expr.span,
+                    })
+                } else {
+                    Err(TypeError::UnknownType {
+                        name: name.clone(),
+                        span: type_expr.span,
+                    })
+                }
+            }
+        }
+    }
+
+    fn typecheck_program(&mut self, program: &mut Program) -> Result<(), TypeError> {
+        // Types can be used before they are declared, and refer to each other.
+        let mut type_spans = HashMap::new();
+        for stmt in program.statements.iter() {
+            if let StmtKind::Type { name, def } = &stmt.kind {
+                if let Some(&first_span) = type_spans.get(name) {
+                    return Err(TypeError::DuplicateDefinition {
+                        name: name.clone(),
+                        span: stmt.span,
+                        first_span,
+                    });
+                }
+                type_spans.insert(name.clone(), stmt.span);
+                match def {
+                    TypeDef::Record(_) => {
+                        self.records.insert(name.clone(), Vec::new());
+                    }
+                    TypeDef::Enum(_) => {
+                        self.enums.insert(name.clone(), Vec::new());
+                    }
+                }
+            }
+        }
+        for stmt in program.statements.iter_mut() {
+            let StmtKind::Type { name, def } = &mut stmt.kind else {
+                continue;
+            };
+            if let TypeDef::Enum(variants) = def {
+                let mut variant_spans: HashMap<String, Span> = HashMap::new();
+                for (variant, field_types) in variants.iter_mut() {
+                    if let Some(&first_span) = variant_spans.get(variant) {
+                        return Err(TypeError::DuplicateDefinition {
+                            name: variant.clone(),
+                            span: stmt.span,
+                            first_span,
+                        });
+                    }
+                    variant_spans.insert(variant.clone(), stmt.span);
+                    for field_type in field_types.iter_mut() {
+                        self.resolve_type(field_type)?;
+                    }
+                    self.variant_enums.insert(variant.clone(), name.clone());
+                }
+                self.enums.insert(name.clone(), variants.clone());
+            }
+            if let TypeDef::Record(fields) = def {
+                let mut field_spans = HashMap::new();
+                for (field_name, field_type) in fields.iter_mut() {
+                    let span = match field_type {
+                        Type::Unresolved(type_expr) => type_expr.span,
+                        _ => stmt.span,
+                    };
+                    if let Some(&first_span) = field_spans.get(field_name) {
+                        return Err(TypeError::DuplicateDefinition {
+                            name: field_name.clone(),
+                            span,
+                            first_span,
+                        });
+                    }
+                    field_spans.insert(field_name.clone(), span);
+                    self.resolve_type(field_type)?;
+                    self.field_records.insert(field_name.clone(), name.clone());
+                }
+                self.records.insert(name.clone(), fields.clone());
+            }
+        }
+
+        // Functions can be called before they are defined.
+        let mut spans = HashMap::new();
+        for stmt in program.statements.iter_mut() {
+            if let StmtKind::Def {
+                name,
+                params,
+                return_type,
+                ..
+            } = &mut stmt.kind
+            {
+                if let Some(&first_span) = spans.get(name) {
+                    return Err(TypeError::DuplicateDefinition {
+                        name: name.clone(),
+                        span: stmt.span,
+                        first_span,
+                    });
+                }
+                spans.insert(name.clone(), stmt.span);
                 for (_, param_type) in params.iter_mut() {
                     self.resolve_type(param_type)?;
                 }
+                if let Type::Unresolved(type_expr) = return_type {
+                    self.return_type_spans.insert(name.clone(), type_expr.span);
+                }
                 self.resolve_type(return_type)?;
-                self.typecheck_expr(body)?;
+                let param_types = params.iter().map(|(_, type_)| type_.clone()).collect();
+                let type_ = Type::Func(param_types, Box::new(return_type.clone()));
+                self.functions.insert(name.clone(), type_);
             }
-            Stmt::Let {
-                name: _,
+        }
+
+        // Top-level `let`s are only visible to the statements that follow them.
+        let mut scope = Scope::new();
+        for stmt in program.statements.iter_mut() {
+            self.globals = scope.len();
+            self.typecheck_stmt(stmt, &mut scope)?;
+        }
+        for stmt in program.statements.iter_mut() {
+            self.resolve_stmt(stmt)?;
+        }
+        Ok(())
+    }
+
+    fn typecheck_stmt(&mut self, stmt: &mut Stmt, scope: &mut Scope) -> Result<(), TypeError> {
+        match &mut stmt.kind {
+            StmtKind::Def {
+                name,
+                params,
+                return_type,
+                body,
+                ..
+            } => {
+                // The types were resolved along with the other signatures.
+                if let Some(body) = body {
+                    let mut scope = scope.clone();
+                    scope.extend(
+                        params
+                            .iter()
+                            .map(|(name, type_)| (name.clone(), Scheme::mono(type_.clone()))),
+                    );
+                    let body_type = self.typecheck_expr(body, &mut scope)?;
+                    let return_type_span = self.return_type_spans.get(name).copied();
+                    self.check_annotation(body, &body_type, return_type, return_type_span)?;
+                }
+            }
+            StmtKind::Let {
+                name,
+                mutable,
+                type_,
+                value,
+            } => {
+                let type_span = match type_ {
+                    Type::Unresolved(type_expr) => Some(type_expr.span),
+                    _ => None,
+                };
+                self.resolve_type(type_)?;
+                let value_type = self.typecheck_expr(value, scope)?;
+                if *type_ == Type::Untyped {
+                    *type_ = value_type;
+                } else {
+                    self.check_annotation(value, &value_type, type_, type_span)?;
+                }
+                // Only functions are generic, so that other values are computed with a single type.
+                // A variable that can be assigned to has a single type too.
+                let scheme = match value.kind {
+                    _ if *mutable => Scheme {
+                        mutable: true,
+                        ..Scheme::mono(type_.clone())
+                    },
+                    ExprKind::Lambda { .. } | ExprKind::Ident(_) => self.generalize(type_, scope),
+                    _ => Scheme::mono(type_.clone()),
+                };
+                scope.push((name.clone(), scheme));
+            }
+            StmtKind::LetTuple {
+                names,
                 type_,
                 value,
             } => {
+                let type_span = match type_ {
+                    Type::Unresolved(type_expr) => Some(type_expr.span),
+                    _ => None,
+                };
                 self.resolve_type(type_)?;
-                self.typecheck_expr(value)?;
+                let value_type = self.typecheck_expr(value, scope)?;
+                if *type_ != Type::Untyped {
+                    self.check_annotation(value, &value_type, type_, type_span)?;
+                }
+                // A tuple with one element for each name.
+                let types: Vec<_> = names.iter().map(|_| self.fresh_var()).collect();
+                *type_ = Type::Tuple(types.clone());
+                self.check_annotation(value, &value_type, type_, None)?;
+                for (name, type_) in names.iter().zip(types) {
+                    scope.push((name.clone(), Scheme::mono(type_)));
+                }
+            }
+            // Checked along with the signatures.
+            StmtKind::Type { .. } => {}
+            StmtKind::Expr(expr) => {
+                self.typecheck_expr(expr, scope)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn expect_type(
+        &mut self,
+        expr: &mut Expr,
+        expected: &Type,
+        scope: &mut Scope,
+    ) -> Result<(), TypeError> {
+        let found = self.typecheck_expr(expr, scope)?;
+        self.unify(expected, &found, expr.span, None)
+    }
+
+    /// The record type that the field `name` belongs to, when the type of the record isn't known.
+    fn field_record(&self, name: &str, span: Span) -> Result<String, TypeError> {
+        self.field_records
+            .get(name)
+            .cloned()
+            .ok_or_else(|| TypeError::UnknownField {
+                name: name.to_string(),
+                type_: None,
+                span,
+            })
+    }
+
+    /// Checks that `expr` has the type written in an annotation at `annotation_span`.
+    fn check_annotation(
+        &mut self,
+        expr: &Expr,
+        found: &Type,
+        expected: &Type,
+        annotation_span: Option<Span>,
+    ) -> Result<(), TypeError> {
+        self.unify(expected, found, tail_span(expr), annotation_span)
+    }
+
+    /// Checks the body of a loop, whose value is discarded, and returns the type of its `break`s.
+    fn typecheck_loop_body(
+        &mut self,
+        body: &mut Expr,
+        span: Span,
+        breaks_with_value: bool,
+        scope: &mut Scope,
+    ) -> Result<Option<Type>, TypeError> {
+        self.loops.push(Loop {
+            span,
+            breaks_with_value,
+            break_type: None,
+        });
+        let result = self.typecheck_expr(body, scope);
+        let loop_ = self.loops.pop().unwrap();
+        result?;
+        Ok(loop_.break_type)
+    }
+
+    /// Returns the type of `expr` and records it in `expr.type_`.
+    fn typecheck_expr(&mut self, expr: &mut Expr, scope: &mut Scope) -> Result<Type, TypeError> {
+        // Variants are built like calls, or named alone if they have no fields.
+        match &mut expr.kind {
+            ExprKind::Call { callee, args } => {
+                if let ExprKind::Ident(variant) = &callee.kind
+                    && self.is_variant(variant, scope)
+                {
+                    expr.kind = ExprKind::Construct {
+                        variant: variant.clone(),
+                        args: std::mem::take(args),
+                    };
+                }
+            }
+            ExprKind::Ident(variant) if self.is_variant(variant, scope) => {
+                expr.kind = ExprKind::Construct {
+                    variant: variant.clone(),
+                    args: Vec::new(),
+                };
+            }
+            _ => {}
+        }
+        let type_ = match &mut expr.kind {
+            ExprKind::BinOp { op, lhs, rhs } => {
+                let lhs_type = self.typecheck_expr(lhs, scope)?;
+                let rhs_type = self.typecheck_expr(rhs, scope)?;
+                self.infer_operand_types(*op, &lhs_type, &rhs_type, expr.span);
+                let int_var = self.is_int_var(&lhs_type);
+                let lhs_type = self.default_ints(&lhs_type);
+                let rhs_type = self.default_ints(&rhs_type);
+                match binop_type(*op, &lhs_type, &rhs_type) {
+                    // `1 + 2` has the type of its operands, which may still become `Int64`.
+                    Some(Type::Int) if int_var => self.resolve(&lhs.type_),
+                    Some(type_) => type_,
+                    None => {
+                        return Err(TypeError::InvalidOperands {
+                            op: *op,
+                            lhs: lhs_type,
+                            rhs: rhs_type,
+                            span: expr.span,
+                        });
+                    }
+                }
+            }
+            ExprKind::UnOp { op, operand } => {
+                let operand_type = match (*op, &operand.kind) {
+                    // `-2147483648` is fine even though `2147483648` is not. Literals without a
+                    // suffix are checked once their type is known, in `check_int_lits`.
+                    (UnOp::Neg, ExprKind::IntLit(lit))
+                        if let Some(type_) = int_lit_type(lit)
+                            && lit.radix == 10
+                            && lit.value == int_lit_max(lit, &type_) + 1 =>
+                    {
+                        operand.type_ = type_;
+                        operand.type_.clone()
+                    }
+                    _ => self.typecheck_expr(operand, scope)?,
+                };
+                // `-x` is an `Int` and `!x` a `Bool` unless the type of `x` is already known, or
+                // is the type of an integer literal, which may still become `Int64`. Mismatches
+                // are reported below.
+                let default_type = match op {
+                    UnOp::Neg => Type::Int,
+                    UnOp::Not => Type::Bool,
+                };
+                if !self.is_int_var(&operand_type) {
+                    let _ = self.unify_inner(&default_type, &operand_type, operand.span);
+                }
+                match (*op, self.default_ints(&operand_type)) {
+                    (UnOp::Neg, Type::Int | Type::Int64 | Type::Float)
+                    | (UnOp::Not, Type::Int | Type::Int64 | Type::Bool) => {
+                        self.resolve(&operand_type)
+                    }
+                    (_, operand_type) => {
+                        return Err(TypeError::InvalidOperand {
+                            op: *op,
+                            operand: operand_type,
+                            span: expr.span,
+                        });
+                    }
+                }
+            }
+            ExprKind::If { cond, then, else_ } => {
+                self.expect_type(cond, &Type::Bool, scope)?;
+                let then_type = self.typecheck_expr(then, scope)?;
+                let else_type = self.typecheck_expr(else_, scope)?;
+                match self.join(&then_type, &else_type, else_.span) {
+                    Some(type_) => type_,
+                    None => {
+                        return Err(TypeError::IncompatibleArms {
+                            then: self.default_ints(&then_type),
+                            else_: self.default_ints(&else_type),
+                            then_span: then.span,
+                            else_span: else_.span,
+                        });
+                    }
+                }
+            }
+            ExprKind::While { cond, body } => {
+                self.expect_type(cond, &Type::Bool, scope)?;
+                self.typecheck_loop_body(body, expr.span, false, scope)?;
+                Type::Unit
+            }
+            ExprKind::For {
+                var,
+                start,
+                end,
+                body,
+            } => {
+                self.expect_type(start, &Type::Int, scope)?;
+                self.expect_type(end, &Type::Int, scope)?;
+                scope.push((var.clone(), Scheme::mono(Type::Int)));
+                let result = self.typecheck_loop_body(body, expr.span, false, scope);
+                scope.pop();
+                result?;
+                Type::Unit
+            }
+            ExprKind::ForEach { var, array, body } => {
+                let elem = self.fresh_var();
+                let array_type = Type::Array(Box::new(elem.clone()));
+                self.expect_type(array, &array_type, scope)?;
+                scope.push((var.clone(), Scheme::mono(elem)));
+                let result = self.typecheck_loop_body(body, expr.span, false, scope);
+                scope.pop();
+                result?;
+                Type::Unit
+            }
+            ExprKind::Loop { body } => {
+                // A `loop` without `break` never ends.
+                self.typecheck_loop_body(body, expr.span, true, scope)?
+                    .unwrap_or(Type::Never)
+            }
+            ExprKind::Break(value) => {
+                let value_type = match value {
+                    Some(value) => Some((self.typecheck_expr(value, scope)?, value.span)),
+                    None => None,
+                };
+                let Some(loop_) = self.loops.last() else {
+                    return Err(TypeError::OutsideLoop {
+                        keyword: "break",
+                        span: expr.span,
+                    });
+                };
+                let (value_type, value_span) = match value_type {
+                    Some(_) if !loop_.breaks_with_value => {
+                        return Err(TypeError::BreakWithValue {
+                            span: expr.span,
+                            loop_span: loop_.span,
+                        });
+                    }
+                    Some((value_type, value_span)) => (value_type, value_span),
+                    None => (Type::Unit, expr.span),
+                };
+                let break_type = match loop_.break_type.clone() {
+                    None => value_type,
+                    Some(break_type) => match self.join(&break_type, &value_type, value_span) {
+                        Some(type_) => type_,
+                        None => {
+                            return Err(TypeError::MismatchedTypes {
+                                expected: self.default_ints(&break_type),
+                                found: self.default_ints(&value_type),
+                                span: value_span,
+                                expected_span: None,
+                            });
+                        }
+                    },
+                };
+                self.loops.last_mut().unwrap().break_type = Some(break_type);
+                Type::Never
+            }
+            ExprKind::Continue => {
+                if self.loops.is_empty() {
+                    return Err(TypeError::OutsideLoop {
+                        keyword: "continue",
+                        span: expr.span,
+                    });
+                }
+                Type::Never
+            }
+            ExprKind::Block { stmts, value } => {
+                let outer_scope = scope.len();
+                // A block that can't reach its end has no value.
+                let mut diverges = false;
+                for stmt in stmts.iter_mut() {
+                    self.typecheck_stmt(stmt, scope)?;
+                    diverges |= match &stmt.kind {
+                        StmtKind::Let { value, .. } | StmtKind::LetTuple { value, .. } => {
+                            self.shallow_resolve(&value.type_) == Type::Never
+                        }
+                        StmtKind::Expr(expr) => self.shallow_resolve(&expr.type_) == Type::Never,
+                        StmtKind::Def { .. } | StmtKind::Type { .. } => false,
+                    };
+                }
+                let type_ = match value {
+                    Some(value) => self.typecheck_expr(value, scope)?,
+                    None if diverges => Type::Never,
+                    None => Type::Unit,
+                };
+                scope.truncate(outer_scope);
+                type_
+            }
+            ExprKind::Call { callee, args } if self.is_conversion(callee, scope) => {
+                let ExprKind::Ident(name) = &callee.kind else {
+                    unreachable!()
+                };
+                let to = conversion_type(name).unwrap();
+                if args.len() != 1 {
+                    return Err(TypeError::WrongArgumentCount {
+                        expected: 1,
+                        found: args.len(),
+                        span: expr.span,
+                    });
+                }
+                let arg = &mut args[0];
+                let from = self.typecheck_expr(arg, scope)?;
+                // Convert from an `Int` unless the type of the argument is already known.
+                let _ = self.unify_inner(&Type::Int, &from, arg.span);
+                let from = self.resolve(&from);
+                if !matches!(from, Type::Int | Type::Int64 | Type::Float) {
+                    return Err(TypeError::InvalidConversion {
+                        from,
+                        to,
+                        span: arg.span,
+                    });
+                }
+                callee.type_ = Type::Func(vec![from], Box::new(to.clone()));
+                to
+            }
+            ExprKind::Call { callee, args } => {
+                let callee_type = match self.builtin_func_type(callee, scope) {
+                    // The built-in functions can only be called, not used as values.
+                    Some(type_) => {
+                        callee.type_ = type_.clone();
+                        type_
+                    }
+                    None => self.typecheck_expr(callee, scope)?,
+                };
+                let (param_types, return_type) = match self.shallow_resolve(&callee_type) {
+                    Type::Func(param_types, return_type) => (param_types, return_type),
+                    // The callee is a parameter of a lambda, like `f` in `|f| f(1)`.
+                    Type::Var(var) if !self.int_vars.contains(&var) => {
+                        let param_types: Vec<_> = args.iter().map(|_| self.fresh_var()).collect();
+                        let return_type = Box::new(self.fresh_var());
+                        let func_type = Type::Func(param_types.clone(), return_type.clone());
+                        self.unify(&callee_type, &func_type, callee.span, None)?;
+                        (param_types, return_type)
+                    }
+                    callee_type => {
+                        return Err(TypeError::NotCallable {
+                            type_: self.default_ints(&callee_type),
+                            span: callee.span,
+                        });
+                    }
+                };
+                if args.len() != param_types.len() {
+                    return Err(TypeError::WrongArgumentCount {
+                        expected: param_types.len(),
+                        found: args.len(),
+                        span: expr.span,
+                    });
+                }
+                for (arg, param_type) in args.iter_mut().zip(param_types.iter()) {
+                    self.expect_type(arg, param_type, scope)?;
+                }
+                if self.builtin_name(callee, scope) == Some("len") {
+                    // The length of a string unless the argument is known to be an array.
+                    let arg = &args[0];
+                    let _ = self.unify_inner(&Type::String, &arg.type_, arg.span);
+                    let arg_type = self.default_ints(&arg.type_);
+                    if !matches!(arg_type, Type::String | Type::Array(_)) {
+                        return Err(TypeError::MismatchedTypes {
+                            expected: Type::String,
+                            found: arg_type,
+                            span: arg.span,
+                            expected_span: None,
+                        });
+                    }
+                }
+                *return_type
+            }
+            ExprKind::Record { fields, base } => {
+                // The grammar requires at least one field.
+                let type_name = self.field_record(&fields[0].0, fields[0].1.span)?;
+                let record_type = Type::Record(type_name.clone());
+                let declared = self.records[&type_name].clone();
+                let mut spans: Vec<(String, Span)> = Vec::new();
+                for (name, value) in fields.iter_mut() {
+                    let Some((_, field_type)) = declared.iter().find(|(field, _)| field == name)
+                    else {
+                        return Err(TypeError::UnknownField {
+                            name: name.clone(),
+                            type_: Some(record_type),
+                            span: value.span,
+                        });
+                    };
+                    if let Some((_, first_span)) = spans.iter().find(|(field, _)| field == name) {
+                        return Err(TypeError::DuplicateDefinition {
+                            name: name.clone(),
+                            span: value.span,
+                            first_span: *first_span,
+                        });
+                    }
+                    spans.push((name.clone(), value.span));
+                    self.expect_type(value, field_type, scope)?;
+                }
+                match base {
+                    Some(base) => self.expect_type(base, &record_type, scope)?,
+                    None => {
+                        let missing: Vec<_> = declared
+                            .iter()
+                            .filter(|(field, _)| !spans.iter().any(|(name, _)| name == field))
+                            .map(|(field, _)| field.clone())
+                            .collect();
+                        if !missing.is_empty() {
+                            return Err(TypeError::MissingFields {
+                                type_: record_type,
+                                fields: missing,
+                                span: expr.span,
+                            });
+                        }
+                    }
+                }
+                record_type
+            }
+            ExprKind::Field { expr: record, name } => {
+                let record_type = self.typecheck_expr(record, scope)?;
+                let type_name = match self.shallow_resolve(&record_type) {
+                    Type::Record(type_name) => type_name,
+                    // The record is a parameter of a lambda, like `p` in `|p| p.x`.
+                    Type::Var(_) => {
+                        let type_name = self.field_record(name, expr.span)?;
+                        let expected = Type::Record(type_name.clone());
+                        self.unify(&expected, &record_type, record.span, None)?;
+                        type_name
+                    }
+                    type_ => {
+                        return Err(TypeError::UnknownField {
+                            name: name.clone(),
+                            type_: Some(type_),
+                            span: expr.span,
+                        });
+                    }
+                };
+                match self.records[&type_name]
+                    .iter()
+                    .find(|(field, _)| field == name)
+                {
+                    Some((_, type_)) => type_.clone(),
+                    None => {
+                        return Err(TypeError::UnknownField {
+                            name: name.clone(),
+                            type_: Some(Type::Record(type_name)),
+                            span: expr.span,
+                        });
+                    }
+                }
+            }
+            ExprKind::Tuple(elements) => {
+                let mut types = Vec::new();
+                for element in elements.iter_mut() {
+                    types.push(self.typecheck_expr(element, scope)?);
+                }
+                // A tuple can't be built if one of its elements diverges.
+                if types
+                    .iter()
+                    .any(|type_| self.shallow_resolve(type_) == Type::Never)
+                {
+                    Type::Never
+                } else {
+                    Type::Tuple(types)
+                }
+            }
+            ExprKind::Array(elements) => {
+                // The elements have the type of the first one.
+                let elem = self.fresh_var();
+                for element in elements.iter_mut() {
+                    self.expect_type(element, &elem, scope)?;
+                }
+                Type::Array(Box::new(elem))
+            }
+            ExprKind::Index { expr: array, index } => {
+                let elem = self.fresh_var();
+                let array_type = Type::Array(Box::new(elem.clone()));
+                self.expect_type(array, &array_type, scope)?;
+                self.expect_type(index, &Type::Int, scope)?;
+                elem
             }
-            Stmt::Expr(expr) => {
-                self.typecheck_expr(expr)?;
+            ExprKind::Construct { variant, args } => {
+                let type_name = self.variant_enums[variant.as_str()].clone();
+                let field_types = self.variant_fields(&type_name, variant).to_vec();
+                if args.len() != field_types.len() {
+                    return Err(TypeError::WrongFieldCount {
+                        variant: variant.clone(),
+                        expected: field_types.len(),
+                        found: args.len(),
+                        span: expr.span,
+                    });
+                }
+                for (arg, field_type) in args.iter_mut().zip(field_types.iter()) {
+                    self.expect_type(arg, field_type, scope)?;
+                }
+                Type::Enum(type_name)
+            }
+            ExprKind::Match { scrutinee, arms } => {
+                let scrutinee_type = self.typecheck_expr(scrutinee, scope)?;
+                // The type of the first arm that doesn't diverge.
+                let mut type_ = Type::Never;
+                let mut type_span = None;
+                for arm in arms.iter_mut() {
+                    let outer_scope = scope.len();
+                    let result = self
+                        .typecheck_pattern(&mut arm.pattern, &scrutinee_type, outer_scope, scope)
+                        .and_then(|()| self.typecheck_expr(&mut arm.body, scope));
+                    scope.truncate(outer_scope);
+                    let body_type = result?;
+                    match self.join(&type_, &body_type, arm.body.span) {
+                        Some(joined) => type_ = joined,
+                        None => {
+                            return Err(TypeError::MismatchedTypes {
+                                expected: self.default_ints(&type_),
+                                found: self.default_ints(&body_type),
+                                span: tail_span(&arm.body),
+                                expected_span: type_span,
+                            });
+                        }
+                    }
+                    if type_span.is_none() && self.shallow_resolve(&type_) != Type::Never {
+                        type_span = Some(tail_span(&arm.body));
+                    }
+                }
+                if let Some(i) = patterns::unreachable_arm(arms, &self.enums) {
+                    return Err(TypeError::UnreachableArm {
+                        span: arms[i].pattern.span,
+                    });
+                }
+                if let Some(missing) = patterns::missing_pattern(arms, &self.enums) {
+                    return Err(TypeError::NonExhaustiveMatch {
+                        missing,
+                        span: scrutinee.span,
+                    });
+                }
+                type_
+            }
+            ExprKind::Ident(name) => {
+                // Variables shadow functions.
+                let variable = scope.iter().rev().find(|(n, _)| n == name);
+                if let Some((_, scheme)) = variable {
+                    self.instantiate(scheme)
+                } else if let Some(type_) = self.functions.get(name) {
+                    type_.clone()
+                } else {
+                    return Err(TypeError::UndefinedVariable {
+                        name: name.clone(),
+                        span: expr.span,
+                    });
+                }
+            }
+            ExprKind::Assign { lhs, rhs } => {
+                let Some(i) = scope.iter().rposition(|(name, _)| name == lhs) else {
+                    if self.functions.contains_key(lhs) {
+                        return Err(TypeError::AssignToImmutable {
+                            name: lhs.clone(),
+                            span: expr.span,
+                        });
+                    }
+                    return Err(TypeError::UndefinedVariable {
+                        name: lhs.clone(),
+                        span: expr.span,
+                    });
+                };
+                let scheme = scope[i].1.clone();
+                if !scheme.mutable {
+                    return Err(TypeError::AssignToImmutable {
+                        name: lhs.clone(),
+                        span: expr.span,
+                    });
+                }
+                // Lambdas can assign to globals, but not to the locals they capture.
+                if i >= self.globals && i < self.lambda_scope {
+                    return Err(TypeError::AssignToCaptured {
+                        name: lhs.clone(),
+                        span: expr.span,
+                    });
+                }
+                self.expect_type(rhs, &scheme.type_, scope)?;
+                Type::Unit
+            }
+            ExprKind::IntLit(lit) => match int_lit_type(lit) {
+                Some(type_) => {
+                    if lit.value > int_lit_max(lit, &type_) {
+                        return Err(TypeError::IntLitOutOfRange {
+                            lit: *lit,
+                            type_,
+                            span: expr.span,
+                        });
+                    }
+                    type_
+                }
+                // Checked by `check_int_lits` once the type is known.
+                None => self.fresh_int_var(),
+            },
+            ExprKind::FloatLit(_) => Type::Float,
+            ExprKind::BoolLit(_) => Type::Bool,
+            ExprKind::StringLit(_) => Type::String,
+            ExprKind::UnitLit => Type::Unit,
+            ExprKind::Lambda { params, body } => {
+                let outer_scope = scope.len();
+                for (name, type_) in params.iter_mut() {
+                    *type_ = self.fresh_var();
+                    scope.push((name.clone(), Scheme::mono(type_.clone())));
+                }
+                // `break` and `continue` can't jump out of a lambda.
+                let loops = std::mem::take(&mut self.loops);
+                let lambda_scope = std::mem::replace(&mut self.lambda_scope, outer_scope);
+                let result = self.typecheck_expr(body, scope);
+                self.loops = loops;
+                self.lambda_scope = lambda_scope;
+                scope.truncate(outer_scope);
+                let param_types = params.iter().map(|(_, type_)| type_.clone()).collect();
+                Type::Func(param_types, Box::new(result?))
+            }
+            ExprKind::Closure(_) => unreachable!("closures are introduced after type checking"),
+        };
+        expr.type_ = type_.clone();
+        Ok(type_)
+    }
+
+    /// Checks that `pattern` matches values of type `expected`, and adds the variables it binds to
+    /// `scope`. The variables from `scope[pattern_scope..]` are bound by the same pattern.
+    fn typecheck_pattern(
+        &mut self,
+        pattern: &mut Pattern,
+        expected: &Type,
+        pattern_scope: usize,
+        scope: &mut Scope,
+    ) -> Result<(), TypeError> {
+        // A name is a variable unless it is a variant without fields.
+        if let PatternKind::Binding(name) = &pattern.kind
+            && self.variant_enums.contains_key(name)
+        {
+            pattern.kind = PatternKind::Variant {
+                name: name.clone(),
+                args: Vec::new(),
+            };
+        }
+        match &mut pattern.kind {
+            PatternKind::Wildcard => {}
+            PatternKind::Binding(name) => {
+                if scope[pattern_scope..].iter().any(|(n, _)| n == name) {
+                    return Err(TypeError::DuplicateDefinition {
+                        name: name.clone(),
+                        span: pattern.span,
+                        first_span: pattern.span,
+                    });
+                }
+                scope.push((name.clone(), Scheme::mono(expected.clone())));
+            }
+            PatternKind::Literal(lit) => {
+                let lit_type = self.typecheck_expr(lit, scope)?;
+                self.unify(expected, &lit_type, pattern.span, None)?;
+                // Checking the arms needs to know how integer literals wrap around, like `-1`
+                // and `0xffffffff`, which are the same `Int` but not the same `Int64`.
+                lit.type_ = self.resolve(&lit.type_);
+                if let ExprKind::UnOp { operand, .. } = &mut lit.kind {
+                    operand.type_ = self.resolve(&operand.type_);
+                }
+            }
+            PatternKind::Variant { name, args } => {
+                let Some(type_name) = self.variant_enums.get(name).cloned() else {
+                    return Err(TypeError::UnknownVariant {
+                        name: name.clone(),
+                        span: pattern.span,
+                    });
+                };
+                let type_ = Type::Enum(type_name.clone());
+                self.unify(expected, &type_, pattern.span, None)?;
+                let field_types = self.variant_fields(&type_name, name).to_vec();
+                if args.len() != field_types.len() {
+                    return Err(TypeError::WrongFieldCount {
+                        variant: name.clone(),
+                        expected: field_types.len(),
+                        found: args.len(),
+                        span: pattern.span,
+                    });
+                }
+                for (arg, field_type) in args.iter_mut().zip(field_types.iter()) {
+                    self.typecheck_pattern(arg, field_type, pattern_scope, scope)?;
+                }
+                pattern.type_ = type_;
+                return Ok(());
             }
         }
+        pattern.type_ = expected.clone();
         Ok(())
     }
 
-    fn typecheck_expr(&self, expr: &mut Expr) -> Result<(), TypeError> {
-        match expr {
-            Expr::BinOp { op: _, lhs, rhs } => {
-                self.typecheck_expr(lhs)?;
-                self.typecheck_expr(rhs)?;
+    fn variant_fields(&self, type_name: &str, variant: &str) -> &[Type] {
+        let (_, field_types) = self.enums[type_name]
+            .iter()
+            .find(|(name, _)| name == variant)
+            .expect("variants belong to their sum type");
+        field_types
+    }
+
+    /// Replaces the inferred type variables in a checked statement by their types.
+    fn resolve_stmt(&self, stmt: &mut Stmt) -> Result<(), TypeError> {
+        match &mut stmt.kind {
+            StmtKind::Def { body, .. } => {
+                if let Some(body) = body {
+                    self.resolve_expr(body)?;
+                }
+            }
+            StmtKind::Let { type_, value, .. } | StmtKind::LetTuple { type_, value, .. } => {
+                *type_ = self.default_ints(type_);
+                self.resolve_expr(value)?;
             }
-            Expr::Ident(_) => {}
-            Expr::IntLit(_) => {}
-            _ => todo!(),
+            StmtKind::Type { .. } => {}
+            StmtKind::Expr(expr) => self.resolve_expr(expr)?,
         }
         Ok(())
     }
+
+    fn resolve_expr(&self, expr: &mut Expr) -> Result<(), TypeError> {
+        expr.type_ = self.default_ints(&expr.type_);
+        match &mut expr.kind {
+            ExprKind::BinOp { lhs, rhs, .. } => {
+                self.resolve_expr(lhs)?;
+                self.resolve_expr(rhs)?;
+            }
+            // `-2147483648` is fine even though `2147483648` is not.
+            ExprKind::UnOp {
+                op: UnOp::Neg,
+                operand,
+            } if let ExprKind::IntLit(lit) = operand.kind
+                && lit.radix == 10 =>
+            {
+                operand.type_ = self.default_ints(&operand.type_);
+                if lit.value > int_lit_max(&lit, &operand.type_) + 1 {
+                    return Err(TypeError::IntLitOutOfRange {
+                        lit,
+                        type_: operand.type_.clone(),
+                        span: operand.span,
+                    });
+                }
+            }
+            ExprKind::UnOp { operand, .. } => self.resolve_expr(operand)?,
+            ExprKind::Assign { rhs, .. } => self.resolve_expr(rhs)?,
+            ExprKind::Lambda { params, body } => {
+                for (_, type_) in params.iter_mut() {
+                    *type_ = self.default_ints(type_);
+                }
+                self.resolve_expr(body)?;
+            }
+            ExprKind::Call { callee, args } => {
+                self.resolve_expr(callee)?;
+                for arg in args.iter_mut() {
+                    self.resolve_expr(arg)?;
+                }
+            }
+            ExprKind::Record { fields, base } => {
+                for (_, value) in fields.iter_mut() {
+                    self.resolve_expr(value)?;
+                }
+                if let Some(base) = base {
+                    self.resolve_expr(base)?;
+                }
+            }
+            ExprKind::Field { expr, .. } => self.resolve_expr(expr)?,
+            ExprKind::Tuple(elements) | ExprKind::Array(elements) => {
+                for element in elements.iter_mut() {
+                    self.resolve_expr(element)?;
+                }
+            }
+            ExprKind::Index { expr, index } => {
+                self.resolve_expr(expr)?;
+                self.resolve_expr(index)?;
+            }
+            ExprKind::Construct { args, .. } => {
+                for arg in args.iter_mut() {
+                    self.resolve_expr(arg)?;
+                }
+            }
+            ExprKind::Match { scrutinee, arms } => {
+                self.resolve_expr(scrutinee)?;
+                for arm in arms.iter_mut() {
+                    self.resolve_pattern(&mut arm.pattern)?;
+                    self.resolve_expr(&mut arm.body)?;
+                }
+            }
+            ExprKind::If { cond, then, else_ } => {
+                self.resolve_expr(cond)?;
+                self.resolve_expr(then)?;
+                self.resolve_expr(else_)?;
+            }
+            ExprKind::While { cond, body } => {
+                self.resolve_expr(cond)?;
+                self.resolve_expr(body)?;
+            }
+            ExprKind::For {
+                start, end, body, ..
+            } => {
+                self.resolve_expr(start)?;
+                self.resolve_expr(end)?;
+                self.resolve_expr(body)?;
+            }
+            ExprKind::ForEach { array, body, .. } => {
+                self.resolve_expr(array)?;
+                self.resolve_expr(body)?;
+            }
+            ExprKind::Loop { body } => self.resolve_expr(body)?,
+            ExprKind::Break(value) => {
+                if let Some(value) = value {
+                    self.resolve_expr(value)?;
+                }
+            }
+            ExprKind::Block { stmts, value } => {
+                for stmt in stmts.iter_mut() {
+                    self.resolve_stmt(stmt)?;
+                }
+                if let Some(value) = value {
+                    self.resolve_expr(value)?;
+                }
+            }
+            // Literals with a suffix were checked along with their type.
+            ExprKind::IntLit(lit) if lit.suffix.is_none() => {
+                if lit.value > int_lit_max(lit, &expr.type_) {
+                    return Err(TypeError::IntLitOutOfRange {
+                        lit: *lit,
+                        type_: expr.type_.clone(),
+                        span: expr.span,
+                    });
+                }
+            }
+            ExprKind::Continue
+            | ExprKind::Closure(_)
+            | ExprKind::Ident(_)
+            | ExprKind::IntLit(_)
+            | ExprKind::FloatLit(_)
+            | ExprKind::BoolLit(_)
+            | ExprKind::StringLit(_)
+            | ExprKind::UnitLit => {}
+        }
+        Ok(())
+    }
+
+    fn resolve_pattern(&self, pattern: &mut Pattern) -> Result<(), TypeError> {
+        pattern.type_ = self.default_ints(&pattern.type_);
+        match &mut pattern.kind {
+            PatternKind::Literal(lit) => self.resolve_expr(lit)?,
+            PatternKind::Variant { args, .. } => {
+                for arg in args.iter_mut() {
+                    self.resolve_pattern(arg)?;
+                }
+            }
+            PatternKind::Wildcard | PatternKind::Binding(_) => {}
+        }
+        Ok(())
+    }
+}
+
+/// The type named `name`, if it is built in.
+fn builtin_type(name: &str) -> Option<Type> {
+    match name {
+        "Int" => Some(Type::Int),
+        "Int64" => Some(Type::Int64),
+        "Float" => Some(Type::Float),
+        "Bool" => Some(Type::Bool),
+        "String" => Some(Type::String),
+        "Unit" => Some(Type::Unit),
+        _ => None,
+    }
+}
+
+/// The type of `lhs op rhs`, or `None` if the operator doesn't apply to these operands.
+fn binop_type(op: BinOp, lhs: &Type, rhs: &Type) -> Option<Type> {
+    // Operators never convert between types.
+    if lhs != rhs {
+        return None;
+    }
+    match (op, lhs) {
+        (
+            BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div,
+            Type::Int | Type::Int64 | Type::Float,
+        )
+        | (BinOp::Rem | BinOp::Shl | BinOp::Shr, Type::Int | Type::Int64)
+        | (BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor, Type::Int | Type::Int64 | Type::Bool) => {
+            Some(lhs.clone())
+        }
+        // Strings are concatenated.
+        (BinOp::Add, Type::String) => Some(Type::String),
+        // Strings are compared byte by byte.
+        (
+            BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge,
+            Type::Int | Type::Int64 | Type::Float | Type::String,
+        )
+        | (BinOp::And | BinOp::Or, Type::Bool) => Some(Type::Bool),
+        // Records, sum types and arrays would only be compared by address.
+        (
+            BinOp::Eq | BinOp::Ne,
+            Type::Func(..)
+            | Type::Record(_)
+            | Type::Enum(_)
+            | Type::Tuple(_)
+            | Type::Array(_)
+            | Type::Never,
+        ) => None,
+        (BinOp::Eq | BinOp::Ne, _) => Some(Type::Bool),
+        _ => None,
+    }
 }
@@ -0,0 +1,574 @@
+use nio::ir::Type;
+use nio::parser::diagnostic::Diagnostic;
+use nio::typecheck::{self, TypeError};
+
+fn typecheck(nio_code: &str) -> Result<(), TypeError> {
+    let program = nio_parser::parse(nio_code).unwrap();
+    let mut program = program.into();
+    typecheck::typecheck(&mut program)
+}
+
+#[test]
+fn test_int_lit_out_of_range() {
+    assert!(typecheck("def f(): Int = 2147483647").is_ok());
+    assert!(matches!(
+        typecheck("def f(): Int = 2147483648"),
+        Err(TypeError::IntLitOutOfRange { .. })
+    ));
+    assert!(typecheck("def f(): Int = 0xffffffff").is_ok());
+    assert!(matches!(
+        typecheck("def f(): Int = 0x1_0000_0000"),
+        Err(TypeError::IntLitOutOfRange { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(): Int = 9223372036854775808i64"),
+        Err(TypeError::IntLitOutOfRange { .. })
+    ));
+    assert!(typecheck("def f(): Int64 = -9223372036854775808i64").is_ok());
+    let err = typecheck("def f(): Int64 = -9223372036854775809i64").unwrap_err();
+    assert!(matches!(err, TypeError::IntLitOutOfRange { .. }));
+    assert_eq!(
+        Diagnostic::from(err).labels[0].message,
+        "`Int64` literals must be between -9223372036854775808 and 9223372036854775807"
+    );
+    let err = typecheck("def f(): Int = 0x1_0000_0000").unwrap_err();
+    assert_eq!(
+        Diagnostic::from(err).labels[0].message,
+        "`Int` literals must be at most 0xffffffff"
+    );
+}
+
+#[test]
+fn test_operators() {
+    assert!(typecheck("def f(x: Int, y: Bool): Bool = x < 1 && !y").is_ok());
+    assert!(typecheck("def f(x: Bool, y: Bool): Bool = x & y == x | y").is_ok());
+    assert!(typecheck("def f(): Int = -2147483648").is_ok());
+    assert!(matches!(
+        typecheck("def f(): Int = -2147483649"),
+        Err(TypeError::IntLitOutOfRange { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(x: Int, y: Bool): Int = x + y"),
+        Err(TypeError::InvalidOperands { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(x: Int): Bool = x && x"),
+        Err(TypeError::InvalidOperands { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(x: Bool): Bool = -x"),
+        Err(TypeError::InvalidOperand { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(): Int = x"),
+        Err(TypeError::UndefinedVariable { .. })
+    ));
+}
+
+#[test]
+fn test_if_else() {
+    assert!(typecheck("def f(x: Int): Int = if x > 0 { x } else { -x }").is_ok());
+    assert!(matches!(
+        typecheck("def f(x: Int): Int = if x { 1 } else { 2 }"),
+        Err(TypeError::MismatchedTypes { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(x: Int): Int = if x > 0 { x } else { x > 1 }"),
+        Err(TypeError::IncompatibleArms { .. })
+    ));
+}
+
+#[test]
+fn test_loops() {
+    assert!(
+        typecheck("def f(x: Int): Int = loop { if x > 0 { break x } else { continue } }").is_ok()
+    );
+    assert!(
+        typecheck("def f(n: Int): Unit = for i in 0..n { if i > 3 { break } else { i } }").is_ok()
+    );
+    assert!(matches!(
+        typecheck("def f(x: Int): Int = if x > 0 { break } else { x }"),
+        Err(TypeError::OutsideLoop {
+            keyword: "break",
+            ..
+        })
+    ));
+    assert!(matches!(
+        typecheck("def f(): Unit = continue"),
+        Err(TypeError::OutsideLoop {
+            keyword: "continue",
+            ..
+        })
+    ));
+    assert!(matches!(
+        typecheck("def f(x: Bool): Unit = while x { break 1 }"),
+        Err(TypeError::BreakWithValue { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(x: Bool): Int = loop { if x { break 1 } else { break x } }"),
+        Err(TypeError::MismatchedTypes { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(n: Int): Unit = for i in 0..n { i }\ndef g(): Int = i"),
+        Err(TypeError::UndefinedVariable { .. })
+    ));
+}
+
+#[test]
+fn test_blocks() {
+    assert!(typecheck("def f(x: Int): Int = { let y = x; y + 1 }").is_ok());
+    assert!(typecheck("def f(x: Int): Unit = { x; }").is_ok());
+    assert!(
+        typecheck("def f(x: Int): Int = loop { if x > 0 { break x } else { { continue; } } }")
+            .is_ok()
+    );
+    assert!(matches!(
+        typecheck("def f(x: Int): Int = { { let y = x }; y }"),
+        Err(TypeError::UndefinedVariable { .. })
+    ));
+}
+
+#[test]
+fn test_calls() {
+    assert!(typecheck("def f(x: Int): Int = g(x, x < 1)\ndef g(x: Int, y: Bool): Int = x").is_ok());
+    assert!(typecheck("def f(f: Int): Int = f").is_ok());
+    assert!(matches!(
+        typecheck("def f(x: Int): Int = f(x, x)"),
+        Err(TypeError::WrongArgumentCount {
+            expected: 1,
+            found: 2,
+            ..
+        })
+    ));
+    assert!(matches!(
+        typecheck("def f(x: Int): Int = f(x < 1)"),
+        Err(TypeError::MismatchedTypes { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(x: Int): Int = x(1)"),
+        Err(TypeError::NotCallable { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(): Int = 1\ndef f(): Int = 2"),
+        Err(TypeError::DuplicateDefinition { .. })
+    ));
+}
+
+#[test]
+fn test_top_level_lets() {
+    assert!(typecheck("let x = 1\ndef f(): Int = x").is_ok());
+    assert!(matches!(
+        typecheck("def f(): Int = x\nlet x = 1"),
+        Err(TypeError::UndefinedVariable { .. })
+    ));
+}
+
+#[test]
+fn test_type_annotations() {
+    assert!(matches!(
+        typecheck("def f(x: Strng): Int = 1"),
+        Err(TypeError::UnknownType { name, .. }) if name == "Strng"
+    ));
+    assert!(matches!(
+        typecheck("let x: Double = 1"),
+        Err(TypeError::UnknownType { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(x: Int): Bool = x + 1"),
+        Err(TypeError::MismatchedTypes {
+            expected_span: Some(_),
+            ..
+        })
+    ));
+    assert!(matches!(
+        typecheck("def f(x: Int): Int = { let y = x > 0; y }"),
+        Err(TypeError::MismatchedTypes { .. })
+    ));
+    assert!(matches!(
+        typecheck("let x: Bool = 1"),
+        Err(TypeError::MismatchedTypes { .. })
+    ));
+    assert!(typecheck("def f(): Int = loop { break 1 }").is_ok());
+    assert!(typecheck("def f(): Unit = {}").is_ok());
+}
+
+#[test]
+fn test_function_types() {
+    assert!(typecheck("def apply(f: (Int) -> Int, x: Int): Int = f(x)").is_ok());
+    assert!(typecheck("def adder(n: Int): (Int) -> Int = |x| x + n").is_ok());
+    assert!(
+        typecheck(
+            "def f(g: () -> ()): () = g()
+let h: (Bool) -> Int = |b| 1"
+        )
+        .is_ok()
+    );
+    assert!(matches!(
+        typecheck("def apply(f: (Int) -> Int): Int = f(1 < 2)"),
+        Err(TypeError::MismatchedTypes { .. })
+    ));
+    assert!(matches!(
+        typecheck("let f: (Int) -> Bool = |x| x + 1"),
+        Err(TypeError::MismatchedTypes { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(x: List[Int]): Int = 1"),
+        Err(TypeError::UnknownType { name, .. }) if name == "List"
+    ));
+    assert!(matches!(
+        typecheck("def f(x: Int[Bool]): Int = 1"),
+        Err(TypeError::WrongTypeArgumentCount { found: 1, .. })
+    ));
+}
+
+#[test]
+fn test_lambdas() {
+    assert!(typecheck("def f(): Int = { let add = |x, y| x + y; add(1, 2) }").is_ok());
+    assert!(typecheck("def f(): Int = { let apply = |g, x| g(x); apply(|x| x * 2, 1) }").is_ok());
+    // `id` is generic, so it can be used at several types.
+    assert!(
+        typecheck("def f(): Int = { let id = |x| x; if id(1 < 2) { id(1) } else { 2 } }").is_ok()
+    );
+    // Only functions are generic.
+    assert!(matches!(
+        typecheck("def f(): Int = { let id = { |x| x }; if id(1 < 2) { id(1) } else { 2 } }"),
+        Err(TypeError::MismatchedTypes { .. })
+    ));
+    // Parameters are not generic inside the lambda.
+    assert!(matches!(
+        typecheck("def f(): Int = { let g = |id| if id(1 < 2) { id(1) } else { 2 }; 0 }"),
+        Err(TypeError::MismatchedTypes { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(): Int = { let inc = |x| x + 1; inc(1 < 2) }"),
+        Err(TypeError::MismatchedTypes {
+            expected_span: Some(_),
+            ..
+        })
+    ));
+    assert!(matches!(
+        typecheck("def f(): Bool = { let inc = |x| x + 1; inc(1) }"),
+        Err(TypeError::MismatchedTypes { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(): Int = { let inc = |x| x + 1; inc(1, 2) }"),
+        Err(TypeError::WrongArgumentCount { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(): Int = { let g = |f| f(f); 0 }"),
+        Err(TypeError::InfiniteType { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(): Int = loop { let g = || break 1; 0 }"),
+        Err(TypeError::OutsideLoop { .. })
+    ));
+}
+
+#[test]
+fn test_primitive_types() {
+    assert!(typecheck("def f(x: Float): Bool = -x < 1.5 && true").is_ok());
+    assert!(typecheck("def f(x: Int64): Int64 = !x % 7i64").is_ok());
+    assert!(typecheck("def f(): Unit = ()").is_ok());
+    assert!(typecheck("def f(x: Int): Float = { let g = |y| float(y) * 2.0; g(x) }").is_ok());
+    assert!(matches!(
+        typecheck("def f(x: Int64): Int64 = x + 1.5"),
+        Err(TypeError::InvalidOperands { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(x: Float): Float = x % 2.0"),
+        Err(TypeError::InvalidOperands { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(x: Bool): Int = int(x)"),
+        Err(TypeError::InvalidConversion { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(x: Int): Float = float(x, x)"),
+        Err(TypeError::WrongArgumentCount { .. })
+    ));
+    // Conversions can be shadowed.
+    assert!(typecheck("def float(x: Bool): Int = 1\ndef f(): Int = float(true)").is_ok());
+}
+
+#[test]
+fn test_int_lit_inference() {
+    assert!(typecheck("def f(x: Int64): Int64 = x + 1").is_ok());
+    assert!(typecheck("def f(): Int64 = 5").is_ok());
+    assert!(typecheck("def f(): Int64 = { let x = 1i64; x + 1 }").is_ok());
+    assert!(typecheck("def f(): Int64 = { let x = 2 * -3; let y = x; y }").is_ok());
+    assert!(typecheck("def f(): Array[Int64] = [1, 2, 3]").is_ok());
+    assert!(typecheck("def f(): Int64 = { let g = |x| x + 1; g(2) }").is_ok());
+    assert!(typecheck("def f(): Int64 = 3000000000").is_ok());
+    assert!(typecheck("def f(): Int64 = -9223372036854775808").is_ok());
+    assert!(typecheck("def f(): Int = -2147483648").is_ok());
+    // Literals are `Int`s unless something else decides their type.
+    assert!(typecheck("def f(): Int = { let x = 1; x + 2 }").is_ok());
+    assert!(typecheck("let x = 1\ndef f(): Int = x").is_ok());
+    assert!(matches!(
+        typecheck("def f(): Int = 3000000000"),
+        Err(TypeError::IntLitOutOfRange { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(): Unit = { let x = 3000000000; () }"),
+        Err(TypeError::IntLitOutOfRange { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(): Int64 = -9223372036854775809"),
+        Err(TypeError::IntLitOutOfRange { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(x: Int64): Int64 = { let y = 1; let z: Int = y; x + y }"),
+        Err(TypeError::InvalidOperands { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(): Float = 1"),
+        Err(TypeError::MismatchedTypes {
+            found: Type::Int,
+            ..
+        })
+    ));
+    assert!(matches!(
+        typecheck("def f(): Bool = 1 && true"),
+        Err(TypeError::InvalidOperands { .. })
+    ));
+}
+
+#[test]
+fn test_records() {
+    let point = "type Point = { x: Int, y: Int }\n";
+    assert!(typecheck(&format!("{point}def f(p: Point): Int = p.x + p.y")).is_ok());
+    assert!(typecheck(&format!("{point}def f(p: Point): Point = {{ y: 1, ..p }}")).is_ok());
+    assert!(
+        typecheck(&format!(
+            "{point}def f(): Int = {{ let g = |p| p.x; g({{ x: 1, y: 2 }}) }}"
+        ))
+        .is_ok()
+    );
+    assert!(matches!(
+        typecheck(&format!("{point}def f(p: Point): Int = p.z")),
+        Err(TypeError::UnknownField { .. })
+    ));
+    assert!(matches!(
+        typecheck(&format!("{point}def f(): Point = {{ x: 1, z: 2 }}")),
+        Err(TypeError::UnknownField { .. })
+    ));
+    assert!(matches!(
+        typecheck(&format!("{point}def f(): Point = {{ x: 1 }}")),
+        Err(TypeError::MissingFields { .. })
+    ));
+    assert!(matches!(
+        typecheck(&format!("{point}def f(): Point = {{ x: 1, y: true }}")),
+        Err(TypeError::MismatchedTypes { .. })
+    ));
+    assert!(matches!(
+        typecheck(&format!("{point}def f(x: Int): Int = x.y")),
+        Err(TypeError::UnknownField { .. })
+    ));
+    assert!(matches!(
+        typecheck("type P = { x: Int, x: Int }"),
+        Err(TypeError::DuplicateDefinition { .. })
+    ));
+}
+
+#[test]
+fn test_match() {
+    let shape = "type Shape = Circle(Float) | Rect(Float, Float) | Empty\n";
+    assert!(
+        typecheck(&format!(
+            "{shape}def f(s: Shape): Float = match s {{ Circle(r) => r, Rect(w, h) => w * h, Empty => 0.0 }}"
+        ))
+        .is_ok()
+    );
+    assert!(typecheck(&format!("{shape}def f(): Shape = Rect(1.0, 2.0)")).is_ok());
+    assert!(typecheck(&format!("{shape}def f(): Shape = Empty")).is_ok());
+    assert!(
+        typecheck("def f(n: Int): Bool = match n { 0 => false, -1 => true, _ => n > 1 }").is_ok()
+    );
+    assert!(typecheck("def f(b: Bool): Int = match b { true => 1, false => 0 }").is_ok());
+    // Variables shadow variants.
+    assert!(typecheck(&format!("{shape}def f(Empty: Int): Int = Empty + 1")).is_ok());
+    assert!(matches!(
+        typecheck(&format!(
+            "{shape}def f(s: Shape): Float = match s {{ Circle(r) => r, Empty => 0.0 }}"
+        )),
+        Err(TypeError::NonExhaustiveMatch { missing, .. }) if missing == "Rect(_, _)"
+    ));
+    assert!(matches!(
+        typecheck("def f(n: Int): Int = match n { 0 => 1, 1 => 2 }"),
+        Err(TypeError::NonExhaustiveMatch { .. })
+    ));
+    assert!(matches!(
+        typecheck(&format!(
+            "{shape}def f(s: Shape): Float = match s {{ _ => 1.0, Empty => 0.0 }}"
+        )),
+        Err(TypeError::UnreachableArm { .. })
+    ));
+    assert!(matches!(
+        typecheck(&format!(
+            "{shape}def f(s: Shape): Float = match s {{ Square(x) => x, _ => 0.0 }}"
+        )),
+        Err(TypeError::UnknownVariant { .. })
+    ));
+    assert!(matches!(
+        typecheck(&format!("{shape}def f(): Shape = Rect(1.0)")),
+        Err(TypeError::WrongFieldCount { .. })
+    ));
+    assert!(matches!(
+        typecheck(&format!(
+            "{shape}def f(s: Shape): Float = match s {{ Rect(w) => w, _ => 0.0 }}"
+        )),
+        Err(TypeError::WrongFieldCount { .. })
+    ));
+    assert!(matches!(
+        typecheck(&format!(
+            "{shape}def f(s: Shape): Float = match s {{ Circle(r) => r, _ => 0 }}"
+        )),
+        Err(TypeError::MismatchedTypes { .. })
+    ));
+    assert!(matches!(
+        typecheck(&format!("{shape}def f(s: Shape): Bool = s == Empty")),
+        Err(TypeError::InvalidOperands { .. })
+    ));
+}
+
+#[test]
+fn test_strings() {
+    assert!(typecheck(r#"def f(s: String): String = s + "!""#).is_ok());
+    assert!(typecheck(r#"def f(s: String): Bool = s < "b" && s != "a""#).is_ok());
+    assert!(typecheck(r#"def f(s: String): String = slice(s, 1, len(s))"#).is_ok());
+    assert!(typecheck(r#"def f(): Int = { let g = |s| len(s + "?"); g("a") }"#).is_ok());
+    // Functions shadow the built-in ones.
+    assert!(typecheck("def len(x: Int): Int = x\ndef f(): Int = len(1)").is_ok());
+    assert!(matches!(
+        typecheck(r#"def f(): String = "a" - "b""#),
+        Err(TypeError::InvalidOperands { .. })
+    ));
+    assert!(matches!(
+        typecheck(r#"def f(): String = "a" + 1"#),
+        Err(TypeError::InvalidOperands { .. })
+    ));
+    assert!(matches!(
+        typecheck(r#"def f(): Int = len(1)"#),
+        Err(TypeError::MismatchedTypes { .. })
+    ));
+    assert!(matches!(
+        typecheck(r#"def f(): String = slice("a", 0)"#),
+        Err(TypeError::WrongArgumentCount { .. })
+    ));
+    assert!(matches!(
+        typecheck(r#"def f(): Int = { let g = len; 0 }"#),
+        Err(TypeError::UndefinedVariable { .. })
+    ));
+}
+
+#[test]
+fn test_arrays() {
+    assert!(typecheck("def f(a: Array[Int]): Int = a[0] + len(a)").is_ok());
+    assert!(typecheck("def f(): Array[Bool] = push([], true)").is_ok());
+    assert!(typecheck(r#"def f(): Array[String] = pop(["a", "b"])"#).is_ok());
+    assert!(typecheck("def f(a: Array[Array[Int]]): Int = a[0][1]").is_ok());
+    assert!(typecheck("def f(a: Array[Int]): Unit = for x in a { x + 1 }").is_ok());
+    assert!(matches!(
+        typecheck("def f(): Int = [1, 2][true]"),
+        Err(TypeError::MismatchedTypes { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(): Array[Int] = [1, 2.0]"),
+        Err(TypeError::MismatchedTypes { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(a: Array[Int]): Array[Int] = push(a, \"x\")"),
+        Err(TypeError::MismatchedTypes { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(a: Array): Int = 0"),
+        Err(TypeError::WrongTypeArgumentCount { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(a: Array[Int, Int]): Int = 0"),
+        Err(TypeError::WrongTypeArgumentCount { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(a: Array[Int]): Bool = a == a"),
+        Err(TypeError::InvalidOperands { .. })
+    ));
+}
+
+#[test]
+fn test_tuples() {
+    assert!(typecheck("def f(x: (Int, Bool)): Int = 1").is_ok());
+    assert!(typecheck("def f(): (Int, Bool) = (1, true)").is_ok());
+    assert!(typecheck("def f(): (Int,) = (1,)").is_ok());
+    assert!(typecheck("def f(): Int = { let (a, b) = (1, 2); a + b }").is_ok());
+    assert!(typecheck("let (a, b,): (Int, String) = (1, \"x\")").is_ok());
+    assert!(
+        typecheck("def f(): Int = { let g = |p| { let (a, b) = p; a + b }; g((1, 2)) }").is_ok()
+    );
+    assert!(matches!(
+        typecheck("def f(): (Int, Bool) = (true, 1)"),
+        Err(TypeError::MismatchedTypes { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(): Int = { let (a, b) = (1, 2, 3); a }"),
+        Err(TypeError::MismatchedTypes { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(): Int = { let (a, b) = 1; a }"),
+        Err(TypeError::MismatchedTypes { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(): Int = { let (a, b): (Int, Int) = (1, true); a }"),
+        Err(TypeError::MismatchedTypes { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(): Bool = (1, 2) == (1, 2)"),
+        Err(TypeError::InvalidOperands { .. })
+    ));
+}
+
+#[test]
+fn test_assignment() {
+    assert!(typecheck("def f(): Int = { let mut x = 1; x = 2; x += 3; x -= 1; x }").is_ok());
+    assert!(typecheck("def f(): Unit = { let mut s = \"a\"; s = s + \"b\" }").is_ok());
+    assert!(typecheck("let mut n = 0\ndef f(): Unit = { let g = || n += 1; g() }").is_ok());
+    assert!(
+        typecheck("def f(): Unit = { let mut x = 1; let g = || { let mut y = x; y = 2 } }").is_ok()
+    );
+    assert!(matches!(
+        typecheck("def f(): Unit = { let x = 1; x = 2 }"),
+        Err(TypeError::AssignToImmutable { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(x: Int): Unit = x += 1"),
+        Err(TypeError::AssignToImmutable { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(): Unit = f = f"),
+        Err(TypeError::AssignToImmutable { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(): Unit = { let mut x = 1; { let x = 2; x = 3 } }"),
+        Err(TypeError::AssignToImmutable { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(): Unit = x = 1"),
+        Err(TypeError::UndefinedVariable { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(): Unit = { let mut x = 1; let g = || x = 2; g() }"),
+        Err(TypeError::AssignToCaptured { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(): Unit = { let mut x = 1; x = true }"),
+        Err(TypeError::MismatchedTypes { .. })
+    ));
+    assert!(matches!(
+        typecheck("def f(): Int = { let mut x = 1; x = 2 }"),
+        Err(TypeError::MismatchedTypes { .. })
+    ));
+    // Variables that can be assigned to aren't generic.
+    assert!(matches!(
+        typecheck("def f(): Unit = { let mut id = |x| x; id(1); id(true); () }"),
+        Err(TypeError::MismatchedTypes { .. })
+    ));
+}
@@ -1,27 +1,44 @@
 use std::error;
 
-use wasmtime::{Engine, Instance, Module, Store};
-
-#[test]
-fn test_add() -> Result<(), Box<dyn error::Error>> {
-    let nio_code = concat! {
-        r#"@export("add") def add(x: Int, y: Int): Int = x + y"#,
-    };
+use nio::codegen::{CodeGenerator, Options};
+use nio::parser::{diagnostic::Diagnostic, span::Span};
+use wasmtime::{Engine, Func, Instance, Module, Store};
 
+fn compile(
+    engine: &Engine,
+    nio_code: &'static str,
+    options: Options,
+) -> Result<Module, Box<dyn error::Error>> {
     let program = nio_parser::parse(nio_code)?;
     let mut program = program.into();
     nio::typecheck::typecheck(&mut program)?;
-    let module = nio::codegen::CodeGenerator::generate(&program)?;
+    nio::closure_conversion::convert(&mut program);
+    let module = CodeGenerator::generate_with_options(&program, options)?;
 
     let mut wasm_bytes = Vec::new();
     nio::wasm::emit(&mut wasm_bytes, &module)?;
 
+    Ok(Module::new(engine, wasm_bytes)?)
+}
+
+fn instantiate(nio_code: &'static str) -> Result<(Store<()>, Instance), Box<dyn error::Error>> {
     let engine = Engine::default();
-    let module = Module::new(&engine, wasm_bytes)?;
+    let module = compile(&engine, nio_code, Options::default())?;
     let mut store = Store::new(&engine, ());
 
     let instance = Instance::new(&mut store, &module, &[])?;
 
+    Ok((store, instance))
+}
+
+#[test]
+fn test_add() -> Result<(), Box<dyn error::Error>> {
+    let nio_code = concat! {
+        r#"@export("add") def add(x: Int, y: Int): Int = x + y"#,
+    };
+
+    let (mut store, instance) = instantiate(nio_code)?;
+
     let add = instance.get_typed_func::<(i32, i32), i32>(&mut store, "add")?;
 
     let result = add.call(&mut store, (3, 4))?;
@@ -29,3 +46,1035 @@ fn test_add() -> Result<(), Box<dyn error::Error>> {
 
     Ok(())
 }
+
+#[test]
+fn test_int_literals() -> Result<(), Box<dyn error::Error>> {
+    let nio_code = concat! {
+        r#"@export("decimal") def decimal(): Int = 1_000_000 + 0"#, "\n",
+        r#"@export("radix") def radix(): Int = 0x10 + 0o10 + 0b10"#, "\n",
+        r#"@export("max") def max(): Int = 2147483647i32"#, "\n",
+        r#"@export("bits") def bits(): Int = 0xffff_ffff"#, "\n",
+    };
+
+    let (mut store, instance) = instantiate(nio_code)?;
+
+    let decimal = instance.get_typed_func::<(), i32>(&mut store, "decimal")?;
+    assert_eq!(decimal.call(&mut store, ())?, 1_000_000);
+    let radix = instance.get_typed_func::<(), i32>(&mut store, "radix")?;
+    assert_eq!(radix.call(&mut store, ())?, 26);
+    let max = instance.get_typed_func::<(), i32>(&mut store, "max")?;
+    assert_eq!(max.call(&mut store, ())?, i32::MAX);
+    let bits = instance.get_typed_func::<(), i32>(&mut store, "bits")?;
+    assert_eq!(bits.call(&mut store, ())?, -1);
+
+    Ok(())
+}
+
+#[test]
+fn test_export_name_escapes() -> Result<(), Box<dyn error::Error>> {
+    let nio_code = concat! {
+        r#"@export("one\u{2d}\"two\"") def one(): Int = 1"#, "\n",
+        r##"@export(r#"\"#) def two(): Int = 2"##, "\n",
+    };
+
+    let (mut store, instance) = instantiate(nio_code)?;
+
+    let one = instance.get_typed_func::<(), i32>(&mut store, "one-\"two\"")?;
+    assert_eq!(one.call(&mut store, ())?, 1);
+    let two = instance.get_typed_func::<(), i32>(&mut store, "\\")?;
+    assert_eq!(two.call(&mut store, ())?, 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_export_name_clashes() -> Result<(), Box<dyn error::Error>> {
+    for (nio_code, name) in [
+        (r#"@export("memory") def f(): Int = 1"#, "memory"),
+        (r#"@export("alloc") def f(): Int = 1"#, "alloc"),
+        (
+            r#"@export("release_string") def f(): Int = 1"#,
+            "release_string",
+        ),
+        (r#"@export("_start") def f(): Int = 1"#, "_start"),
+    ] {
+        let error = instantiate(nio_code).expect_err("reserved export name");
+        assert_eq!(
+            error.to_string(),
+            format!("error[E0206]: the export name `{}` is reserved", name)
+        );
+    }
+
+    // Without a `_start` export, a function can take its name.
+    let engine = Engine::default();
+    let nio_code = r#"@export("_start") def f(): Int = 1"#;
+    compile(
+        &engine,
+        nio_code,
+        Options {
+            start_section: true,
+        },
+    )?;
+
+    let nio_code = concat! {
+        r#"@export("f") def f(): Int = 1"#, "\n",
+        r#"@export("f") def g(): Int = 2"#, "\n",
+    };
+    let error = instantiate(nio_code).expect_err("duplicate export name");
+    let error = error.downcast::<Diagnostic>().expect("a diagnostic");
+    assert_eq!(error.message, "the export name `f` is used twice");
+    assert_eq!(error.primary_span(), Some(Span::new(31, 42)));
+
+    Ok(())
+}
+
+#[test]
+fn test_operators() -> Result<(), Box<dyn error::Error>> {
+    let nio_code = concat! {
+        r#"@export("arith") def arith(x: Int, y: Int): Int = x / y + x % y * 10 - -x"#, "\n",
+        r#"@export("bits") def bits(x: Int, y: Int): Int = x & y | x ^ y << 4 >> 1"#, "\n",
+        r#"@export("not") def not(x: Int): Int = !x"#, "\n",
+        r#"@export("min") def min(): Int = -2147483648"#, "\n",
+        r#"@export("between") def between(x: Int, lo: Int, hi: Int): Bool = lo <= x && x < hi"#, "\n",
+        r#"@export("outside") def outside(x: Int, lo: Int, hi: Int): Bool = !(lo <= x) || x >= hi"#, "\n",
+        r#"@export("same") def same(x: Int, y: Int): Bool = x > y == y > x"#, "\n",
+    };
+
+    let (mut store, instance) = instantiate(nio_code)?;
+
+    let arith = instance.get_typed_func::<(i32, i32), i32>(&mut store, "arith")?;
+    assert_eq!(arith.call(&mut store, (-7, 2))?, -3 + -10 - 7);
+    let bits = instance.get_typed_func::<(i32, i32), i32>(&mut store, "bits")?;
+    assert_eq!(bits.call(&mut store, (6, 3))?, 6 & 3 | 6 ^ ((3 << 4) >> 1));
+    let not = instance.get_typed_func::<i32, i32>(&mut store, "not")?;
+    assert_eq!(not.call(&mut store, 5)?, !5);
+    let min = instance.get_typed_func::<(), i32>(&mut store, "min")?;
+    assert_eq!(min.call(&mut store, ())?, i32::MIN);
+    let between = instance.get_typed_func::<(i32, i32, i32), i32>(&mut store, "between")?;
+    assert_eq!(between.call(&mut store, (3, 0, 10))?, 1);
+    assert_eq!(between.call(&mut store, (10, 0, 10))?, 0);
+    let outside = instance.get_typed_func::<(i32, i32, i32), i32>(&mut store, "outside")?;
+    assert_eq!(outside.call(&mut store, (-1, 0, 10))?, 1);
+    assert_eq!(outside.call(&mut store, (5, 0, 10))?, 0);
+    let same = instance.get_typed_func::<(i32, i32), i32>(&mut store, "same")?;
+    assert_eq!(same.call(&mut store, (2, 2))?, 1);
+    assert_eq!(same.call(&mut store, (1, 2))?, 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_short_circuit() -> Result<(), Box<dyn error::Error>> {
+    // The division by zero would trap if it were evaluated.
+    let nio_code = concat! {
+        r#"@export("and") def and(x: Int): Bool = x != 0 && 10 / x > 1"#, "\n",
+        r#"@export("or") def or(x: Int): Bool = x == 0 || 10 / x > 1"#, "\n",
+    };
+
+    let (mut store, instance) = instantiate(nio_code)?;
+
+    let and = instance.get_typed_func::<i32, i32>(&mut store, "and")?;
+    assert_eq!(and.call(&mut store, 0)?, 0);
+    assert_eq!(and.call(&mut store, 2)?, 1);
+    let or = instance.get_typed_func::<i32, i32>(&mut store, "or")?;
+    assert_eq!(or.call(&mut store, 0)?, 1);
+    assert_eq!(or.call(&mut store, 20)?, 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_if_else() -> Result<(), Box<dyn error::Error>> {
+    let nio_code = concat! {
+        r#"@export("max") def max(x: Int, y: Int): Int = if x > y { x } else { y }"#, "\n",
+        r#"@export("sign") def sign(x: Int): Int = if x < 0 {"#, "\n",
+        r#"    -1"#, "\n",
+        r#"} else if x == 0 { 0 } else {"#, "\n",
+        r#"    1"#, "\n",
+        r#"}"#, "\n",
+        r#"@export("safe_div") def safe_div(x: Int, y: Int): Int = if y == 0 { 0 } else { x / y }"#, "\n",
+    };
+
+    let (mut store, instance) = instantiate(nio_code)?;
+
+    let max = instance.get_typed_func::<(i32, i32), i32>(&mut store, "max")?;
+    assert_eq!(max.call(&mut store, (3, 4))?, 4);
+    assert_eq!(max.call(&mut store, (5, -4))?, 5);
+    let sign = instance.get_typed_func::<i32, i32>(&mut store, "sign")?;
+    assert_eq!(sign.call(&mut store, -7)?, -1);
+    assert_eq!(sign.call(&mut store, 0)?, 0);
+    assert_eq!(sign.call(&mut store, 9)?, 1);
+    let safe_div = instance.get_typed_func::<(i32, i32), i32>(&mut store, "safe_div")?;
+    assert_eq!(safe_div.call(&mut store, (7, 0))?, 0);
+    assert_eq!(safe_div.call(&mut store, (7, 2))?, 3);
+
+    Ok(())
+}
+
+#[test]
+fn test_loops() -> Result<(), Box<dyn error::Error>> {
+    // The loops below trap on a division by zero if they run too far.
+    let nio_code = concat! {
+        r#"@export("range") def range(n: Int): Unit = for i in 0..n { 10 / (5 - i) }"#, "\n",
+        r#"@export("skip") def skip(n: Int): Unit = for i in -3..n { if i == 0 { continue } else { 10 / i } }"#, "\n",
+        r#"@export("stop") def stop(n: Int): Unit = for i in -3..n { if i == 0 { break } else { 10 / i } }"#, "\n",
+        r#"@export("never") def never(x: Int): Unit = while x > 0 { 1 / 0 }"#, "\n",
+        r#"@export("once") def once(x: Int): Unit = while x > 0 { break }"#, "\n",
+        r#"@export("abs") def abs(x: Int): Int = loop { if x < 0 { break -x } else { break x } }"#, "\n",
+    };
+
+    let (mut store, instance) = instantiate(nio_code)?;
+
+    let range = instance.get_typed_func::<i32, ()>(&mut store, "range")?;
+    range.call(&mut store, 5)?;
+    assert!(range.call(&mut store, 6).is_err());
+    let skip = instance.get_typed_func::<i32, ()>(&mut store, "skip")?;
+    skip.call(&mut store, 3)?;
+    let stop = instance.get_typed_func::<i32, ()>(&mut store, "stop")?;
+    stop.call(&mut store, 3)?;
+    let never = instance.get_typed_func::<i32, ()>(&mut store, "never")?;
+    never.call(&mut store, 0)?;
+    let once = instance.get_typed_func::<i32, ()>(&mut store, "once")?;
+    once.call(&mut store, 1)?;
+    let abs = instance.get_typed_func::<i32, i32>(&mut store, "abs")?;
+    assert_eq!(abs.call(&mut store, -4)?, 4);
+    assert_eq!(abs.call(&mut store, 4)?, 4);
+
+    Ok(())
+}
+
+#[test]
+fn test_blocks() -> Result<(), Box<dyn error::Error>> {
+    let nio_code = concat! {
+        r#"@export("hypot2") def hypot2(x: Int, y: Int): Int = {"#, "\n",
+        r#"    let xx = x * x"#, "\n",
+        r#"    let yy = y * y"#, "\n",
+        r#"    xx + yy"#, "\n",
+        r#"}"#, "\n",
+        r#"@export("shadow") def shadow(x: Int): Int = { let y = { let x = x * 10; x + 1 }; let x = x + y; x }"#, "\n",
+        r#"@export("ignore") def ignore(): Unit = { 1; 2; }"#, "\n",
+        r#"@export("nested") def nested(n: Int): Int = loop {"#, "\n",
+        r#"    let half = n / 2"#, "\n",
+        r#"    for i in 0..half { let unused = i; if i == 1 { break } else { continue } }"#, "\n",
+        r#"    break half"#, "\n",
+        r#"}"#, "\n",
+    };
+
+    let (mut store, instance) = instantiate(nio_code)?;
+
+    let hypot2 = instance.get_typed_func::<(i32, i32), i32>(&mut store, "hypot2")?;
+    assert_eq!(hypot2.call(&mut store, (3, 4))?, 25);
+    let shadow = instance.get_typed_func::<i32, i32>(&mut store, "shadow")?;
+    assert_eq!(shadow.call(&mut store, 2)?, 23);
+    let ignore = instance.get_typed_func::<(), ()>(&mut store, "ignore")?;
+    ignore.call(&mut store, ())?;
+    let nested = instance.get_typed_func::<i32, i32>(&mut store, "nested")?;
+    assert_eq!(nested.call(&mut store, 9)?, 4);
+
+    Ok(())
+}
+
+#[test]
+fn test_calls() -> Result<(), Box<dyn error::Error>> {
+    let nio_code = concat! {
+        r#"@export("fact") def fact(n: Int): Int = if n <= 1 { 1 } else { n * fact(n - 1) }"#, "\n",
+        r#"@export("is_even") def is_even(n: Int): Bool = if n == 0 { 1 == 1 } else { is_odd(n - 1) }"#, "\n",
+        r#"def is_odd(n: Int): Bool = if n == 0 { 1 == 0 } else { is_even(n - 1) }"#, "\n",
+        r#"@export("hypot2") def hypot2(x: Int, y: Int): Int = square(x) + square(y)"#, "\n",
+        r#"def square(x: Int): Int = x * x"#, "\n",
+    };
+
+    let (mut store, instance) = instantiate(nio_code)?;
+
+    let fact = instance.get_typed_func::<i32, i32>(&mut store, "fact")?;
+    assert_eq!(fact.call(&mut store, 5)?, 120);
+    let is_even = instance.get_typed_func::<i32, i32>(&mut store, "is_even")?;
+    assert_eq!(is_even.call(&mut store, 10)?, 1);
+    assert_eq!(is_even.call(&mut store, 7)?, 0);
+    let hypot2 = instance.get_typed_func::<(i32, i32), i32>(&mut store, "hypot2")?;
+    assert_eq!(hypot2.call(&mut store, (3, 4))?, 25);
+
+    Ok(())
+}
+
+#[test]
+fn test_imports() -> Result<(), Box<dyn error::Error>> {
+    let nio_code = concat! {
+        r#"@export("quad") def quad(x: Int): Int = double(double(x))"#, "\n",
+        r#"@import("env", "double") def double(x: Int): Int"#, "\n",
+        r#"@import("env", "log") def log(x: Int): Unit"#, "\n",
+        r#"@export("count") def count(n: Int): Unit = for i in 0..n { log(i) }"#, "\n",
+    };
+
+    let engine = Engine::default();
+    let module = compile(&engine, nio_code, Options::default())?;
+    let mut store = Store::new(&engine, Vec::new());
+    let double = Func::wrap(&mut store, |x: i32| x * 2);
+    let log = Func::wrap(
+        &mut store,
+        |mut caller: wasmtime::Caller<'_, Vec<i32>>, x: i32| {
+            caller.data_mut().push(x);
+        },
+    );
+    let instance = Instance::new(&mut store, &module, &[double.into(), log.into()])?;
+
+    let quad = instance.get_typed_func::<i32, i32>(&mut store, "quad")?;
+    assert_eq!(quad.call(&mut store, 5)?, 20);
+    let count = instance.get_typed_func::<i32, ()>(&mut store, "count")?;
+    count.call(&mut store, 3)?;
+    assert_eq!(store.data(), &[0, 1, 2]);
+
+    Ok(())
+}
+
+#[test]
+fn test_top_level_statements() -> Result<(), Box<dyn error::Error>> {
+    let nio_code = concat! {
+        r#"@import("env", "log") def log(x: Int): Unit"#, "\n",
+        r#"let base = 40"#, "\n",
+        r#"log(base)"#, "\n",
+        r#"let base = base + 2"#, "\n",
+        r#"let flag = base > 0"#, "\n",
+        r#"log(answer())"#, "\n",
+        r#"@export("answer") def answer(): Int = if flag { base } else { 0 }"#, "\n",
+    };
+
+    for start_section in [false, true] {
+        let engine = Engine::default();
+        let module = compile(&engine, nio_code, Options { start_section })?;
+        let mut store = Store::new(&engine, Vec::new());
+        let log = Func::wrap(
+            &mut store,
+            |mut caller: wasmtime::Caller<'_, Vec<i32>>, x: i32| {
+                caller.data_mut().push(x);
+            },
+        );
+        let instance = Instance::new(&mut store, &module, &[log.into()])?;
+
+        if start_section {
+            assert!(instance.get_func(&mut store, "_start").is_none());
+        } else {
+            // `flag` isn't initialized before `_start` runs.
+            let answer = instance.get_typed_func::<(), i32>(&mut store, "answer")?;
+            let error = format!("{:?}", answer.call(&mut store, ()).unwrap_err());
+            assert!(error.contains("uninitialized_variable"), "{}", error);
+            let start = instance.get_typed_func::<(), ()>(&mut store, "_start")?;
+            start.call(&mut store, ())?;
+        }
+        assert_eq!(store.data(), &[40, 42]);
+        let answer = instance.get_typed_func::<(), i32>(&mut store, "answer")?;
+        assert_eq!(answer.call(&mut store, ())?, 42);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_uninitialized_globals() -> Result<(), Box<dyn error::Error>> {
+    // `answer` reads `flag` before the statement that initializes it.
+    let nio_code = concat! {
+        r#"let base = 42"#, "\n",
+        r#"let first = answer()"#, "\n",
+        r#"let flag = base > 0"#, "\n",
+        r#"def answer(): Int = if flag { base } else { 0 }"#, "\n",
+    };
+    let (mut store, instance) = instantiate(nio_code)?;
+    let start = instance.get_typed_func::<(), ()>(&mut store, "_start")?;
+    let error = format!("{:?}", start.call(&mut store, ()).unwrap_err());
+    assert!(error.contains("uninitialized_variable"), "{}", error);
+
+    // So does a lambda called before `_start`, and assigning to a `let mut` traps as well.
+    let nio_code = concat! {
+        r#"let mut total = 7"#, "\n",
+        r#"@export("read") def read(): Int = { let f = || total; f() }"#, "\n",
+        r#"@export("reset") def reset(): Unit = total = 0"#, "\n",
+    };
+    let (mut store, instance) = instantiate(nio_code)?;
+    let read = instance.get_typed_func::<(), i32>(&mut store, "read")?;
+    let error = format!("{:?}", read.call(&mut store, ()).unwrap_err());
+    assert!(error.contains("uninitialized_variable"), "{}", error);
+    let reset = instance.get_typed_func::<(), ()>(&mut store, "reset")?;
+    assert!(reset.call(&mut store, ()).is_err());
+    let start = instance.get_typed_func::<(), ()>(&mut store, "_start")?;
+    start.call(&mut store, ())?;
+    assert_eq!(read.call(&mut store, ())?, 7);
+    reset.call(&mut store, ())?;
+    assert_eq!(read.call(&mut store, ())?, 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_primitive_types() -> Result<(), Box<dyn error::Error>> {
+    let nio_code = concat! {
+        r#"@export("mul64") def mul64(x: Int64, y: Int64): Int64 = x * y + 1i64"#, "\n",
+        r#"@export("min64") def min64(): Int64 = -9223372036854775808i64"#, "\n",
+        r#"@export("step64") def step64(x: Int64): Int64 = x * 3 + 1"#, "\n",
+        r#"@export("big") def big(): Int64 = -5_000_000_000"#, "\n",
+        r#"@export("sign64") def sign64(x: Int64): Int = match x { 0 => 0, 5000000000 => 5, _ => 1 }"#, "\n",
+        r#"@export("area") def area(r: Float): Float = 1.5 * r * r"#, "\n",
+        r#"@export("avg") def avg(x: Int, y: Int): Float = float(x + y) / 2.0"#, "\n",
+        r#"@export("round") def round(x: Float): Int = int(x + 0.5)"#, "\n",
+        r#"@export("widen") def widen(x: Int): Int64 = int64(x) << 32i64"#, "\n",
+        r#"@export("narrow") def narrow(x: Int64): Int = int(x)"#, "\n",
+        r#"@export("flip") def flip(x: Bool): Bool = x != true"#, "\n",
+        r#"@export("unit") def unit(): Unit = ()"#, "\n",
+        r#"@export("same") def same(): Bool = unit() == ()"#, "\n",
+    };
+
+    let (mut store, instance) = instantiate(nio_code)?;
+
+    let mul64 = instance.get_typed_func::<(i64, i64), i64>(&mut store, "mul64")?;
+    assert_eq!(mul64.call(&mut store, (1 << 40, 3))?, (3 << 40) + 1);
+    let min64 = instance.get_typed_func::<(), i64>(&mut store, "min64")?;
+    assert_eq!(min64.call(&mut store, ())?, i64::MIN);
+    let step64 = instance.get_typed_func::<i64, i64>(&mut store, "step64")?;
+    assert_eq!(step64.call(&mut store, 1 << 40)?, (3 << 40) + 1);
+    let big = instance.get_typed_func::<(), i64>(&mut store, "big")?;
+    assert_eq!(big.call(&mut store, ())?, -5_000_000_000);
+    let sign64 = instance.get_typed_func::<i64, i32>(&mut store, "sign64")?;
+    assert_eq!(sign64.call(&mut store, 0)?, 0);
+    assert_eq!(sign64.call(&mut store, 5_000_000_000)?, 5);
+    assert_eq!(sign64.call(&mut store, 705_032_704)?, 1);
+    let area = instance.get_typed_func::<f64, f64>(&mut store, "area")?;
+    assert_eq!(area.call(&mut store, 2.0)?, 6.0);
+    let avg = instance.get_typed_func::<(i32, i32), f64>(&mut store, "avg")?;
+    assert_eq!(avg.call(&mut store, (3, 4))?, 3.5);
+    let round = instance.get_typed_func::<f64, i32>(&mut store, "round")?;
+    assert_eq!(round.call(&mut store, 2.6)?, 3);
+    assert_eq!(round.call(&mut store, 1e20)?, i32::MAX);
+    let widen = instance.get_typed_func::<i32, i64>(&mut store, "widen")?;
+    assert_eq!(widen.call(&mut store, -1)?, -1 << 32);
+    let narrow = instance.get_typed_func::<i64, i32>(&mut store, "narrow")?;
+    assert_eq!(narrow.call(&mut store, (1 << 32) + 5)?, 5);
+    let flip = instance.get_typed_func::<i32, i32>(&mut store, "flip")?;
+    assert_eq!(flip.call(&mut store, 1)?, 0);
+    let unit = instance.get_typed_func::<(), ()>(&mut store, "unit")?;
+    unit.call(&mut store, ())?;
+    let same = instance.get_typed_func::<(), i32>(&mut store, "same")?;
+    assert_eq!(same.call(&mut store, ())?, 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_closures() -> Result<(), Box<dyn error::Error>> {
+    let nio_code = concat! {
+        r#"@export("adder") def adder(n: Int, x: Int): Int = { let add = |y| y + n; add(x) }"#, "\n",
+        r#"@export("twice") def twice(n: Int): Int = { let twice = |f, x| f(f(x)); twice(|x| x * n, 3) }"#, "\n",
+        r#"@export("nested") def nested(a: Int64, b: Float): Float = {"#, "\n",
+        r#"    let outer = |x| { let inner = |y| float(a) + b + x + y; inner(1.0) };"#, "\n",
+        r#"    outer(0.5)"#, "\n",
+        r#"}"#, "\n",
+        r#"@export("apply") def apply(x: Int): Int = { let f = square; let g = |h, y| h(y) + 1; g(f, x) + g(square, 1) }"#, "\n",
+        r#"def square(x: Int): Int = x * x"#, "\n",
+        r#"let offset = 10"#, "\n",
+        r#"let shift = |x| x + offset"#, "\n",
+        r#"@export("shifted") def shifted(x: Int): Int = shift(x)"#, "\n",
+    };
+
+    let (mut store, instance) = instantiate(nio_code)?;
+
+    let adder = instance.get_typed_func::<(i32, i32), i32>(&mut store, "adder")?;
+    assert_eq!(adder.call(&mut store, (3, 4))?, 7);
+    let twice = instance.get_typed_func::<i32, i32>(&mut store, "twice")?;
+    assert_eq!(twice.call(&mut store, 5)?, 75);
+    let nested = instance.get_typed_func::<(i64, f64), f64>(&mut store, "nested")?;
+    assert_eq!(
+        nested.call(&mut store, (1 << 40, 0.25))?,
+        (1u64 << 40) as f64 + 1.75
+    );
+    let apply = instance.get_typed_func::<i32, i32>(&mut store, "apply")?;
+    assert_eq!(apply.call(&mut store, 3)?, 12);
+    let start = instance.get_typed_func::<(), ()>(&mut store, "_start")?;
+    start.call(&mut store, ())?;
+    let shifted = instance.get_typed_func::<i32, i32>(&mut store, "shifted")?;
+    assert_eq!(shifted.call(&mut store, 1)?, 11);
+
+    Ok(())
+}
+
+#[test]
+fn test_null_closure() -> Result<(), Box<dyn error::Error>> {
+    // Address 0 is never a closure, so calling it traps instead of running the first lambda.
+    let nio_code = concat! {
+        r#"@export("apply") def apply(f: (Int) -> Int, x: Int): Int = f(x)"#, "\n",
+        r#"@export("inc") def inc(x: Int): Int = apply(|y| y + 1, x)"#, "\n",
+    };
+
+    let (mut store, instance) = instantiate(nio_code)?;
+
+    let inc = instance.get_typed_func::<i32, i32>(&mut store, "inc")?;
+    assert_eq!(inc.call(&mut store, 5)?, 6);
+    let apply = instance.get_typed_func::<(i32, i32), i32>(&mut store, "apply")?;
+    let error = format!("{:?}", apply.call(&mut store, (0, 5)).unwrap_err());
+    assert!(error.contains("uninitialized element"), "{}", error);
+
+    Ok(())
+}
+
+#[test]
+fn test_function_types() -> Result<(), Box<dyn error::Error>> {
+    let nio_code = concat! {
+        r#"def adder(n: Int): (Int) -> Int = |x| x + n"#, "\n",
+        r#"def compose(f: (Int) -> Int, g: (Int) -> Int): (Int) -> Int = |x| g(f(x))"#, "\n",
+        r#"def curry(f: (Int, Int) -> Int): (Int) -> (Int) -> Int = |x| |y| f(x, y)"#, "\n",
+        r#"def sub(x: Int, y: Int): Int = x - y"#, "\n",
+        r#"@export("run") def run(x: Int): Int = compose(adder(1), curry(sub)(100))(x)"#, "\n",
+    };
+
+    let (mut store, instance) = instantiate(nio_code)?;
+
+    let run = instance.get_typed_func::<i32, i32>(&mut store, "run")?;
+    assert_eq!(run.call(&mut store, 9)?, 90);
+
+    Ok(())
+}
+
+#[test]
+fn test_generic_lets() -> Result<(), Box<dyn error::Error>> {
+    let nio_code = concat! {
+        r#"let id = |x| x"#, "\n",
+        r#"@export("pick") def pick(x: Int): Int = {"#, "\n",
+        r#"    let choose = |c, a, b| if id(c) { a } else { b };"#, "\n",
+        r#"    let same = id;"#, "\n",
+        r#"    if same(x > 0) { choose(true, id(x), 0) } else { int(choose(false, 1.5, same(2.5))) }"#, "\n",
+        r#"}"#, "\n",
+    };
+
+    let (mut store, instance) = instantiate(nio_code)?;
+
+    let start = instance.get_typed_func::<(), ()>(&mut store, "_start")?;
+    start.call(&mut store, ())?;
+    let pick = instance.get_typed_func::<i32, i32>(&mut store, "pick")?;
+    assert_eq!(pick.call(&mut store, 5)?, 5);
+    assert_eq!(pick.call(&mut store, -5)?, 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_records() -> Result<(), Box<dyn error::Error>> {
+    let nio_code = concat! {
+        r#"type Point = { x: Int, y: Int }"#, "\n",
+        r#"type Body = { id: Int, pos: Point, mass: Float, age: Int64, alive: Bool }"#, "\n",
+        r#"def point(x: Int, y: Int): Point = { x: x, y: y }"#, "\n",
+        r#"def dist(p: Point, q: Point): Int = (p.x - q.x) * (p.x - q.x) + (p.y - q.y) * (p.y - q.y)"#, "\n",
+        r#"@export("dist") def dist_to(x: Int, y: Int): Int = dist(point(x, y), { y: 1, x: 2 })"#, "\n",
+        r#"@export("moved") def moved(dx: Int): Int = { let p = point(1, 2); let q = { x: p.x + dx, ..p }; q.x * 10 + q.y }"#, "\n",
+        r#"@export("body") def body(m: Float): Float = {"#, "\n",
+        r#"    let b = { id: 1, pos: point(3, 4), mass: m, age: 7i64, alive: true };"#, "\n",
+        r#"    let c = { mass: b.mass * 2.0, ..b };"#, "\n",
+        r#"    if c.alive { c.mass + float(c.pos.y) + float(int(c.age)) } else { 0.0 }"#, "\n",
+        r#"}"#, "\n",
+    };
+
+    let (mut store, instance) = instantiate(nio_code)?;
+
+    let dist = instance.get_typed_func::<(i32, i32), i32>(&mut store, "dist")?;
+    assert_eq!(dist.call(&mut store, (5, 5))?, 25);
+    let moved = instance.get_typed_func::<i32, i32>(&mut store, "moved")?;
+    assert_eq!(moved.call(&mut store, 4)?, 52);
+    let body = instance.get_typed_func::<f64, f64>(&mut store, "body")?;
+    assert_eq!(body.call(&mut store, 1.25)?, 13.5);
+
+    Ok(())
+}
+
+#[test]
+fn test_match() -> Result<(), Box<dyn error::Error>> {
+    let nio_code = concat! {
+        r#"type Shape = Circle(Float) | Rect(Float, Float) | Empty"#, "\n",
+        r#"type Tree = Leaf | Node(Tree, Int, Tree)"#, "\n",
+        r#"def area(s: Shape): Float = match s {"#, "\n",
+        r#"    Circle(r) => 3.0 * r * r,"#, "\n",
+        r#"    Rect(w, h) => w * h,"#, "\n",
+        r#"    Empty => 0.0"#, "\n",
+        r#"}"#, "\n",
+        r#"@export("area") def shape_area(n: Int, x: Float): Float = {"#, "\n",
+        r#"    let s = if n == 0 { Circle(x) } else if n == 1 { Rect(x, 2.0) } else { Empty };"#, "\n",
+        r#"    area(s)"#, "\n",
+        r#"}"#, "\n",
+        r#"def insert(t: Tree, x: Int): Tree = match t {"#, "\n",
+        r#"    Leaf => Node(Leaf, x, Leaf),"#, "\n",
+        r#"    Node(l, y, r) => if x < y { Node(insert(l, x), y, r) } else { Node(l, y, insert(r, x)) }"#, "\n",
+        r#"}"#, "\n",
+        r#"def sum(t: Tree): Int = match t { Leaf => 0, Node(l, x, r) => sum(l) + x + sum(r) }"#, "\n",
+        r#"def depth(t: Tree): Int = match t {"#, "\n",
+        r#"    Leaf => 0,"#, "\n",
+        r#"    Node(l, _, r) => { let a = depth(l); let b = depth(r); 1 + if a > b { a } else { b } }"#, "\n",
+        r#"}"#, "\n",
+        r#"/// The value of the leftmost node whose left child is a leaf, or -1."#, "\n",
+        r#"def leftmost(t: Tree): Int = match t { Node(Leaf, x, _) => x, Node(l, _, _) => leftmost(l), Leaf => -1 }"#, "\n",
+        r#"def tree(n: Int): Tree = { let t = Node(Leaf, 5, Leaf); let t2 = insert(insert(t, 3), 8); insert(insert(t2, n), 1) }"#, "\n",
+        r#"@export("sum") def tree_sum(n: Int): Int = sum(tree(n))"#, "\n",
+        r#"@export("depth") def tree_depth(n: Int): Int = depth(tree(n))"#, "\n",
+        r#"@export("leftmost") def tree_leftmost(n: Int): Int = leftmost(tree(n))"#, "\n",
+        r#"@export("classify") def classify(n: Int): Int = match n { 0 => 10, -1 => 20, 7 => 30, _ => n }"#, "\n",
+        r#"@export("classify64") def classify64(n: Int64): Int = match n { 0i64 => 1, 5000000000i64 => 2, _ => 3 }"#, "\n",
+        r#"@export("not") def not(b: Bool): Int = match b { true => 0, false => 1 }"#, "\n",
+    };
+
+    let (mut store, instance) = instantiate(nio_code)?;
+
+    let area = instance.get_typed_func::<(i32, f64), f64>(&mut store, "area")?;
+    assert_eq!(area.call(&mut store, (0, 2.0))?, 12.0);
+    assert_eq!(area.call(&mut store, (1, 2.5))?, 5.0);
+    assert_eq!(area.call(&mut store, (2, 2.5))?, 0.0);
+    let sum = instance.get_typed_func::<i32, i32>(&mut store, "sum")?;
+    assert_eq!(sum.call(&mut store, 4)?, 21);
+    let depth = instance.get_typed_func::<i32, i32>(&mut store, "depth")?;
+    assert_eq!(depth.call(&mut store, 4)?, 3);
+    assert_eq!(depth.call(&mut store, 0)?, 4);
+    let leftmost = instance.get_typed_func::<i32, i32>(&mut store, "leftmost")?;
+    assert_eq!(leftmost.call(&mut store, 4)?, 1);
+    assert_eq!(leftmost.call(&mut store, 0)?, 0);
+    let classify = instance.get_typed_func::<i32, i32>(&mut store, "classify")?;
+    assert_eq!(classify.call(&mut store, 0)?, 10);
+    assert_eq!(classify.call(&mut store, -1)?, 20);
+    assert_eq!(classify.call(&mut store, 7)?, 30);
+    assert_eq!(classify.call(&mut store, 9)?, 9);
+    let classify64 = instance.get_typed_func::<i64, i32>(&mut store, "classify64")?;
+    assert_eq!(classify64.call(&mut store, 0)?, 1);
+    assert_eq!(classify64.call(&mut store, 5_000_000_000)?, 2);
+    assert_eq!(classify64.call(&mut store, 5)?, 3);
+    let not = instance.get_typed_func::<i32, i32>(&mut store, "not")?;
+    assert_eq!(not.call(&mut store, 1)?, 0);
+    assert_eq!(not.call(&mut store, 0)?, 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_strings() -> Result<(), Box<dyn error::Error>> {
+    let nio_code = concat! {
+        r#"type Person = { name: String, age: Int }"#, "\n",
+        r#"type Greeting = Hello(String) | Bye"#, "\n",
+        r#"def greet(name: String): String = "Hello, " + name + "!""#, "\n",
+        r#"@export("greeting") def greeting(): String = greet("Nio")"#, "\n",
+        r#"@export("length") def length(): Int = len(greet("Nio")) * 10 + len("é")"#, "\n",
+        r#"@export("compare") def compare(n: Int): Int = {"#, "\n",
+        r#"    let words = "ab abc abd b";"#, "\n",
+        r#"    let a = if n == 0 { slice(words, 3, 6) } else if n == 1 { slice(words, 11, 12) } else { slice(words, 0, 2) };"#, "\n",
+        r#"    let b = slice(words, 3, 6);"#, "\n",
+        r#"    if a < b { -1 } else if a == b { 0 } else { 1 }"#, "\n",
+        r#"}"#, "\n",
+        r#"@export("prefix") def prefix(): Bool = "ab" < "abc" && "abc" >= "abc" && "abc" != "abd""#, "\n",
+        r#"@export("slice") def slice_at(start: Int, end: Int): String = slice("Hello, world", start, end)"#, "\n",
+        r#"@export("choose") def choose(yes: Bool): String = if yes { "yes" } else { "no" }"#, "\n",
+        r#"@export("aggregates") def aggregates(): String = {"#, "\n",
+        r#"    let p = { name: "Ada", age: 36 };"#, "\n",
+        r#"    let suffix = "!";"#, "\n",
+        r#"    let shout = |s| s + suffix;"#, "\n",
+        r#"    match Hello(p.name) { Hello(name) => shout(name), Bye => "" }"#, "\n",
+        r#"}"#, "\n",
+    };
+
+    let (mut store, instance) = instantiate(nio_code)?;
+    let memory = instance
+        .get_memory(&mut store, "memory")
+        .ok_or("no memory")?;
+    let read = |store: &Store<()>, (ptr, len): (i32, i32)| {
+        String::from_utf8(memory.data(store)[ptr as usize..(ptr + len) as usize].to_vec())
+    };
+
+    let greeting = instance.get_typed_func::<(), (i32, i32)>(&mut store, "greeting")?;
+    let result = greeting.call(&mut store, ())?;
+    assert_eq!(read(&store, result)?, "Hello, Nio!");
+    let length = instance.get_typed_func::<(), i32>(&mut store, "length")?;
+    assert_eq!(length.call(&mut store, ())?, 112);
+    let compare = instance.get_typed_func::<i32, i32>(&mut store, "compare")?;
+    assert_eq!(compare.call(&mut store, 0)?, 0);
+    assert_eq!(compare.call(&mut store, 1)?, 1);
+    assert_eq!(compare.call(&mut store, 2)?, -1);
+    let prefix = instance.get_typed_func::<(), i32>(&mut store, "prefix")?;
+    assert_eq!(prefix.call(&mut store, ())?, 1);
+    let slice = instance.get_typed_func::<(i32, i32), (i32, i32)>(&mut store, "slice")?;
+    let result = slice.call(&mut store, (7, 12))?;
+    assert_eq!(read(&store, result)?, "world");
+    let result = slice.call(&mut store, (3, 3))?;
+    assert_eq!(read(&store, result)?, "");
+    assert!(slice.call(&mut store, (5, 13)).is_err());
+    assert!(slice.call(&mut store, (4, 3)).is_err());
+    assert!(slice.call(&mut store, (-1, 3)).is_err());
+    let choose = instance.get_typed_func::<i32, (i32, i32)>(&mut store, "choose")?;
+    let result = choose.call(&mut store, 1)?;
+    assert_eq!(read(&store, result)?, "yes");
+    let result = choose.call(&mut store, 0)?;
+    assert_eq!(read(&store, result)?, "no");
+    let aggregates = instance.get_typed_func::<(), (i32, i32)>(&mut store, "aggregates")?;
+    let result = aggregates.call(&mut store, ())?;
+    assert_eq!(read(&store, result)?, "Ada!");
+
+    Ok(())
+}
+
+#[test]
+fn test_memory_reuse() -> Result<(), Box<dyn error::Error>> {
+    // Each call allocates about 20 KiB, which is freed before it returns.
+    let nio_code = concat! {
+        r#"type Point = { x: Int, y: Int }"#, "\n",
+        r#"type Labeled = { label: String, point: Point }"#, "\n",
+        r#"type Tree = Leaf | Node(Tree, Labeled, Tree)"#, "\n",
+        r#"def tree(n: Int, name: String): Tree = if n == 0 { Leaf } else {"#, "\n",
+        r#"    let p = { label: name + "!", point: { x: n, y: n } };"#, "\n",
+        r#"    Node(tree(n - 1, name + "l"), { label: p.label, ..p }, tree(n - 1, slice(name, 0, 1)))"#, "\n",
+        r#"}"#, "\n",
+        r#"def total(t: Tree): Int = match t {"#, "\n",
+        r#"    Leaf => 0,"#, "\n",
+        r#"    Node(l, x, r) => total(l) + len(x.label) + x.point.x + total(r)"#, "\n",
+        r#"}"#, "\n",
+        r#"@export("churn") def churn(n: Int): Int = {"#, "\n",
+        r#"    let t = tree(n, "node");"#, "\n",
+        r#"    let count = |x| x + total(t);"#, "\n",
+        r#"    for i in 0..3 { let s = "tmp" + "!"; if i == 1 { break } else { len(s) } };"#, "\n",
+        r#"    count(0)"#, "\n",
+        r#"}"#, "\n",
+        r#"@export("greet") def greet(name: String): String = "Hello, " + name + "!""#, "\n",
+    };
+
+    let (mut store, instance) = instantiate(nio_code)?;
+    let memory = instance
+        .get_memory(&mut store, "memory")
+        .ok_or("no memory")?;
+
+    let churn = instance.get_typed_func::<i32, i32>(&mut store, "churn")?;
+    let expected = churn.call(&mut store, 8)?;
+    let size = memory.size(&store);
+    for _ in 0..200 {
+        assert_eq!(churn.call(&mut store, 8)?, expected);
+    }
+    assert_eq!(memory.size(&store), size);
+
+    // The host allocates the strings it passes, and releases the ones it gets back.
+    let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+    let release = instance.get_typed_func::<i32, ()>(&mut store, "release_string")?;
+    let greet = instance.get_typed_func::<(i32, i32), (i32, i32)>(&mut store, "greet")?;
+    for _ in 0..1000 {
+        let name = alloc.call(&mut store, 3)?;
+        memory.write(&mut store, name as usize, b"Nio")?;
+        let (ptr, len) = greet.call(&mut store, (name, 3))?;
+        assert_eq!(
+            &memory.data(&store)[ptr as usize..(ptr + len) as usize],
+            b"Hello, Nio!"
+        );
+        release.call(&mut store, ptr)?;
+    }
+    assert_eq!(memory.size(&store), size);
+
+    Ok(())
+}
+
+#[test]
+fn test_memory_growth() -> Result<(), Box<dyn error::Error>> {
+    let nio_code = concat! {
+        r#"def double(s: String, n: Int): String = if n == 0 { s } else { double(s + s, n - 1) }"#, "\n",
+        r#"@export("grow") def grow(n: Int): Int = len(double("ab", n))"#, "\n",
+    };
+
+    let (mut store, instance) = instantiate(nio_code)?;
+    let memory = instance
+        .get_memory(&mut store, "memory")
+        .ok_or("no memory")?;
+
+    // The blocks freed by the first call are merged and reused by the next ones.
+    let grow = instance.get_typed_func::<i32, i32>(&mut store, "grow")?;
+    assert_eq!(grow.call(&mut store, 20)?, 2 << 20);
+    assert!(memory.size(&store) > 32);
+    let size = memory.size(&store);
+    for _ in 0..5 {
+        assert_eq!(grow.call(&mut store, 20)?, 2 << 20);
+    }
+    assert_eq!(memory.size(&store), size);
+
+    Ok(())
+}
+
+#[test]
+fn test_long_string() -> Result<(), Box<dyn error::Error>> {
+    let nio_code = concat! {
+        r#"@export("build") def build(n: Int): Int = {"#, "\n",
+        r#"    let mut s = """#, "\n",
+        r#"    for i in 0..n { s = s + "ab" }"#, "\n",
+        r#"    len(s)"#, "\n",
+        r#"}"#, "\n",
+    };
+
+    let (mut store, instance) = instantiate(nio_code)?;
+    let memory = instance
+        .get_memory(&mut store, "memory")
+        .ok_or("no memory")?;
+
+    // Each string is freed right after the next one is allocated, and the blocks of the previous
+    // ones are merged to hold the following ones, so the memory stays a few times the final string.
+    let build = instance.get_typed_func::<i32, i32>(&mut store, "build")?;
+    assert_eq!(build.call(&mut store, 50_000)?, 100_000);
+    assert!(memory.size(&store) <= 8, "{} pages", memory.size(&store));
+
+    Ok(())
+}
+
+#[test]
+fn test_alloc_size() -> Result<(), Box<dyn error::Error>> {
+    let (mut store, instance) = instantiate("")?;
+    let memory = instance
+        .get_memory(&mut store, "memory")
+        .ok_or("no memory")?;
+
+    // Sizes the host passes as negative numbers are too large to allocate, and leave the heap as
+    // it was.
+    let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+    let first = alloc.call(&mut store, 16)?;
+    for size in [-16, -1, i32::MIN] {
+        assert!(alloc.call(&mut store, size).is_err());
+    }
+    assert_eq!(alloc.call(&mut store, 16)?, first + 24);
+    assert_eq!(memory.size(&store), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_arrays() -> Result<(), Box<dyn error::Error>> {
+    let nio_code = concat! {
+        r#"type Point = { x: Int, y: Int }"#, "\n",
+        r#"@export("index") def index(i: Int): Int = [10, 20, 30][i]"#, "\n",
+        r#"@export("floats") def floats(): Float = { let a = [1.5, 2.5]; a[0] + a[1] + float(len(a)) }"#, "\n",
+        r#"@export("sum") def sum(n: Int): Int = {"#, "\n",
+        r#"    let a = [];"#, "\n",
+        r#"    let a = push(push(a, n), n * 2);"#, "\n",
+        r#"    let b = push(a, 5);"#, "\n",
+        r#"    let c = pop(pop(b));"#, "\n",
+        r#"    let total = 0;"#, "\n",
+        r#"    for x in a { if x > 100 { break } else { total + x } };"#, "\n",
+        r#"    len(a) * 1000 + len(b) * 100 + len(c) * 10 + a[1] - c[0]"#, "\n",
+        r#"}"#, "\n",
+        r#"@export("pop_empty") def pop_empty(): Int = len(pop([1]))"#, "\n",
+        r#"@export("pop_twice") def pop_twice(): Int = len(pop(pop([1])))"#, "\n",
+        r#"@export("names") def names(n: Int): String = {"#, "\n",
+        r#"    let names = ["Ada", "Alan"];"#, "\n",
+        r#"    let more = push(names, "Grace");"#, "\n",
+        r#"    let points = [{ x: 1, y: 2 }, { x: 3, y: 4 }];"#, "\n",
+        r#"    for p in points { if p.x == 1 { continue } else { names[0] + slice(more[2], 0, p.y - 3) } };"#, "\n",
+        r#"    for name in more { if len(name) == n { break } else { name + "!" } };"#, "\n",
+        r#"    if n < len(names) { names[n] } else { more[n] }"#, "\n",
+        r#"}"#, "\n",
+        r#"@export("nested") def nested(): Int = {"#, "\n",
+        r#"    let rows = [[1, 2], [3]];"#, "\n",
+        r#"    let rows = push(rows, push(rows[1], 4));"#, "\n",
+        r#"    let total = 0;"#, "\n",
+        r#"    for row in rows { for x in row { if x == 2 { continue } else if x == 4 { break } else { x } } };"#, "\n",
+        r#"    len(rows[2]) * 10 + rows[2][1]"#, "\n",
+        r#"}"#, "\n",
+    };
+
+    let (mut store, instance) = instantiate(nio_code)?;
+    let memory = instance
+        .get_memory(&mut store, "memory")
+        .ok_or("no memory")?;
+    let read = |store: &Store<()>, (ptr, len): (i32, i32)| {
+        String::from_utf8(memory.data(store)[ptr as usize..(ptr + len) as usize].to_vec())
+    };
+
+    let index = instance.get_typed_func::<i32, i32>(&mut store, "index")?;
+    assert_eq!(index.call(&mut store, 0)?, 10);
+    assert_eq!(index.call(&mut store, 2)?, 30);
+    // Out-of-range indices trap in a function named after the error.
+    for i in [3, -1] {
+        let error = format!("{:?}", index.call(&mut store, i).unwrap_err());
+        assert!(error.contains("index_out_of_bounds"), "{}", error);
+    }
+    let floats = instance.get_typed_func::<(), f64>(&mut store, "floats")?;
+    assert_eq!(floats.call(&mut store, ())?, 6.0);
+    let sum = instance.get_typed_func::<i32, i32>(&mut store, "sum")?;
+    assert_eq!(sum.call(&mut store, 7)?, 2317);
+    let pop_empty = instance.get_typed_func::<(), i32>(&mut store, "pop_empty")?;
+    assert_eq!(pop_empty.call(&mut store, ())?, 0);
+    let pop_twice = instance.get_typed_func::<(), i32>(&mut store, "pop_twice")?;
+    let error = format!("{:?}", pop_twice.call(&mut store, ()).unwrap_err());
+    assert!(error.contains("index_out_of_bounds"), "{}", error);
+    let names = instance.get_typed_func::<i32, (i32, i32)>(&mut store, "names")?;
+    let result = names.call(&mut store, 1)?;
+    assert_eq!(read(&store, result)?, "Alan");
+    let result = names.call(&mut store, 2)?;
+    assert_eq!(read(&store, result)?, "Grace");
+    let nested = instance.get_typed_func::<(), i32>(&mut store, "nested")?;
+    assert_eq!(nested.call(&mut store, ())?, 24);
+
+    // Arrays are freed with their elements.
+    let size = memory.size(&store);
+    for _ in 0..1000 {
+        names.call(&mut store, 2)?;
+        nested.call(&mut store, ())?;
+        sum.call(&mut store, 7)?;
+    }
+    assert_eq!(memory.size(&store), size);
+
+    Ok(())
+}
+
+#[test]
+fn test_push_in_place() -> Result<(), Box<dyn error::Error>> {
+    let nio_code = concat! {
+        r#"let mut log = [0]"#, "\n",
+        r#"@export("fill") def fill(n: Int): Int = {"#, "\n",
+        r#"    let mut a = [0];"#, "\n",
+        r#"    for i in 1..n { a = push(a, i) };"#, "\n",
+        r#"    for i in 0..n / 2 { a = pop(a) };"#, "\n",
+        r#"    len(a) * 10 + a[len(a) - 1] % 10"#, "\n",
+        r#"}"#, "\n",
+        r#"@export("record") def record(x: Int): Int = { log = push(log, x); len(log) }"#, "\n",
+        r#"@export("shared") def shared(): Int = {"#, "\n",
+        r#"    let mut a = [1, 2];"#, "\n",
+        r#"    let b = a;"#, "\n",
+        r#"    a = push(a, 3);"#, "\n",
+        r#"    a = push(a, { a = [4]; 5 });"#, "\n",
+        r#"    len(b) * 100 + len(a) * 10 + a[0]"#, "\n",
+        r#"}"#, "\n",
+    };
+
+    let (mut store, instance) = instantiate(nio_code)?;
+    let memory = instance
+        .get_memory(&mut store, "memory")
+        .ok_or("no memory")?;
+
+    // The array grows in place, doubling its capacity, so 100k elements take about 1 MiB.
+    let fill = instance.get_typed_func::<i32, i32>(&mut store, "fill")?;
+    assert_eq!(fill.call(&mut store, 100_000)?, 500_009);
+    assert!(memory.size(&store) <= 32, "{} pages", memory.size(&store));
+
+    // So does a top-level `let mut`.
+    let start = instance.get_typed_func::<(), ()>(&mut store, "_start")?;
+    start.call(&mut store, ())?;
+    let record = instance.get_typed_func::<i32, i32>(&mut store, "record")?;
+    for i in 1..100_000 {
+        assert_eq!(record.call(&mut store, i)?, i + 1);
+    }
+    assert!(memory.size(&store) <= 32, "{} pages", memory.size(&store));
+
+    // Another reference to the array still sees it as it was, and an array assigned to the
+    // variable while the arguments are computed is replaced like any other value.
+    let shared = instance.get_typed_func::<(), i32>(&mut store, "shared")?;
+    assert_eq!(shared.call(&mut store, ())?, 241);
+
+    Ok(())
+}
+
+#[test]
+fn test_tuples() -> Result<(), Box<dyn error::Error>> {
+    let nio_code = concat! {
+        r#"type Pair = { first: (String, Int), second: Float }"#, "\n",
+        r#"def divmod(a: Int, b: Int): (Int, Int) = (a / b, a % b)"#, "\n",
+        r#"let (q, r) = divmod(17, 5)"#, "\n",
+        r#"@export("divmod") def export_divmod(a: Int, b: Int): (Int, Int) = divmod(a, b)"#, "\n",
+        r#"@export("globals") def globals(): Int = q * 10 + r"#, "\n",
+        r#"@export("order") def order(a: Int64, b: Float, swap: Bool): (Float, Int64) = {"#, "\n",
+        r#"    if swap { (b * 2.0, a) } else { let (x, y) = (float(a), int64(b)); (x, y) }"#, "\n",
+        r#"}"#, "\n",
+        r#"@export("names") def names(n: Int): (String, Int, String) = {"#, "\n",
+        r#"    let (greeting, name) = ("Hello", "Nio");"#, "\n",
+        r#"    let both = (greeting + ", " + name, len(name));"#, "\n",
+        r#"    let show = |p| { let (s, k) = p; s + "!" + slice("0123456789", k, k + 1) };"#, "\n",
+        r#"    let pair = { first: both, second: 1.0 };"#, "\n",
+        r#"    let (s, k) = pair.first;"#, "\n",
+        r#"    // The copy of a shared array refers to the same strings."#, "\n",
+        r#"    let more = { let items = [(1, name + "a"), (2, name + "b")]; push(items, (n, name)) };"#, "\n",
+        r#"    let (last, label) = more[2];"#, "\n",
+        r#"    let (_, first) = more[0];"#, "\n",
+        r#"    (show(both), k + last + len(more), label + s + "/" + first)"#, "\n",
+        r#"}"#, "\n",
+    };
+
+    let (mut store, instance) = instantiate(nio_code)?;
+    let memory = instance
+        .get_memory(&mut store, "memory")
+        .ok_or("no memory")?;
+    let read = |store: &Store<()>, ptr: i32, len: i32| {
+        String::from_utf8(memory.data(store)[ptr as usize..(ptr + len) as usize].to_vec())
+    };
+
+    let divmod = instance.get_typed_func::<(i32, i32), (i32, i32)>(&mut store, "divmod")?;
+    assert_eq!(divmod.call(&mut store, (7, 2))?, (3, 1));
+    let start = instance.get_typed_func::<(), ()>(&mut store, "_start")?;
+    start.call(&mut store, ())?;
+    let globals = instance.get_typed_func::<(), i32>(&mut store, "globals")?;
+    assert_eq!(globals.call(&mut store, ())?, 32);
+    let order = instance.get_typed_func::<(i64, f64, i32), (f64, i64)>(&mut store, "order")?;
+    assert_eq!(order.call(&mut store, (3, 1.5, 1))?, (3.0, 3));
+    assert_eq!(order.call(&mut store, (3, 1.5, 0))?, (3.0, 1));
+    let names = instance.get_typed_func::<i32, (i32, i32, i32, i32, i32)>(&mut store, "names")?;
+    let (ptr, len, k, label_ptr, label_len) = names.call(&mut store, 4)?;
+    assert_eq!(read(&store, ptr, len)?, "Hello, Nio!3");
+    assert_eq!(k, 10);
+    assert_eq!(read(&store, label_ptr, label_len)?, "NioHello, Nio/Nioa");
+
+    let size = memory.size(&store);
+    for _ in 0..1000 {
+        names.call(&mut store, 4)?;
+    }
+    assert_eq!(memory.size(&store), size);
+
+    Ok(())
+}
+
+#[test]
+fn test_assignment() -> Result<(), Box<dyn error::Error>> {
+    let nio_code = concat! {
+        r#"let mut calls = 0"#, "\n",
+        r#"let mut last = ("none", 0)"#, "\n",
+        r#"@export("sum") def sum(n: Int): Int = {"#, "\n",
+        r#"    calls += 1;"#, "\n",
+        r#"    let mut total = 0;"#, "\n",
+        r#"    let mut i = 0;"#, "\n",
+        r#"    while i < n { i += 1; total = total + i };"#, "\n",
+        r#"    total -= n;"#, "\n",
+        r#"    total"#, "\n",
+        r#"}"#, "\n",
+        r#"@export("calls") def get_calls(): Int = calls"#, "\n",
+        r#"@export("shout") def shout(n: Int): (String, Int) = {"#, "\n",
+        r#"    let record = || calls = calls + 1;"#, "\n",
+        r#"    let mut s = "a";"#, "\n",
+        r#"    let mut pair = (s, 0);"#, "\n",
+        r#"    for i in 0..n { s = s + "!"; pair = (s + "?", i); record() };"#, "\n",
+        r#"    last = pair;"#, "\n",
+        r#"    let x = loop { let mut y = 1; y = break y + 1 };"#, "\n",
+        r#"    let (t, k) = last;"#, "\n",
+        r#"    (t, k + x)"#, "\n",
+        r#"}"#, "\n",
+    };
+
+    let (mut store, instance) = instantiate(nio_code)?;
+    let memory = instance
+        .get_memory(&mut store, "memory")
+        .ok_or("no memory")?;
+    let read = |store: &Store<()>, ptr: i32, len: i32| {
+        String::from_utf8(memory.data(store)[ptr as usize..(ptr + len) as usize].to_vec())
+    };
+
+    let start = instance.get_typed_func::<(), ()>(&mut store, "_start")?;
+    start.call(&mut store, ())?;
+    let sum = instance.get_typed_func::<i32, i32>(&mut store, "sum")?;
+    assert_eq!(sum.call(&mut store, 4)?, 6);
+    assert_eq!(sum.call(&mut store, 0)?, 0);
+    let calls = instance.get_typed_func::<(), i32>(&mut store, "calls")?;
+    assert_eq!(calls.call(&mut store, ())?, 2);
+    let shout = instance.get_typed_func::<i32, (i32, i32, i32)>(&mut store, "shout")?;
+    let (ptr, len, k) = shout.call(&mut store, 3)?;
+    assert_eq!(read(&store, ptr, len)?, "a!!!?");
+    assert_eq!(k, 4);
+    assert_eq!(calls.call(&mut store, ())?, 5);
+    let (ptr, len, k) = shout.call(&mut store, 101)?;
+    assert_eq!(
+        read(&store, ptr, len)?,
+        "a".to_string() + &"!".repeat(101) + "?"
+    );
+    assert_eq!(k, 102);
+
+    let size = memory.size(&store);
+    for _ in 0..1000 {
+        shout.call(&mut store, 5)?;
+    }
+    assert_eq!(memory.size(&store), size);
+
+    Ok(())
+}